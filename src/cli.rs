@@ -1,84 +1,267 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
 use std::ffi::OsStr;
-use std::io::Write;
+use std::fmt;
+use std::io::{self, Write};
 use std::process::exit;
+use std::rc::Rc;
 
-use crate::runner::Runner;
-use anyhow::Error;
-use clap::ArgMatches;
-use dialoguer::theme::ColorfulTheme;
-use dialoguer::{Confirm, Input, Select};
+use crate::event::{Event, EventBus, JsonLines, Sink};
+use crate::gitconfig;
+use crate::runner::{Executor, Runner, SpawnCounter, System};
+use anyhow::{bail, Context, Error};
+use clap::{Arg, ArgMatches};
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme};
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+use tempfile::TempPath;
 use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 
+/// Environment used for cargo commands in the rust repo.
+///
+/// `RUSTC_BOOTSTRAP` is a hack because clippy currently requires nightly, and
+/// this will override the nightly feature check.
+pub const BOOTSTRAP_ENV: &[(&str, &str)] = &[("RUSTC_BOOTSTRAP", "1")];
+
+/// Exit code when there is nothing to update, with `--fail-if-no-changes`.
+pub const EXIT_NO_CHANGES: i32 = 2;
+
+/// Exit code when the tests of the update failed.
+pub const EXIT_TESTS_FAILED: i32 = 3;
+
+/// Context added to the error of a failed test run, so that `Cli::doit`
+/// exits with `EXIT_TESTS_FAILED`.
+#[derive(Debug)]
+pub struct TestsFailed;
+
+impl fmt::Display for TestsFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The tests failed.")
+    }
+}
+
 pub struct Cli<'a> {
     pub matches: ArgMatches<'a>,
     out_writer: BufferWriter,
-    theme: ColorfulTheme,
+    theme: Box<dyn Theme>,
     is_interactive: bool,
+    events: EventBus,
+    executor: Rc<dyn Executor>,
+    /// Send the output of commands as events instead of showing it, for
+    /// the `--tui` dashboard and `--json`.
+    stream_output: bool,
+    /// Events are printed as JSON lines instead of text.
+    json: bool,
+    /// The generated global git config used with `--isolate-git`, created
+    /// by `doit` and removed on exit.
+    git_config: RefCell<Option<TempPath>>,
+    /// The processes started by this run.
+    spawns: SpawnCounter,
+    /// `log::git_origin` of each submodule path looked up so far.
+    origins: RefCell<HashMap<String, String>>,
 }
 
 impl<'a> Cli<'a> {
-    pub fn new(matches: ArgMatches<'a>) -> Cli {
+    pub fn new(matches: ArgMatches<'a>) -> Cli<'a> {
         let is_interactive = !matches.is_present("force") && atty::is(atty::Stream::Stdout);
-        Cli {
+        let events = EventBus::default();
+        let json = matches.is_present("json");
+        let color = color_choice(&matches);
+        let mut dashboard_error = None;
+        let stream_output = if json {
+            events.add(JsonLines::stdout());
+            true
+        } else {
+            add_dashboard(&matches, &events).unwrap_or_else(|e| {
+                dashboard_error = Some(e);
+                false
+            })
+        };
+        if !stream_output {
+            events.add(Terminal {
+                out_writer: BufferWriter::stdout(color),
+                verbose: matches.is_present("verbose"),
+                redraw: color != ColorChoice::Never && atty::is(atty::Stream::Stdout),
+                step: None,
+                bar_shown: false,
+            });
+        }
+        let cli = Cli {
             matches,
-            out_writer: BufferWriter::stdout(ColorChoice::Auto),
-            theme: ColorfulTheme::default(),
+            out_writer: BufferWriter::stdout(color),
+            theme: if color == ColorChoice::Never {
+                Box::new(SimpleTheme)
+            } else {
+                Box::new(ColorfulTheme::default())
+            },
             is_interactive,
+            events,
+            executor: Rc::new(System),
+            stream_output,
+            json,
+            git_config: RefCell::new(None),
+            spawns: SpawnCounter::default(),
+            origins: RefCell::new(HashMap::new()),
+        };
+        if let Some(e) = dashboard_error {
+            // Printing only fails if stdout is closed.
+            let _ = cli.warning(&format!("Failed to start the dashboard: {}", e));
         }
+        cli
+    }
+
+    /// Runs commands with `executor` instead of as processes.
+    pub fn with_executor(mut self, executor: Rc<dyn Executor>) -> Cli<'a> {
+        self.executor = executor;
+        self
+    }
+
+    /// The origin cached for `path`, or the result of `lookup`, which is
+    /// cached if it succeeds.
+    pub fn cached_origin(
+        &self,
+        path: &str,
+        lookup: impl FnOnce() -> Result<String, Error>,
+    ) -> Result<String, Error> {
+        if let Some(origin) = self.origins.borrow().get(path) {
+            return Ok(origin.clone());
+        }
+        let origin = lookup()?;
+        self.origins
+            .borrow_mut()
+            .insert(path.to_string(), origin.clone());
+        Ok(origin)
+    }
+
+    /// The remote of the rust repo, `upstream` unless given with
+    /// `--upstream-remote`.
+    pub fn upstream_remote(&self) -> &str {
+        self.matches
+            .value_of("upstream-remote")
+            .unwrap_or("upstream")
+    }
+
+    /// The remote-tracking branch of `branch` of the rust repo, such as
+    /// `upstream/master`.
+    pub fn upstream_branch(&self, branch: &str) -> String {
+        format!("{}/{}", self.upstream_remote(), branch)
+    }
+
+    /// The processes started so far, by category.
+    pub fn spawns(&self) -> &SpawnCounter {
+        &self.spawns
+    }
+
+    /// The sinks that observe what happens while running.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn emit(&self, event: &Event<'_>) -> Result<(), Error> {
+        self.events.emit(event)
     }
 
     pub fn doit(&self, f: impl Fn(&Cli) -> Result<(), Error>) -> ! {
-        if let Err(e) = f(&self) {
-            let _ = self.emit_message("Error: ", Color::Red, &e.to_string(), true);
-            for cause in e.chain().skip(1) {
-                let _ = self.emit_message("Caused by: ", Color::Red, &cause.to_string(), true);
-            }
-            self.exit_err();
+        if let Err(e) = self.isolate_git().and_then(|()| f(self)) {
+            self.fail(e);
         }
-        exit(0)
+        self.exit(0)
     }
 
-    pub fn exit_err(&self) -> ! {
-        exit(1)
+    /// Reports `e` and exits.
+    pub fn fail(&self, e: Error) -> ! {
+        let causes: Vec<String> = e.chain().skip(1).map(|c| c.to_string()).collect();
+        let _ = self.emit(&Event::Failed {
+            message: &e.to_string(),
+            causes: &causes,
+        });
+        // Restore the terminal from the dashboard first.
+        self.events.close();
+        if self.json {
+            self.exit(exit_code(&e));
+        }
+        let _ = emit_message(
+            &self.out_writer,
+            "Error: ",
+            Color::Red,
+            &e.to_string(),
+            true,
+        );
+        for cause in &causes {
+            let _ = emit_message(&self.out_writer, "Caused by: ", Color::Red, cause, true);
+        }
+        self.exit(exit_code(&e));
     }
 
-    fn emit_message(
-        &self,
-        header: &str,
-        color: Color,
-        message: &str,
-        bold: bool,
-    ) -> Result<(), Error> {
-        let mut buffer = self.out_writer.buffer();
-        buffer.set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true))?;
-        buffer.write_all(header.as_bytes())?;
-        buffer.reset()?;
-        if bold {
-            buffer.set_color(ColorSpec::new().set_bold(true))?;
-        }
-        buffer.write_all(message.as_bytes())?;
-        buffer.reset()?;
-        buffer.write_all(b"\n")?;
-        self.out_writer.print(&buffer)?;
+    /// Writes the config for `--isolate-git`, if given.
+    fn isolate_git(&self) -> Result<(), Error> {
+        if self.matches.is_present("isolate-git") {
+            let path = gitconfig::write_isolated().context("Failed to set up --isolate-git.")?;
+            *self.git_config.borrow_mut() = Some(path);
+        }
         Ok(())
     }
 
+    /// Exits the process, after closing the event sinks.
+    pub fn exit(&self, code: i32) -> ! {
+        self.events.close();
+        if let Some(path) = self.git_config.borrow_mut().take() {
+            let _ = path.close();
+        }
+        exit(code)
+    }
+
+    pub fn exit_err(&self) -> ! {
+        self.exit(1)
+    }
+
+    /// Exits because there is nothing to update, which is only an error with
+    /// `--fail-if-no-changes`.
+    pub fn exit_no_changes(&self) -> ! {
+        if self.matches.is_present("fail-if-no-changes") {
+            self.exit(EXIT_NO_CHANGES)
+        }
+        self.exit(0)
+    }
+
     pub fn warning(&self, message: &str) -> Result<(), Error> {
-        self.emit_message("Warning: ", Color::Yellow, message, true)
+        self.emit(&Event::Warning(message))
     }
 
     pub fn status(&self, message: &str) -> Result<(), Error> {
-        self.emit_message("Status: ", Color::Green, message, true)
+        self.emit(&Event::Status(message))
+    }
+
+    /// Reports that `done` of `total` parallel tasks have finished.
+    pub fn progress(&self, done: usize, total: usize, message: &str) -> Result<(), Error> {
+        self.emit(&Event::Progress {
+            done,
+            total,
+            message,
+        })
     }
 
     pub fn info(&self, message: &str) -> Result<(), Error> {
-        self.emit_message("Info: ", Color::Blue, message, false)
+        self.emit(&Event::Info(message))
+    }
+
+    /// Shows the given lines inside a box with a bold title.
+    pub fn boxed(&self, title: &str, lines: &[String]) -> Result<(), Error> {
+        self.emit(&Event::Boxed { title, lines })
     }
 
     pub fn is_interactive(&self) -> bool {
         self.is_interactive
     }
 
+    /// Runs a prompt, letting the sinks know that the terminal is in use.
+    fn prompt<T>(&self, f: impl FnOnce() -> io::Result<T>) -> Result<T, Error> {
+        self.emit(&Event::PromptStarted)?;
+        let result = f();
+        self.emit(&Event::PromptFinished)?;
+        Ok(result?)
+    }
+
     pub fn confirm(&self, message: &str, default: bool) -> Result<bool, Error> {
         if self.matches.is_present("force") {
             return Ok(default);
@@ -86,10 +269,12 @@ impl<'a> Cli<'a> {
         if !self.is_interactive() {
             return Ok(false);
         }
-        Ok(Confirm::with_theme(&self.theme)
-            .with_prompt(message)
-            .default(default)
-            .interact()?)
+        self.prompt(|| {
+            Confirm::with_theme(&*self.theme)
+                .with_prompt(message)
+                .default(default)
+                .interact()
+        })
     }
 
     pub fn input(&self, message: &str, default: Option<&str>) -> Result<Option<String>, Error> {
@@ -99,12 +284,12 @@ impl<'a> Cli<'a> {
         if !self.is_interactive() {
             return Ok(None);
         }
-        let mut input: Input<'_, String> = Input::with_theme(&self.theme);
+        let mut input: Input<'_, String> = Input::with_theme(&*self.theme);
         input.with_prompt(message);
         if let Some(d) = default {
             input.default(d.to_string());
         }
-        Ok(Some(input.interact()?))
+        Ok(Some(self.prompt(|| input.interact())?))
     }
 
     pub fn select(
@@ -119,35 +304,367 @@ impl<'a> Cli<'a> {
         if !self.is_interactive() {
             return Ok(None);
         }
-        let mut select = Select::with_theme(&self.theme);
+        let mut select = Select::with_theme(&*self.theme);
         select.with_prompt(prompt).items(items);
         if let Some(default) = default {
             select.default(default);
         }
-        Ok(select.interact_opt()?)
+        self.prompt(|| select.interact_opt())
+    }
+
+    /// Asks the user to choose any number of items, returns their indexes.
+    ///
+    /// Returns `None` if not interactive.
+    pub fn multi_select(
+        &self,
+        prompt: &str,
+        items: &[String],
+    ) -> Result<Option<Vec<usize>>, Error> {
+        if !self.is_interactive() {
+            return Ok(None);
+        }
+        Ok(Some(self.prompt(|| {
+            MultiSelect::with_theme(&*self.theme)
+                .with_prompt(prompt)
+                .items(items)
+                .interact()
+        })?))
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.matches.is_present("verbose")
+    }
+
+    /// Whether commands that change anything should only be printed.
+    pub fn is_dry_run(&self) -> bool {
+        self.matches.is_present("dry-run")
+    }
+
+    /// Whether nothing may be changed on a remote.
+    pub fn is_local_only(&self) -> bool {
+        self.matches.is_present("local-only")
+    }
+
+    /// Checks before changing something on a remote, such as pushing or
+    /// creating a PR. `what` completes "Going to ...".
+    ///
+    /// Returns `false` if it should be skipped, with `--local-only` or if
+    /// the user says no. Without a terminal, `--confirm-remote` is required.
+    /// In dry-run mode, the caller only reports what it would do.
+    pub fn confirm_remote(&self, what: &str) -> Result<bool, Error> {
+        if self.is_dry_run() || self.matches.is_present("confirm-remote") {
+            return Ok(true);
+        }
+        if self.is_local_only() {
+            self.info(&format!("Not going to {} with --local-only.", what))?;
+            return Ok(false);
+        }
+        if !self.is_interactive() {
+            bail!(
+                "Going to {}, which changes a remote, pass --confirm-remote to allow it.",
+                what
+            );
+        }
+        self.confirm(&format!("Going to {}, continue?", what), true)
     }
 
     /// Create a `Runner` (a wrapper around `Command`).
     pub fn runner(&self, program: impl AsRef<OsStr>, args: &[impl AsRef<OsStr>]) -> Runner {
-        let r = Runner::new(program, args);
-        if self.matches.is_present("verbose") {
-            let _ = self.info(&format!("Running: {}", r.cmd_str()));
-        }
-        r
+        Runner::new(program, args)
+            .executor(self.executor.clone())
+            .counter(self.spawns.clone())
+            .events(self.events.clone())
+            .stream_output(self.stream_output)
+            .local_only(self.is_local_only())
+            .dry_run(self.is_dry_run())
     }
 
+    /// Runs `git` with the arguments split on whitespace. This is only for
+    /// fixed arguments, use `git_cmd` for anything with a value.
     pub fn git(&self, args: &str) -> Runner {
-        let args: Vec<_> = args.split_whitespace().collect();
-        self.runner("git", &args)
+        self.git_cmd(args.split_whitespace())
     }
 
+    /// Runs `git` with the given arguments, which are passed as is.
+    pub fn git_cmd<S: AsRef<str>>(&self, args: impl IntoIterator<Item = S>) -> Runner {
+        let mut args: Vec<String> = args.into_iter().map(|a| a.as_ref().to_string()).collect();
+        if self.is_verbose() {
+            // Slow commands get progress output so it is clear what they
+            // are doing.
+            let words: Vec<&str> = args.iter().take(2).map(String::as_str).collect();
+            let (at, flags): (usize, &[&str]) = match words.as_slice() {
+                ["fetch", ..] => (1, &["--verbose", "--progress"]),
+                ["checkout", ..] => (1, &["--progress"]),
+                ["submodule", "update"] => (2, &["--progress"]),
+                _ => (0, &[]),
+            };
+            for (i, flag) in flags.iter().enumerate() {
+                args.insert(at + i, flag.to_string());
+            }
+        }
+        let runner = self.runner("git", &args);
+        match &*self.git_config.borrow() {
+            Some(path) => runner
+                .env("GIT_CONFIG_GLOBAL", path)
+                .env("GIT_CONFIG_NOSYSTEM", "1"),
+            None => runner,
+        }
+    }
+
+    /// Runs `cargo` with the arguments split on whitespace. This is only
+    /// for fixed arguments, use `cargo_cmd` for anything with a value.
     pub fn cargo(&self, args: &str) -> Runner {
-        let args: Vec<_> = args.split_whitespace().collect();
+        self.cargo_cmd(args.split_whitespace())
+    }
+
+    /// Runs `cargo` with the given arguments, which are passed as is.
+    pub fn cargo_cmd<S: AsRef<str>>(&self, args: impl IntoIterator<Item = S>) -> Runner {
+        let args: Vec<String> = args.into_iter().map(|a| a.as_ref().to_string()).collect();
         // TODO: This should use the version of cargo from stage0,
         // but I'm uncertain how to get the path.
-        let runner = self.runner("cargo", &args);
-        // Hack because clippy currently requires nightly, and this will
-        // override the nightly feature check.
-        runner.env("RUSTC_BOOTSTRAP", "1")
+        self.runner("cargo", &args)
+            .envs(BOOTSTRAP_ENV.iter().copied())
+    }
+}
+
+/// Adds the dashboard for `--tui`, returns whether it was added.
+#[cfg(feature = "tui")]
+fn add_dashboard(matches: &ArgMatches<'_>, events: &EventBus) -> Result<bool, Error> {
+    if !matches.is_present("tui") || !atty::is(atty::Stream::Stdout) {
+        return Ok(false);
+    }
+    events.add(crate::tui::Dashboard::new()?);
+    Ok(true)
+}
+
+#[cfg(not(feature = "tui"))]
+fn add_dashboard(_matches: &ArgMatches<'_>, _events: &EventBus) -> Result<bool, Error> {
+    Ok(false)
+}
+
+/// Prints the given lines inside a box with a bold title.
+fn print_boxed(out_writer: &BufferWriter, title: &str, lines: &[String]) -> Result<(), Error> {
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .chain(Some(title.chars().count()))
+        .max()
+        .unwrap_or(0);
+    let border = format!("+{}+\n", "-".repeat(width + 2));
+    let mut buffer = out_writer.buffer();
+    buffer.write_all(border.as_bytes())?;
+    buffer.write_all(b"| ")?;
+    buffer.set_color(ColorSpec::new().set_bold(true))?;
+    write!(buffer, "{:width$}", title, width = width)?;
+    buffer.reset()?;
+    buffer.write_all(b" |\n")?;
+    buffer.write_all(border.as_bytes())?;
+    for line in lines {
+        writeln!(buffer, "| {:width$} |", line, width = width)?;
+    }
+    buffer.write_all(border.as_bytes())?;
+    out_writer.print(&buffer)?;
+    Ok(())
+}
+
+/// The exit code for a run that failed with `e`.
+fn exit_code(e: &Error) -> i32 {
+    if e.downcast_ref::<TestsFailed>().is_some() {
+        EXIT_TESTS_FAILED
+    } else {
+        1
+    }
+}
+
+/// The `--color` argument, global so it can be given before or after a
+/// subcommand.
+pub fn color_arg() -> Arg<'static, 'static> {
+    Arg::with_name("color")
+        .long("color")
+        .takes_value(true)
+        .value_name("WHEN")
+        .possible_values(&["auto", "always", "never"])
+        .default_value("auto")
+        .global(true)
+        .help("Whether to use colors, `auto` turns them off if NO_COLOR is set or not on a tty")
+}
+
+/// The `--isolate-git` argument, global like `--color`.
+pub fn isolate_git_arg() -> Arg<'static, 'static> {
+    Arg::with_name("isolate-git")
+        .long("isolate-git")
+        .global(true)
+        .help(
+            "Run git with a minimal generated global config (keeping only the \
+             user, credential, and url settings) and without the system config",
+        )
+}
+
+/// The `--upstream-remote` argument, global like `--color`.
+pub fn upstream_remote_arg() -> Arg<'static, 'static> {
+    Arg::with_name("upstream-remote")
+        .long("upstream-remote")
+        .takes_value(true)
+        .value_name("REMOTE")
+        .global(true)
+        .help("The remote of the rust repo (defaults to upstream)")
+}
+
+/// The `--push-remote` argument, global so that `status` and `abort` find
+/// the pushed branch too.
+pub fn push_remote_arg() -> Arg<'static, 'static> {
+    Arg::with_name("push-remote")
+        .long("push-remote")
+        .takes_value(true)
+        .value_name("REMOTE")
+        .global(true)
+        .help("The remote of your fork to push to (defaults to origin)")
+}
+
+/// Whether to color output, from `--color` and the environment.
+fn color_choice(matches: &ArgMatches<'_>) -> ColorChoice {
+    match matches.value_of("color") {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        // See https://no-color.org/, an empty value does not count.
+        _ if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => ColorChoice::Never,
+        _ if !atty::is(atty::Stream::Stdout) => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    }
+}
+
+fn emit_message(
+    out_writer: &BufferWriter,
+    header: &str,
+    color: Color,
+    message: &str,
+    bold: bool,
+) -> Result<(), Error> {
+    let mut buffer = out_writer.buffer();
+    buffer.set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true))?;
+    buffer.write_all(header.as_bytes())?;
+    buffer.reset()?;
+    if bold {
+        buffer.set_color(ColorSpec::new().set_bold(true))?;
+    }
+    buffer.write_all(message.as_bytes())?;
+    buffer.reset()?;
+    buffer.write_all(b"\n")?;
+    out_writer.print(&buffer)?;
+    Ok(())
+}
+
+/// The width of the bar drawn by `progress_bar`.
+const BAR_WIDTH: usize = 30;
+
+/// Draws `[=====>    ] done/total`.
+fn progress_bar(done: usize, total: usize) -> String {
+    let filled = (BAR_WIDTH * done)
+        .checked_div(total)
+        .unwrap_or(BAR_WIDTH)
+        .min(BAR_WIDTH);
+    let head = if filled < BAR_WIDTH { ">" } else { "" };
+    format!(
+        "[{}{}{}] {}/{}",
+        "=".repeat(filled),
+        head,
+        " ".repeat(BAR_WIDTH - filled - head.len()),
+        done,
+        total
+    )
+}
+
+/// Prints events to the terminal.
+struct Terminal {
+    out_writer: BufferWriter,
+    /// Print every command as it runs.
+    verbose: bool,
+    /// Progress is drawn as a bar that is updated in place, which needs
+    /// escape codes, so only on a terminal with color enabled. Otherwise it
+    /// is printed as plain lines.
+    redraw: bool,
+    /// The number of the running step, and the number of steps.
+    step: Option<(usize, usize)>,
+    /// A progress bar is on the current line.
+    bar_shown: bool,
+}
+
+impl Sink for Terminal {
+    fn event(&mut self, event: &Event<'_>) -> Result<(), Error> {
+        if self.bar_shown && !matches!(event, Event::Progress { .. }) {
+            // Leave the bar where it is.
+            println!();
+            self.bar_shown = false;
+        }
+        let w = &self.out_writer;
+        match *event {
+            Event::Status(message) => match self.step {
+                Some((step, total)) => {
+                    let header = format!("Step {}/{}: ", step, total);
+                    emit_message(w, &header, Color::Green, message, true)
+                }
+                None => emit_message(w, "Status: ", Color::Green, message, true),
+            },
+            Event::PhaseStarted { step, total, .. } => {
+                self.step = Some((step, total));
+                Ok(())
+            }
+            Event::PhaseFinished { .. } => {
+                self.step = None;
+                Ok(())
+            }
+            Event::Progress {
+                done,
+                total,
+                message,
+            } if self.redraw => {
+                // `\x1b[K` clears the rest of the line.
+                print!("\r{} {}\x1b[K", progress_bar(done, total), message);
+                self.bar_shown = done < total;
+                if !self.bar_shown {
+                    println!();
+                }
+                io::stdout().flush()?;
+                Ok(())
+            }
+            Event::Progress {
+                done,
+                total,
+                message,
+            } => {
+                let message = format!("[{}/{}] {}", done, total, message);
+                emit_message(w, "Info: ", Color::Blue, &message, false)
+            }
+            Event::Warning(message) => emit_message(w, "Warning: ", Color::Yellow, message, true),
+            Event::Info(message) => emit_message(w, "Info: ", Color::Blue, message, false),
+            Event::CommandRun {
+                command,
+                dir,
+                dry_run,
+                quiet,
+            } => {
+                let verb = if dry_run {
+                    "Would run"
+                } else if self.verbose && !quiet {
+                    "Running"
+                } else {
+                    return Ok(());
+                };
+                let message = format!("{}: {} (in {})", verb, command, dir.display());
+                emit_message(w, "Info: ", Color::Blue, &message, false)
+            }
+            Event::Boxed { title, lines } => print_boxed(w, title, lines),
+            Event::PrCreated { url } => {
+                println!("Created PR at {}", url);
+                Ok(())
+            }
+            // The error is printed by `doit` after the sinks are closed.
+            Event::CommandOutput(_)
+            | Event::PromptStarted
+            | Event::PromptFinished
+            | Event::SubmoduleUpdated { .. }
+            | Event::LockChanged { .. }
+            | Event::Failed { .. } => Ok(()),
+        }
     }
 }
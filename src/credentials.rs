@@ -0,0 +1,60 @@
+//! Lookup of API tokens.
+//!
+//! Tokens are never read from subup's own configuration. They come from the
+//! environment, the `gh` CLI, or the git credential helper.
+
+use crate::cli::Cli;
+use anyhow::{bail, Error};
+use std::env;
+
+/// Returns a token for the GitHub API on `host` (usually `github.com`).
+///
+/// The sources are tried in order:
+///
+/// 1. The `GITHUB_TOKEN` environment variable.
+/// 2. `gh auth token`.
+/// 3. The password from the git credential helper for `https://{host}`.
+pub fn github_token(cli: &Cli<'_>, host: &str) -> Result<String, Error> {
+    if let Ok(token) = env::var("GITHUB_TOKEN") {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    if let Some(token) = gh_token(cli, host) {
+        return Ok(token);
+    }
+    if let Some(token) = credential_helper_token(cli, host) {
+        return Ok(token);
+    }
+    bail!(
+        "Could not find a GitHub token for `{}`. Set `GITHUB_TOKEN`, log in \
+         with `gh auth login`, or configure a git credential helper for \
+         https://{}.",
+        host,
+        host
+    );
+}
+
+fn gh_token(cli: &Cli<'_>, host: &str) -> Option<String> {
+    let token = cli
+        .runner("gh", &["auth", "token", "--hostname", host])
+        .quiet()
+        .capture_stdout("Failed to get token from gh.")
+        .ok()?;
+    Some(token).filter(|token| !token.is_empty())
+}
+
+fn credential_helper_token(cli: &Cli<'_>, host: &str) -> Option<String> {
+    let output = cli
+        .git("credential fill")
+        .quiet()
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(format!("protocol=https\nhost={}\n\n", host))
+        .capture_stdout("Failed to get token from git credential helper.")
+        .ok()?;
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_string())
+}
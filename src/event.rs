@@ -0,0 +1,206 @@
+//! Events emitted while running, observed by pluggable sinks.
+//!
+//! The terminal output is one sink, others (such as `JsonLines`) can be
+//! added to record the same events in other forms.
+
+use anyhow::{Context, Error};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Something that happened while running.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A step of the pipeline is starting, the `step`th (from 1) of `total`.
+    PhaseStarted {
+        phase: &'a str,
+        step: usize,
+        total: usize,
+    },
+    /// A step of the pipeline finished successfully.
+    PhaseFinished {
+        phase: &'a str,
+        duration: Duration,
+    },
+    Status(&'a str),
+    /// `done` of `total` parallel tasks (such as fetches) have finished.
+    Progress {
+        done: usize,
+        total: usize,
+        message: &'a str,
+    },
+    Warning(&'a str),
+    Info(&'a str),
+    /// A command is about to run (or would run, in a dry run).
+    CommandRun {
+        command: &'a str,
+        dir: &'a Path,
+        /// The command is only printed, not run.
+        dry_run: bool,
+        /// The command asked not to be echoed.
+        quiet: bool,
+    },
+    /// A line of output from the running command, only sent for commands
+    /// run with `Runner::stream_output`.
+    CommandOutput(&'a str),
+    /// Lines shown together in a box with a title.
+    Boxed {
+        title: &'a str,
+        lines: &'a [String],
+    },
+    /// The user is about to be asked something on the terminal.
+    PromptStarted,
+    /// The user answered the prompt.
+    PromptFinished,
+    /// A submodule was updated between the given hashes.
+    SubmoduleUpdated {
+        path: &'a str,
+        from: &'a str,
+        to: &'a str,
+    },
+    /// A lock file changed, `packages` are the names of the packages that
+    /// were added, removed, or changed version.
+    LockChanged {
+        path: &'a str,
+        packages: &'a [String],
+    },
+    PrCreated {
+        url: &'a str,
+    },
+    /// The run failed with the given error, and the errors that caused it.
+    Failed {
+        message: &'a str,
+        causes: &'a [String],
+    },
+}
+
+impl Event<'_> {
+    /// The event as a JSON object with a `kind` field.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Event::PhaseStarted { phase, step, total } => json!({
+                "kind": "phase-started",
+                "phase": phase,
+                "step": step,
+                "total": total,
+            }),
+            Event::PhaseFinished { phase, duration } => json!({
+                "kind": "phase-finished",
+                "phase": phase,
+                "seconds": duration.as_secs_f64(),
+            }),
+            Event::Status(message) => json!({"kind": "status", "message": message}),
+            Event::Progress {
+                done,
+                total,
+                message,
+            } => json!({
+                "kind": "progress",
+                "done": done,
+                "total": total,
+                "message": message,
+            }),
+            Event::Warning(message) => json!({"kind": "warning", "message": message}),
+            Event::Info(message) => json!({"kind": "info", "message": message}),
+            Event::CommandRun {
+                command,
+                dir,
+                dry_run,
+                quiet: _,
+            } => json!({
+                "kind": "command-run",
+                "command": command,
+                "dir": dir,
+                "dry_run": dry_run,
+            }),
+            Event::CommandOutput(line) => json!({"kind": "command-output", "line": line}),
+            Event::Boxed { title, lines } => json!({
+                "kind": "boxed",
+                "title": title,
+                "lines": lines,
+            }),
+            Event::PromptStarted => json!({"kind": "prompt-started"}),
+            Event::PromptFinished => json!({"kind": "prompt-finished"}),
+            Event::SubmoduleUpdated { path, from, to } => json!({
+                "kind": "submodule-updated",
+                "path": path,
+                "from": from,
+                "to": to,
+            }),
+            Event::LockChanged { path, packages } => json!({
+                "kind": "lock-changed",
+                "path": path,
+                "packages": packages,
+            }),
+            Event::PrCreated { url } => json!({"kind": "pr-created", "url": url}),
+            Event::Failed { message, causes } => json!({
+                "kind": "failed",
+                "message": message,
+                "causes": causes,
+            }),
+        }
+    }
+}
+
+/// Receives events.
+pub trait Sink {
+    fn event(&mut self, event: &Event<'_>) -> Result<(), Error>;
+}
+
+/// The sinks that events are sent to, shared by `Cli` and every `Runner` it
+/// creates.
+#[derive(Clone, Default)]
+pub struct EventBus(Rc<RefCell<Vec<Box<dyn Sink>>>>);
+
+impl EventBus {
+    pub fn add(&self, sink: impl Sink + 'static) {
+        self.0.borrow_mut().push(Box::new(sink));
+    }
+
+    /// Removes every sink, so that they are dropped before exiting.
+    pub fn close(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    /// Sends the event to every sink.
+    pub fn emit(&self, event: &Event<'_>) -> Result<(), Error> {
+        for sink in self.0.borrow_mut().iter_mut() {
+            sink.event(event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes each event as a line of JSON.
+pub struct JsonLines {
+    out: Box<dyn Write>,
+}
+
+impl JsonLines {
+    pub fn create(path: &Path) -> Result<JsonLines, Error> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create event log `{}`.", path.display()))?;
+        Ok(JsonLines {
+            out: Box::new(file),
+        })
+    }
+
+    /// Writes to stdout, for `--json`.
+    pub fn stdout() -> JsonLines {
+        JsonLines {
+            out: Box::new(io::stdout()),
+        }
+    }
+}
+
+impl Sink for JsonLines {
+    fn event(&mut self, event: &Event<'_>) -> Result<(), Error> {
+        writeln!(self.out, "{}", event.to_json())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
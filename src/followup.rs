@@ -0,0 +1,72 @@
+//! Heuristics for superproject files that may need manual follow-up after a
+//! submodule update.
+
+/// If a file starting with `changed` is modified in a submodule matching
+/// `submodule`, then `check` in the superproject may need to be updated.
+struct Rule {
+    /// Submodule path, or `*` for any submodule.
+    submodule: &'static str,
+    /// Path prefix of the changed file within the submodule.
+    changed: &'static str,
+    /// Superproject file or directory to check.
+    check: &'static str,
+    /// Why `check` may need to change.
+    reason: &'static str,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        submodule: "*",
+        changed: ".github/workflows/",
+        check: "src/ci/docker",
+        reason: "CI configuration changed, there may be new system dependencies",
+    },
+    Rule {
+        submodule: "*",
+        changed: "rust-toolchain",
+        check: "src/stage0",
+        reason: "the required toolchain changed",
+    },
+    Rule {
+        submodule: "*",
+        changed: "build.rs",
+        check: "src/bootstrap",
+        reason: "the build script changed, there may be new environment variables",
+    },
+    Rule {
+        submodule: "*",
+        changed: ".cargo/config",
+        check: "config.example.toml",
+        reason: "the cargo configuration changed",
+    },
+    Rule {
+        submodule: "src/tools/cargo",
+        changed: "crates/",
+        check: "src/bootstrap/src/core/build_steps/tool.rs",
+        reason: "cargo's workspace crates changed",
+    },
+    Rule {
+        submodule: "src/llvm-project",
+        changed: "llvm/CMakeLists.txt",
+        check: "src/bootstrap/src/core/build_steps/llvm.rs",
+        reason: "LLVM's build configuration changed",
+    },
+];
+
+/// Returns checklist entries for superproject files that may need follow-up
+/// given the files changed in the submodule at `path`.
+pub fn checklist(path: &str, changed_files: &[&str]) -> Vec<String> {
+    RULES
+        .iter()
+        .filter(|rule| rule.submodule == "*" || rule.submodule == path)
+        .filter_map(|rule| {
+            let file = changed_files
+                .iter()
+                .find(|file| file.starts_with(rule.changed))?;
+            Some(format!(
+                "- [ ] `{}`: {} (`{}` in `{}`)",
+                rule.check, rule.reason, file, path
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,333 @@
+//! Support for the code hosting services that PRs are created on.
+
+use crate::cli::Cli;
+use crate::github::GitHubApi;
+use crate::log;
+use crate::remote::RemoteUrl;
+use anyhow::{bail, format_err, Context, Error};
+use serde_json::json;
+use std::fs;
+
+/// A pull request (or merge request) to create.
+pub struct PullRequest<'a> {
+    pub title: &'a str,
+    /// Path to a file with the body of the PR.
+    pub body_file: &'a str,
+    /// The branch to merge into.
+    pub base: &'a str,
+    /// The branch with the changes.
+    pub head: &'a str,
+    /// The git remote that `head` was pushed to.
+    pub head_remote: &'a str,
+    /// Assign the PR to the current user.
+    pub self_assign: bool,
+    pub labels: Vec<&'a str>,
+}
+
+/// The most characters GitHub accepts in a PR body.
+pub const MAX_BODY_CHARS: usize = 65536;
+
+/// Makes `body` acceptable as a PR body. Control characters (other than
+/// newlines and tabs) are removed, and a body that is too long is cut at a
+/// line boundary with a note that `rest` has the full text.
+pub fn fit_body(body: &str, rest: &str) -> String {
+    let body: String = body
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect();
+    if body.chars().count() <= MAX_BODY_CHARS {
+        return body;
+    }
+    let note = format!("\n\n(Truncated, see {} for the full text.)\n", rest);
+    let budget = MAX_BODY_CHARS - note.chars().count();
+    let mut result = String::new();
+    let mut count = 0;
+    for line in body.split_inclusive('\n') {
+        let len = line.chars().count();
+        if count + len > budget {
+            break;
+        }
+        result.push_str(line);
+        count += len;
+    }
+    if result.is_empty() {
+        // A single enormous line.
+        result = body.chars().take(budget).collect();
+    }
+    result.truncate(result.trim_end().len());
+    result + &note
+}
+
+/// A code hosting service.
+pub trait Forge {
+    /// Name of the service, for messages.
+    fn name(&self) -> &'static str;
+
+    /// Creates the PR, returning its URL.
+    fn create_pr(&self, cli: &Cli<'_>, pr: &PullRequest<'_>) -> Result<String, Error>;
+
+    /// Posts a comment to the PR at the given URL.
+    fn comment(&self, cli: &Cli<'_>, pr_url: &str, body: &str) -> Result<(), Error>;
+
+    /// URL of the page to manually create a PR for `branch` in the fork at
+    /// `repo_url`.
+    fn manual_pr_url(&self, repo_url: &str, branch: &str) -> String;
+}
+
+/// GitHub, using the `gh` CLI.
+pub struct GitHub;
+
+impl Forge for GitHub {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn create_pr(&self, cli: &Cli<'_>, pr: &PullRequest<'_>) -> Result<String, Error> {
+        let mut args = vec![
+            "pr",
+            "create",
+            "--title",
+            pr.title,
+            "--body-file",
+            pr.body_file,
+        ];
+        if pr.self_assign {
+            args.push("--assignee=@me");
+        }
+        // gh guesses the fork from the remotes, which may not be the one
+        // the branch was pushed to.
+        let owner = remote_repo(cli, pr.head_remote)?;
+        let owner = owner.split('/').next().unwrap();
+        let head = format!("{}:{}", owner, pr.head);
+        args.push("--head");
+        args.push(&head);
+        if pr.base != "master" {
+            args.push("--base");
+            args.push(pr.base);
+        }
+        for label in &pr.labels {
+            args.push("--label");
+            args.push(label);
+        }
+        let output = cli
+            .runner("gh", &args)
+            .remote()
+            .capture_stdout("Failed to execute gh to create the PR.")?;
+        if !output.starts_with("https://") || !output.contains("/pull/") {
+            bail!("Expected gh to return the PR URL, got:\n{output}");
+        }
+        Ok(output)
+    }
+
+    fn comment(&self, cli: &Cli<'_>, pr_url: &str, body: &str) -> Result<(), Error> {
+        cli.runner("gh", &["pr", "comment", pr_url, "--body", body])
+            .remote()
+            .run("Failed to execute gh to post a comment.")?;
+        Ok(())
+    }
+
+    fn manual_pr_url(&self, repo_url: &str, branch: &str) -> String {
+        format!("{}/pull/new/{}", repo_url, branch)
+    }
+}
+
+/// GitHub, using the REST API directly, for when `gh` is not installed.
+pub struct GitHubRest {
+    /// The `owner/name` of the upstream repository.
+    repo: String,
+}
+
+impl GitHubRest {
+    /// The API path of the issue (or PR) at the given URL.
+    fn issue_path(&self, pr_url: &str) -> String {
+        let number = pr_url.rsplit('/').next().unwrap();
+        format!("/repos/{}/issues/{}", self.repo, number)
+    }
+}
+
+impl Forge for GitHubRest {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn create_pr(&self, cli: &Cli<'_>, pr: &PullRequest<'_>) -> Result<String, Error> {
+        let api = GitHubApi::new(cli)?;
+        let body = fs::read_to_string(pr.body_file)
+            .with_context(|| format!("Failed to read {}", pr.body_file))?;
+        // The API needs the owner of the fork the branch was pushed to.
+        let fork = remote_repo(cli, pr.head_remote)?;
+        let owner = fork.split('/').next().unwrap();
+        let response = api.request(
+            "POST",
+            &format!("/repos/{}/pulls", self.repo),
+            Some(&json!({
+                "title": pr.title,
+                "body": body,
+                "base": pr.base,
+                "head": format!("{}:{}", owner, pr.head),
+            })),
+        )?;
+        let url = response["html_url"]
+            .as_str()
+            .ok_or_else(|| format_err!("Expected the PR URL in the response, got:\n{}", response))?
+            .to_string();
+        let issue = self.issue_path(&url);
+        if pr.self_assign {
+            let user = api.get("/user")?;
+            api.request(
+                "POST",
+                &format!("{}/assignees", issue),
+                Some(&json!({ "assignees": [user["login"]] })),
+            )
+            .context("Failed to assign the PR.")?;
+        }
+        if !pr.labels.is_empty() {
+            api.request(
+                "POST",
+                &format!("{}/labels", issue),
+                Some(&json!({ "labels": pr.labels })),
+            )
+            .context("Failed to label the PR.")?;
+        }
+        Ok(url)
+    }
+
+    fn comment(&self, cli: &Cli<'_>, pr_url: &str, body: &str) -> Result<(), Error> {
+        GitHubApi::new(cli)?.request(
+            "POST",
+            &format!("{}/comments", self.issue_path(pr_url)),
+            Some(&json!({ "body": body })),
+        )?;
+        Ok(())
+    }
+
+    fn manual_pr_url(&self, repo_url: &str, branch: &str) -> String {
+        GitHub.manual_pr_url(repo_url, branch)
+    }
+}
+
+/// GitLab, using the `glab` CLI.
+pub struct GitLab;
+
+impl Forge for GitLab {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn create_pr(&self, cli: &Cli<'_>, pr: &PullRequest<'_>) -> Result<String, Error> {
+        let body = fs::read_to_string(pr.body_file)
+            .with_context(|| format!("Failed to read {}", pr.body_file))?;
+        let mut args = vec![
+            "mr",
+            "create",
+            "--yes",
+            "--title",
+            pr.title,
+            "--description",
+            &body,
+            "--source-branch",
+            pr.head,
+            "--target-branch",
+            pr.base,
+        ];
+        if pr.self_assign {
+            args.push("--assignee=@me");
+        }
+        let url = cli
+            .git_cmd(["remote", "get-url", pr.head_remote])
+            .capture_stdout(format!("Failed to get {} url.", pr.head_remote))?;
+        let head = RemoteUrl::parse(&url)?.slug();
+        args.push("--head");
+        args.push(&head);
+        let labels = pr.labels.join(",");
+        if !labels.is_empty() {
+            args.push("--label");
+            args.push(&labels);
+        }
+        let output = cli
+            .runner("glab", &args)
+            .remote()
+            .capture_stdout("Failed to execute glab to create the MR.")?;
+        // glab prints some progress before the URL.
+        match output.lines().last() {
+            Some(url) if url.starts_with("https://") && url.contains("/merge_requests/") => {
+                Ok(url.to_string())
+            }
+            _ => bail!("Expected glab to return the MR URL, got:\n{output}"),
+        }
+    }
+
+    fn comment(&self, cli: &Cli<'_>, pr_url: &str, body: &str) -> Result<(), Error> {
+        // glab identifies the MR by its number.
+        let id = pr_url.rsplit('/').next().unwrap();
+        cli.runner("glab", &["mr", "note", id, "--message", body])
+            .remote()
+            .run("Failed to execute glab to post a comment.")?;
+        Ok(())
+    }
+
+    fn manual_pr_url(&self, repo_url: &str, branch: &str) -> String {
+        format!(
+            "{}/-/merge_requests/new?merge_request[source_branch]={}",
+            repo_url, branch
+        )
+    }
+}
+
+/// The GitHub `owner/name` of the given git remote.
+pub fn remote_repo(cli: &Cli<'_>, remote: &str) -> Result<String, Error> {
+    let url = cli
+        .git_cmd(["remote", "get-url", remote])
+        .capture_stdout(format!("Failed to get {} url.", remote))?;
+    log::github_repo(&url)
+}
+
+/// Determines the forge to use, either from the `forge` option or from the
+/// given remote URL. The `pr-backend` option chooses between the CLI and
+/// the REST API.
+pub fn detect(cli: &Cli<'_>, remote_url: &str) -> Result<Box<dyn Forge>, Error> {
+    let name = match cli.matches.value_of("forge") {
+        Some(name) => name,
+        None if remote_url.contains("gitlab") => "gitlab",
+        None => "github",
+    };
+    let api = cli.matches.value_of("pr-backend") == Some("api");
+    match (name, api) {
+        ("github", false) => Ok(Box::new(GitHub)),
+        ("github", true) => Ok(Box::new(GitHubRest {
+            repo: log::github_repo(remote_url)?,
+        })),
+        ("gitlab", false) => Ok(Box::new(GitLab)),
+        ("gitlab", true) => bail!("--pr-backend=api is only supported for GitHub"),
+        _ => bail!("Unknown forge `{}`, expected `github` or `gitlab`", name),
+    }
+}
+
+/// Converts a git remote URL (ssh or https) to the https URL of the
+/// repository, without the `.git` suffix.
+pub fn repo_web_url(remote_url: &str) -> Result<String, Error> {
+    Ok(RemoteUrl::parse(remote_url)?.web_url())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_control_chars() {
+        assert_eq!(fit_body("a\x1b[0m\r\n\tb\x00\n", "x"), "a[0m\n\tb\n");
+    }
+
+    #[test]
+    fn body_truncated() {
+        let line = format!("{}\n", "x".repeat(99));
+        let body = line.repeat(1000);
+        let fitted = fit_body(&body, "the commit message");
+        assert!(fitted.chars().count() <= MAX_BODY_CHARS);
+        assert!(fitted.starts_with(&line.repeat(600)));
+        assert!(fitted.ends_with("x\n\n(Truncated, see the commit message for the full text.)\n"));
+
+        let long = "y".repeat(MAX_BODY_CHARS * 2);
+        assert!(fit_body(&long, "z").chars().count() <= MAX_BODY_CHARS);
+    }
+}
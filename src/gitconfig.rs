@@ -0,0 +1,142 @@
+//! A minimal global git config for `--isolate-git`.
+//!
+//! Settings in the user's git config (such as `fetch.prune` or
+//! `rebase.autostash`) can change how the git commands of an update behave.
+//! With `--isolate-git`, git runs with `GIT_CONFIG_GLOBAL` pointing to a
+//! generated file that only keeps the settings needed to commit and push,
+//! and the system config is ignored.
+//!
+//! The kept `credential.*` and `url.*` settings may contain tokens, so the
+//! file is only readable by the user, and removed when the run ends.
+
+use anyhow::{Context, Error};
+use std::io::Write;
+use tempfile::TempPath;
+
+/// The settings kept from the user's config, as a regex of names.
+const KEEP: &str = r"^(user\.(name|email)|credential\..*|url\..*)$";
+
+/// Settings that are always set, so they do not depend on git's defaults
+/// changing.
+const FIXED: &[(&str, &str)] = &[
+    ("advice.detachedHead", "false"),
+    ("commit.gpgSign", "false"),
+    ("tag.gpgSign", "false"),
+    ("fetch.prune", "false"),
+    ("pull.rebase", "false"),
+    ("rebase.autoStash", "false"),
+];
+
+/// Writes the config for this run to a new temporary file, which is removed
+/// when the returned path is dropped.
+pub fn write_isolated() -> Result<TempPath, Error> {
+    let config = git2::Config::open_default().context("Failed to read the git config.")?;
+    let mut entries = Vec::new();
+    config
+        .entries(Some(KEEP))?
+        .for_each(|entry| {
+            if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+                entries.push((name.to_string(), value.to_string()));
+            }
+        })
+        .context("Failed to read the git config.")?;
+    entries.extend(
+        FIXED
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+    );
+    // Created exclusively, with mode 0600 on unix.
+    let mut file = tempfile::Builder::new()
+        .prefix("subup-gitconfig-")
+        .tempfile()
+        .context("Failed to create the isolated git config.")?;
+    file.write_all(to_text(&entries).as_bytes())
+        .with_context(|| format!("Failed to write {}", file.path().display()))?;
+    Ok(file.into_temp_path())
+}
+
+/// Formats `(name, value)` settings as a git config file. Names are
+/// `section.key` or `section.subsection.key`, repeated names are kept as
+/// multiple values.
+fn to_text(entries: &[(String, String)]) -> String {
+    let mut text = String::new();
+    let mut current = None;
+    for (name, value) in entries {
+        let (section, key) = name.rsplit_once('.').unwrap_or(("core", name));
+        if current != Some(section) {
+            match section.split_once('.') {
+                Some((section, subsection)) => {
+                    text.push_str(&format!("[{} {}]\n", section, quote(subsection)));
+                }
+                None => text.push_str(&format!("[{}]\n", section)),
+            }
+            current = Some(section);
+        }
+        text.push_str(&format!("\t{} = {}\n", key, quote(value)));
+    }
+    text
+}
+
+/// Quotes a value or subsection name, escaping what git requires.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn private_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = write_isolated().unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let kept = path.to_path_buf();
+        drop(path);
+        assert!(!kept.exists());
+    }
+
+    #[test]
+    fn text() {
+        let entries: Vec<(String, String)> = [
+            ("user.name", "A \"B\" C"),
+            ("user.email", "abc@example.com"),
+            ("credential.helper", ""),
+            ("credential.helper", "store"),
+            (
+                "credential.https://github.com.helper",
+                "!gh auth git-credential",
+            ),
+            ("url.git@github.com:.pushinsteadof", "https://github.com/"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        assert_eq!(
+            to_text(&entries),
+            "[user]\n\
+             \tname = \"A \\\"B\\\" C\"\n\
+             \temail = \"abc@example.com\"\n\
+             [credential]\n\
+             \thelper = \"\"\n\
+             \thelper = \"store\"\n\
+             [credential \"https://github.com\"]\n\
+             \thelper = \"!gh auth git-credential\"\n\
+             [url \"git@github.com:\"]\n\
+             \tpushinsteadof = \"https://github.com/\"\n"
+        );
+    }
+}
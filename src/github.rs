@@ -0,0 +1,240 @@
+//! Minimal GitHub REST API client.
+//!
+//! Requests are made with `curl`. The token is passed on stdin so that it
+//! does not show up in the process list or verbose output.
+
+use crate::cli::Cli;
+use crate::credentials;
+use crate::runner::Spawned;
+use anyhow::{format_err, Context, Error};
+use serde_json::{json, Value};
+
+const API_URL: &str = "https://api.github.com";
+
+pub struct GitHubApi<'a> {
+    cli: &'a Cli<'a>,
+    token: String,
+}
+
+/// Combined CI status of a commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CiStatus {
+    Success,
+    Failure,
+    Pending,
+    /// The commit has no statuses or check runs.
+    Missing,
+}
+
+impl<'a> GitHubApi<'a> {
+    pub fn new(cli: &'a Cli<'a>) -> Result<GitHubApi<'a>, Error> {
+        let token = credentials::github_token(cli, "github.com")?;
+        Ok(GitHubApi { cli, token })
+    }
+
+    /// Sends a request to the given API path (such as `/repos/o/r`),
+    /// returning the JSON response.
+    pub fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value, Error> {
+        self.start(method, path, body)?.wait()
+    }
+
+    /// Starts a request in the background, see `request`.
+    pub fn start(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<PendingRequest, Error> {
+        let url = format!("{}{}", API_URL, path);
+        let mut args = vec![
+            "--silent".to_string(),
+            "--show-error".to_string(),
+            "--fail".to_string(),
+            "--request".to_string(),
+            method.to_string(),
+            "--header".to_string(),
+            "Accept: application/vnd.github+json".to_string(),
+            // Read the authorization header from stdin.
+            "--header".to_string(),
+            "@-".to_string(),
+        ];
+        if let Some(body) = body {
+            args.push("--data-binary".to_string());
+            args.push(body.to_string());
+        }
+        args.push(url);
+        let what = format!("{} {}", method, path);
+        let mut runner = self
+            .cli
+            .runner("curl", &args)
+            .stdin(format!("Authorization: Bearer {}\n", self.token));
+        if method != "GET" {
+            runner = runner.remote();
+        }
+        let spawned = runner.spawn(format!("Failed to {}", what))?;
+        Ok(PendingRequest { spawned, what })
+    }
+
+    pub fn get(&self, path: &str) -> Result<Value, Error> {
+        self.request("GET", path, None)
+    }
+
+    /// Creates a secret gist with one file, returning its URL.
+    pub fn create_gist(
+        &self,
+        description: &str,
+        filename: &str,
+        content: &str,
+    ) -> Result<String, Error> {
+        let gist = self.request(
+            "POST",
+            "/gists",
+            Some(&json!({
+                "description": description,
+                "public": false,
+                "files": { filename: { "content": content } },
+            })),
+        )?;
+        gist["html_url"]
+            .as_str()
+            .map(|url| url.to_string())
+            .ok_or_else(|| format_err!("Expected the gist URL in the response, got:\n{}", gist))
+    }
+
+    /// Returns whether PR `number` in `repo` (`owner/name`) is open, and its
+    /// title.
+    pub fn pr_state(&self, repo: &str, number: u64) -> Result<(bool, String), Error> {
+        let pr = self.get(&format!("/repos/{}/pulls/{}", repo, number))?;
+        let title = pr["title"].as_str().unwrap_or_default().to_string();
+        Ok((pr["state"].as_str() == Some("open"), title))
+    }
+
+    /// Returns the URL and body of the open PR in `repo` (`owner/name`)
+    /// from the branch `head` (`owner:branch`), if there is one.
+    pub fn open_pr_from(&self, repo: &str, head: &str) -> Result<Option<(String, String)>, Error> {
+        let prs = self.get(&format!(
+            "/repos/{}/pulls?state=open&head={}",
+            repo,
+            url_encode(head)
+        ))?;
+        Ok(prs.as_array().and_then(|prs| prs.first()).map(|pr| {
+            (
+                pr["html_url"].as_str().unwrap_or_default().to_string(),
+                pr["body"].as_str().unwrap_or_default().to_string(),
+            )
+        }))
+    }
+
+    /// Searches for open PRs matching `query`, returning the URL and title
+    /// of each.
+    pub fn search_open_prs(&self, query: &str) -> Result<Vec<(String, String)>, Error> {
+        let query = format!("{} is:pr is:open", query);
+        let results = self.get(&format!("/search/issues?q={}", url_encode(&query)))?;
+        Ok(results["items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|item| {
+                (
+                    item["html_url"].as_str().unwrap_or_default().to_string(),
+                    item["title"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect())
+    }
+
+    /// Returns the number of the open issue in `repo` (`owner/name`) titled
+    /// exactly `title`, if there is one.
+    pub fn find_open_issue(&self, repo: &str, title: &str) -> Result<Option<u64>, Error> {
+        let query = format!("repo:{} is:issue is:open in:title \"{}\"", repo, title);
+        let results = self.get(&format!("/search/issues?q={}", url_encode(&query)))?;
+        Ok(results["items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|issue| issue["title"] == title)
+            .and_then(|issue| issue["number"].as_u64()))
+    }
+
+    /// Returns the combined status of commit statuses and check runs for
+    /// `hash` in `repo` (`owner/name`).
+    pub fn ci_status(&self, repo: &str, hash: &str) -> Result<CiStatus, Error> {
+        self.start_ci_status(repo, hash)?.wait()
+    }
+
+    /// Starts querying the CI status in the background, see `ci_status`.
+    pub fn start_ci_status(&self, repo: &str, hash: &str) -> Result<PendingCiStatus, Error> {
+        let path = format!("/repos/{}/commits/{}", repo, hash);
+        Ok(PendingCiStatus {
+            status: self.start("GET", &format!("{}/status", path), None)?,
+            checks: self.start("GET", &format!("{}/check-runs", path), None)?,
+        })
+    }
+}
+
+/// Percent-encodes a URL query value.
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A request started with `GitHubApi::start`.
+pub struct PendingRequest {
+    spawned: Spawned,
+    /// The method and path, for error messages.
+    what: String,
+}
+
+impl PendingRequest {
+    /// Waits for the JSON response.
+    pub fn wait(self) -> Result<Value, Error> {
+        let PendingRequest { spawned, what } = self;
+        let output = spawned.wait()?;
+        serde_json::from_str(&output.stdout)
+            .with_context(|| format!("Failed to parse response from {}", what))
+    }
+}
+
+/// A query started with `GitHubApi::start_ci_status`.
+pub struct PendingCiStatus {
+    status: PendingRequest,
+    checks: PendingRequest,
+}
+
+impl PendingCiStatus {
+    pub fn wait(self) -> Result<CiStatus, Error> {
+        let mut states = Vec::new();
+        let status = self.status.wait()?;
+        if status["total_count"].as_u64().unwrap_or(0) > 0 {
+            states.push(match status["state"].as_str() {
+                Some("success") => CiStatus::Success,
+                Some("pending") => CiStatus::Pending,
+                _ => CiStatus::Failure,
+            });
+        }
+        let checks = self.checks.wait()?;
+        for run in checks["check_runs"].as_array().into_iter().flatten() {
+            states.push(match (run["status"].as_str(), run["conclusion"].as_str()) {
+                (Some("completed"), Some("success" | "neutral" | "skipped")) => CiStatus::Success,
+                (Some("completed"), _) => CiStatus::Failure,
+                _ => CiStatus::Pending,
+            });
+        }
+        Ok(if states.is_empty() {
+            CiStatus::Missing
+        } else if states.contains(&CiStatus::Failure) {
+            CiStatus::Failure
+        } else if states.contains(&CiStatus::Pending) {
+            CiStatus::Pending
+        } else {
+            CiStatus::Success
+        })
+    }
+}
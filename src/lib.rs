@@ -1,3 +1,22 @@
 pub mod cli;
+pub mod credentials;
+pub mod event;
+pub mod followup;
+pub mod forge;
+pub mod gitconfig;
+pub mod github;
+pub mod lock;
 pub mod log;
+pub mod msrv;
+pub mod pipeline;
+pub mod plan;
+pub mod profile;
+pub mod remote;
+pub mod repo;
 pub mod runner;
+pub mod schedule;
+pub mod spec;
+pub mod title;
+pub mod triagebot;
+#[cfg(feature = "tui")]
+pub mod tui;
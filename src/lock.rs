@@ -0,0 +1,235 @@
+//! Helpers for inspecting `Cargo.lock` changes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Parses the `(name, version)` of every package in a `Cargo.lock` file.
+fn packages(lock: &str) -> BTreeSet<(&str, &str)> {
+    let mut result = BTreeSet::new();
+    let mut name = None;
+    for line in lock.lines() {
+        let line = line.trim();
+        if line == "[[package]]" {
+            name = None;
+        } else if let Some(value) = line.strip_prefix("name = ") {
+            name = Some(value.trim_matches('"'));
+        } else if let Some(value) = line.strip_prefix("version = ") {
+            if let Some(name) = name.take() {
+                result.insert((name, value.trim_matches('"')));
+            }
+        }
+    }
+    result
+}
+
+/// The version of the local package `name` (one without a `source`, such
+/// as a workspace member) in a `Cargo.lock` file.
+pub fn local_version<'a>(lock: &'a str, name: &str) -> Option<&'a str> {
+    lock.split("[[package]]").skip(1).find_map(|package| {
+        let mut version = None;
+        let mut found = false;
+        for line in package.lines() {
+            let line = line.trim();
+            if let Some(value) = line.strip_prefix("name = ") {
+                found = value.trim_matches('"') == name;
+            } else if let Some(value) = line.strip_prefix("version = ") {
+                version = Some(value.trim_matches('"'));
+            } else if line.starts_with("source = ") {
+                return None;
+            }
+        }
+        if found {
+            version
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the names of packages that were added, removed, or changed
+/// version between two `Cargo.lock` files.
+pub fn changed_packages(old: &str, new: &str) -> Vec<String> {
+    let old = packages(old);
+    let new = packages(new);
+    let names: BTreeSet<&str> = old
+        .symmetric_difference(&new)
+        .map(|(name, _)| *name)
+        .collect();
+    names.into_iter().map(|name| name.to_string()).collect()
+}
+
+/// Describes the packages that were added, removed, or changed version
+/// between two `Cargo.lock` files, one line per change.
+pub fn change_summary(old: &str, new: &str) -> Vec<String> {
+    fn versions(lock: &str) -> BTreeMap<&str, BTreeSet<&str>> {
+        let mut result: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (name, version) in packages(lock) {
+            result.entry(name).or_default().insert(version);
+        }
+        result
+    }
+    let old = versions(old);
+    let new = versions(new);
+    let empty = BTreeSet::new();
+    let names: BTreeSet<&str> = old.keys().chain(new.keys()).copied().collect();
+    let mut lines = Vec::new();
+    for name in names {
+        let old_versions = old.get(name).unwrap_or(&empty);
+        let new_versions = new.get(name).unwrap_or(&empty);
+        let removed: Vec<&str> = old_versions.difference(new_versions).copied().collect();
+        let added: Vec<&str> = new_versions.difference(old_versions).copied().collect();
+        // A single version replaced by another is an update, otherwise
+        // (such as a semver-incompatible duplicate) each is listed.
+        if let ([from], [to]) = (removed.as_slice(), added.as_slice()) {
+            lines.push(format!("Updated `{}` v{} -> v{}", name, from, to));
+            continue;
+        }
+        for version in removed {
+            lines.push(format!("Removed `{}` v{}", name, version));
+        }
+        for version in added {
+            lines.push(format!("Added `{}` v{}", name, version));
+        }
+    }
+    lines
+}
+
+/// Parses the dependency names of every package in a `Cargo.lock` file,
+/// along with whether it is local (has no `source`, such as workspace
+/// members).
+fn dependencies(lock: &str) -> BTreeMap<&str, (bool, Vec<&str>)> {
+    let mut result = BTreeMap::new();
+    let mut name = None;
+    let mut in_deps = false;
+    for line in lock.lines() {
+        let line = line.trim();
+        if line == "[[package]]" {
+            name = None;
+            in_deps = false;
+        } else if let Some(value) = line.strip_prefix("name = ") {
+            let value = value.trim_matches('"');
+            name = Some(value);
+            result.insert(value, (true, Vec::new()));
+        } else if let Some(entry) = name.and_then(|name| result.get_mut(name)) {
+            if line.starts_with("source = ") {
+                entry.0 = false;
+            } else if line == "dependencies = [" {
+                in_deps = true;
+            } else if in_deps && line == "]" {
+                in_deps = false;
+            } else if in_deps {
+                // Entries are `"name"`, `"name version"`, or
+                // `"name version (source)"`.
+                let dep = line.trim_matches(|c| c == '"' || c == ',');
+                entry.1.push(dep.split(' ').next().unwrap());
+            }
+        }
+    }
+    result
+}
+
+/// Returns the names of the local packages in `lock` that depend on any of
+/// `changed`, directly or indirectly.
+pub fn affected_local_packages(lock: &str, changed: &[String]) -> Vec<String> {
+    let packages = dependencies(lock);
+    let mut affected: BTreeSet<&str> = changed.iter().map(|name| name.as_str()).collect();
+    loop {
+        let more: Vec<&str> = packages
+            .iter()
+            .filter(|(name, (_, deps))| {
+                !affected.contains(*name) && deps.iter().any(|dep| affected.contains(dep))
+            })
+            .map(|(name, _)| *name)
+            .collect();
+        if more.is_empty() {
+            break;
+        }
+        affected.extend(more);
+    }
+    affected
+        .into_iter()
+        .filter(|name| packages.get(name).is_some_and(|(local, _)| *local))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = r#"# This file is automatically @generated by Cargo.
+[[package]]
+name = "cargotest2"
+version = "0.1.0"
+
+[[package]]
+name = "clippy"
+version = "0.1.0"
+dependencies = [
+ "serde",
+ "toml 0.5.8 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "rustfmt"
+version = "1.4.0"
+dependencies = [
+ "serde",
+]
+
+[[package]]
+name = "serde"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "toml"
+version = "0.5.8"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "serde",
+]
+"#;
+
+    #[test]
+    fn affected() {
+        let changed =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+        assert_eq!(
+            affected_local_packages(LOCK, &changed(&["toml"])),
+            ["clippy"]
+        );
+        assert_eq!(
+            affected_local_packages(LOCK, &changed(&["serde"])),
+            ["clippy", "rustfmt"]
+        );
+        assert!(affected_local_packages(LOCK, &[]).is_empty());
+    }
+
+    #[test]
+    fn local_versions() {
+        assert_eq!(local_version(LOCK, "rustfmt"), Some("1.4.0"));
+        assert_eq!(local_version(LOCK, "serde"), None);
+        assert_eq!(local_version(LOCK, "missing"), None);
+    }
+
+    #[test]
+    fn summary() {
+        let new = LOCK
+            .replace("version = \"1.4.0\"", "version = \"1.5.0\"")
+            .replace(
+                "[[package]]\nname = \"cargotest2\"\nversion = \"0.1.0\"\n",
+                "",
+            )
+            + "\n[[package]]\nname = \"toml\"\nversion = \"0.7.0\"\n\n[[package]]\nname = \"url\"\nversion = \"2.0.0\"\n";
+        assert_eq!(
+            change_summary(LOCK, &new),
+            [
+                "Removed `cargotest2` v0.1.0",
+                "Updated `rustfmt` v1.4.0 -> v1.5.0",
+                "Added `toml` v0.7.0",
+                "Added `url` v2.0.0",
+            ]
+        );
+        assert!(change_summary(LOCK, LOCK).is_empty());
+    }
+}
@@ -1,5 +1,9 @@
 use crate::cli::Cli;
-use anyhow::{bail, format_err, Error};
+use crate::followup;
+use crate::remote::RemoteUrl;
+use crate::repo;
+use crate::runner::Spawned;
+use anyhow::{bail, format_err, Context, Error};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::borrow::Cow;
@@ -7,173 +11,871 @@ use std::fs;
 use std::iter;
 use std::path::Path;
 
+/// Markdown rendering of the commit message, used for the PR body.
+pub const COMMIT_MSG_PATH: &str = ".SUBUP_COMMIT_MSG";
+/// Plain-text rendering of the commit message, used for the git commit body.
+pub const COMMIT_MSG_PLAIN_PATH: &str = ".SUBUP_COMMIT_MSG.txt";
+/// Where the commits of a submodule come from.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    /// The `owner/name` GitHub repository, for links to its PRs.
+    pub repo: String,
+    /// Whether the hashes are rewritten, such as for josh subtrees. GitHub
+    /// does not know them, so there are no links to them.
+    pub rewritten: bool,
+}
+
+/// Summaries longer than this many characters are shortened.
+const MAX_SUMMARY_CHARS: usize = 200;
+
+/// Writes the commit message for the given submodule ranges to
+/// `COMMIT_MSG_PATH` and `COMMIT_MSG_PLAIN_PATH`.
+///
+/// Conventional-commit prefixes (`fix:`) are removed from the summaries
+/// with the `strip-commit-prefixes` option. With `collapse-after N`, a
+/// submodule with more than N commits only gets a compare link instead of
+/// the list.
 pub fn generate_commit(
     cli: &Cli,
     // (path, start_hash, end_hash)
     submodules: &[(&str, impl AsRef<str>, impl AsRef<str>)],
 ) -> Result<(), Error> {
-    let commit_re = Regex::new(r"(?m)^commit ([0-9A-Fa-f]+)").unwrap();
-    let message_re = Regex::new(r"(?m)^$\n").unwrap();
-    let summary_res = [
-        Regex::new(
-            r"(?mx)
-        \s*Merge\ pull\ request\ \#(?P<pr>[0-9]+).*\n
-        \s*(?P<summary>.*)",
-        )
-        .unwrap(),
-        Regex::new(
-            r"(?mx)
-        \s*Auto\ merge\ of\ \#(?P<pr>[0-9]+).*\n
-        \s*(?P<summary>.*)",
-        )
-        .unwrap(),
-        Regex::new(r"(?mx)\s*(?P<summary>.*)").unwrap(),
-    ];
-    let gh_short_re = Regex::new(r"(?:^|\B)(#[0-9]+)\b").unwrap();
+    let origins = submodules
+        .iter()
+        .map(|(path, _, _)| {
+            Ok(Origin {
+                repo: git_origin(cli, path)?,
+                rewritten: false,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    generate_commit_with_origins(cli, submodules, &origins)
+}
 
-    fn path_to_name(path: &str) -> Cow<str> {
+/// Same as `generate_commit`, with the origin of each submodule given, for
+/// sources whose origin is not their upstream (such as josh subtrees).
+pub fn generate_commit_with_origins(
+    cli: &Cli,
+    // (path, start_hash, end_hash)
+    submodules: &[(&str, impl AsRef<str>, impl AsRef<str>)],
+    origins: &[Origin],
+) -> Result<(), Error> {
+    let strip_prefix = cli.matches.is_present("strip-commit-prefixes");
+    let collapse_after = match cli.matches.value_of("collapse-after") {
+        Some(n) => Some(
+            n.parse::<usize>()
+                .with_context(|| format!("Invalid --collapse-after `{}`", n))?,
+        ),
+        None => None,
+    };
+
+    fn path_to_name(path: &str) -> Cow<'_, str> {
         Path::new(path).file_name().unwrap().to_string_lossy()
     }
 
-    let mut result = Vec::new();
+    // Sections are labeled by name, unless two submodules share a name or
+    // an upstream repository, in which case the path is used.
+    let label = |i: usize| -> Cow<'_, str> {
+        let path = submodules[i].0;
+        let is_ambiguous = submodules.iter().enumerate().any(|(j, (other, _, _))| {
+            j != i
+                && (path_to_name(other) == path_to_name(path) || origins[j].repo == origins[i].repo)
+        });
+        if is_ambiguous {
+            Cow::from(path)
+        } else {
+            path_to_name(path)
+        }
+    };
+
+    let mut markdown = Vec::new();
+    let mut plain = Vec::new();
+    let mut followups = Vec::new();
 
+    // The range may not be available locally (shallow or not fetched).
     for (path, start_hash, end_hash) in submodules {
+        fetch_if_missing(cli, path, start_hash.as_ref())?;
+        fetch_if_missing(cli, path, end_hash.as_ref())?;
+    }
+    // Each submodule needs several git commands, so they are all started at
+    // once. They are waited on in order, which keeps the output the same.
+    let pending = submodules
+        .iter()
+        .map(|(path, start_hash, end_hash)| {
+            PendingLog::start(cli, path, start_hash.as_ref(), end_hash.as_ref())
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for (i, ((path, start_hash, end_hash), pending)) in submodules.iter().zip(pending).enumerate() {
         let start_hash = start_hash.as_ref();
         let end_hash = end_hash.as_ref();
-        let origin = git_origin(cli, path)?;
-        // git log
-        let output = cli
-            .git(&format!("log --first-parent {}..{}", start_hash, end_hash))
-            .dir(path)
-            .capture_stdout("Failed to get log for submodule.")?;
-        // Find where ^commit starts.
-        let commit_starts: Vec<(usize, &str)> = commit_re
-            .captures_iter(&output)
-            .map(|c| {
-                (
-                    c.get(0).expect("missing match").start(),
-                    c.get(1).expect("missing hash match").as_str(),
-                )
-            })
-            .collect();
-        // The end index of each commit message.
-        let ends = commit_starts
-            .iter()
-            .skip(1)
-            .map(|(start, _)| *start)
-            .chain(iter::once(output.len()));
-        // Collect each commit message and hash.
-        let messages = commit_starts.iter().zip(ends).map(|((start, hash), end)| {
-            let commit = &output[*start..end];
-            // Skip past the headers.
-            let message_start = message_re
-                .find(commit)
-                .expect("can't find commit message")
-                .end();
-            let headers = &commit[..message_start];
-            let message = &commit[message_start..];
-            (hash, headers, message)
-        });
-        // Extract a summary from the commit message.
+        let origin = &origins[i].repo;
+        let output = stdout(pending.log)?;
+        // Extract a summary from each commit message.
         let mut summaries = Vec::new();
-        for (hash, headers, message) in messages {
-            for (summary, pr) in find_summary(&summary_res, headers, message)? {
-                // Rewrite github relative links to the correct path.
-                let summary = summary.replace("<", "&lt;").replace(">", "&gt;");
-                let summary = gh_short_re
-                    .replace_all(&summary, format!("{}$1", origin).as_str())
-                    .into_owned();
-                summaries.push((*hash, summary, pr));
+        for (hash, headers, message) in split_log(&output) {
+            for (summary, pr) in find_summary(headers, message) {
+                summaries.push((hash, summary, pr));
             }
         }
+        dedup_summaries(&mut summaries);
         // Create a commit summary.
-        let mut submodule_summary = Vec::new();
+        let mut md_summary = Vec::new();
+        let mut plain_summary = Vec::new();
         if submodules.len() > 1 {
-            let name = path_to_name(path);
-            submodule_summary.push(format!("## {}", name));
-            submodule_summary.push("".to_string());
+            let name = label(i);
+            md_summary.push(format!("## {}", name));
+            md_summary.push("".to_string());
+            plain_summary.push(format!("{}:", name));
+            plain_summary.push("".to_string());
         }
-        // if summaries.len() > 15 {
-        submodule_summary.push(format!(
-            "{} commits in {}..{}",
+        let dates = stdout(pending.dates)?;
+        let mut dates = dates.lines();
+        let start_date = dates.next().unwrap_or_default();
+        // `git log` shows the commit once when the hashes are the same.
+        let end_date = dates.next().unwrap_or(start_date);
+        let tags = stdout(pending.tags)?;
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            format!(" (tag {})", tags.lines().collect::<Vec<_>>().join(", "))
+        };
+        let header = format!(
+            "{} commits in {}..{}{}\n{} to {}",
             summaries.len(),
             start_hash,
-            end_hash
-        ));
-        submodule_summary.push(format!(
-            "{} to {}",
-            git_date(cli, path, start_hash)?,
-            git_date(cli, path, end_hash)?
-        ));
-        // } else {
+            end_hash,
+            tags,
+            start_date,
+            end_date
+        );
+        md_summary.push(header.clone());
+        plain_summary.push(header);
+        if collapse_after.is_some_and(|max| summaries.len() > max) {
+            let compare = full_list(&origins[i], start_hash, end_hash);
+            md_summary.push(String::new());
+            md_summary.push(compare.clone());
+            plain_summary.push(String::new());
+            plain_summary.push(compare);
+            summaries.clear();
+        }
         for (_hash, summary, pr) in summaries {
+            let summary = defang(&normalize_summary(summary, strip_prefix));
+            let md = summary.replace("<", "&lt;").replace(">", "&gt;");
+            let md = qualify_refs(&md, origin);
             let extra = if let Some(pr) = pr {
                 format!(" ({}#{})", origin, pr)
             } else {
                 String::new()
             };
-            submodule_summary.push(format!("- {}{}", summary, extra));
+            md_summary.push(format!("- {}{}", md, extra));
+            plain_summary.push(format!("- {}", qualify_refs(&summary, origin)));
         }
-        // }
-        result.push(submodule_summary.join("\n"));
+        markdown.push(md_summary.join("\n"));
+        plain.push(plain_summary.join("\n"));
+
+        let changed = stdout(pending.changed)?;
+        let changed: Vec<&str> = changed.lines().collect();
+        followups.extend(followup::checklist(path, &changed));
     }
 
-    let output = result.join("\n\n") + "\n";
-    fs::write(".SUBUP_COMMIT_MSG", output)?;
+    if !followups.is_empty() {
+        markdown.push(format!("## Possible follow-up\n\n{}", followups.join("\n")));
+    }
+
+    fs::write(COMMIT_MSG_PATH, markdown.join("\n\n") + "\n")?;
+    fs::write(COMMIT_MSG_PLAIN_PATH, plain.join("\n\n") + "\n")?;
     Ok(())
 }
 
-fn find_summary<'a>(
-    summary_res: &[Regex],
-    headers: &'a str,
-    message: &'a str,
-) -> Result<Vec<(&'a str, Option<&'a str>)>, Error> {
+/// Rewrites GitHub relative links such as `#123` in `text` to point to
+/// `origin`, since they would link to the rust repo.
+fn qualify_refs(text: &str, origin: &str) -> String {
+    lazy_static! {
+        static ref GH_SHORT_RE: Regex = Regex::new(r"(?:^|\B)(#[0-9]+)\b").unwrap();
+    }
+    GH_SHORT_RE
+        .replace_all(text, format!("{}$1", origin).as_str())
+        .into_owned()
+}
+
+/// Where to find the commits left out by `collapse-after`.
+fn full_list(origin: &Origin, start_hash: &str, end_hash: &str) -> String {
+    if origin.rewritten {
+        format!(
+            "See `git log {}..{}` for the full list.",
+            start_hash, end_hash
+        )
+    } else {
+        format!(
+            "See https://github.com/{}/compare/{}...{} for the full list.",
+            origin.repo, start_hash, end_hash
+        )
+    }
+}
+
+/// Splits the output of `git log` into the `(hash, headers, message)` of
+/// each commit.
+fn split_log(output: &str) -> Vec<(&str, &str, &str)> {
+    lazy_static! {
+        static ref COMMIT_RE: Regex = Regex::new(r"(?m)^commit ([0-9A-Fa-f]+)").unwrap();
+        static ref MESSAGE_RE: Regex = Regex::new(r"(?m)^\r?$\n").unwrap();
+    }
+    // Find where ^commit starts.
+    let commit_starts: Vec<(usize, &str)> = COMMIT_RE
+        .captures_iter(output)
+        .map(|c| (c.get(0).unwrap().start(), c.get(1).unwrap().as_str()))
+        .collect();
+    // The end index of each commit message.
+    let ends = commit_starts
+        .iter()
+        .skip(1)
+        .map(|(start, _)| *start)
+        .chain(iter::once(output.len()));
+    commit_starts
+        .iter()
+        .zip(ends)
+        .map(|((start, hash), end)| {
+            let commit = &output[*start..end];
+            // Skip past the headers. A commit with an empty message may not
+            // have a blank line after them.
+            let message_start = MESSAGE_RE
+                .find(commit)
+                .map(|m| m.end())
+                .unwrap_or_else(|| commit.len());
+            (*hash, &commit[..message_start], &commit[message_start..])
+        })
+        .collect()
+}
+
+/// Writes the commit message for the given submodules, from the commit
+/// recorded in the upstream `branch` to the commit checked out in the
+/// submodule.
+pub fn generate_commit_from_branch(
+    cli: &Cli,
+    branch: &str,
+    submodules: &[&str],
+) -> Result<(), Error> {
+    let submodules = submodules
+        .iter()
+        .map(|path| {
+            let first = repo::try_hash(".", &format!("{}:{}", cli.upstream_branch(branch), path))?
+                .ok_or_else(|| format_err!("`{}` is not a submodule in `{}`", path, branch))?;
+            let current = repo::worktree_head(path)?;
+            Ok((*path, first, current))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    generate_commit(cli, &submodules)
+}
+
+/// Extracts the `(summary, pr_number)` of each change in a commit message.
+///
+/// This never fails, so that a single malformed upstream commit does not
+/// abort the update. The summary may be empty.
+fn find_summary<'a>(headers: &'a str, message: &'a str) -> Vec<(&'a str, Option<&'a str>)> {
+    lazy_static! {
+        static ref NG_RE: Regex = Regex::new(r"(?m)^\s*([0-9]+): (.*)(?:r=.* a=.*$)").unwrap();
+        static ref SUMMARY_RES: [Regex; 3] = [
+            // GitHub merge commits, including ones from the merge queue.
+            Regex::new(
+                r"(?mx)
+            \s*Merge\ pull\ request\ \#(?P<pr>[0-9]+).*\n
+            \s*(?P<summary>.*)",
+            )
+            .unwrap(),
+            Regex::new(
+                r"(?mx)
+            \s*Auto\ merge\ of\ \#(?P<pr>[0-9]+).*\n
+            \s*(?P<summary>.*)",
+            )
+            .unwrap(),
+            // Squash merges, where GitHub appends the PR number to the
+            // title. The merge queue does the same when it squashes.
+            Regex::new(
+                r"(?x)
+            \A\s*(?P<summary>[^\n]*?)\s*\(\#(?P<pr>[0-9]+)\)[\ \t\r]*(?:\n|\z)",
+            )
+            .unwrap(),
+        ];
+    }
     if headers.contains("bors[bot]") && message.contains("Merge #") {
         // bors-ng style consolidated merge
-        lazy_static! {
-            static ref NG_RE: Regex = Regex::new(r"(?m)^\s*([0-9]+): (.*)(?:r=.* a=.*$)").unwrap();
-        }
         let results: Vec<_> = NG_RE
             .captures_iter(message)
             .map(|cap| {
                 (
-                    cap.get(2).unwrap().as_str(),
+                    cap.get(2).unwrap().as_str().trim_end(),
                     Some(cap.get(1).unwrap().as_str()),
                 )
             })
             .collect();
         if !results.is_empty() {
-            return Ok(results);
+            return results;
         }
     }
-    for re in summary_res {
+    for re in SUMMARY_RES.iter() {
         if let Some(captures) = re.captures(message) {
-            let summary = captures
-                .name("summary")
-                .expect("Can't find summary")
-                .as_str();
+            let summary = captures.name("summary").unwrap().as_str().trim_end();
             let pr = captures.name("pr").map(|m| m.as_str());
-            return Ok(vec![(summary, pr)]);
+            return vec![(summary, pr)];
+        }
+    }
+    // Otherwise the first line is the summary.
+    let summary = message.trim_start().lines().next().unwrap_or("").trim_end();
+    vec![(summary, None)]
+}
+
+/// Keeps summaries from notifying people or closing issues when they are
+/// copied into the rust repo: a zero-width space is inserted after the `@`
+/// of mentions, into closing keywords such as `fixes #123`, and into
+/// triagebot's `r?` so it doesn't pick a reviewer from a summary.
+fn defang(summary: &str) -> String {
+    lazy_static! {
+        static ref MENTION_RE: Regex =
+            Regex::new(r"(^|[^\w`@])@([A-Za-z0-9][A-Za-z0-9-]*)").unwrap();
+        static ref KEYWORD_RE: Regex = Regex::new(
+            r"(?i)\b(close[sd]?|fix(?:e[sd])?|resolve[sd]?)(:?\s+)([\w.-]*/?[\w.-]*#[0-9]+|https://github\.com/)"
+        )
+        .unwrap();
+        static ref REVIEW_RE: Regex = Regex::new(r"(^|\s)r\?(\s|$)").unwrap();
+    }
+    let summary = MENTION_RE.replace_all(summary, "$1@\u{200B}$2");
+    let summary = REVIEW_RE.replace_all(&summary, "${1}r\u{200B}?$2");
+    KEYWORD_RE
+        .replace_all(&summary, |c: &regex::Captures<'_>| {
+            let keyword = &c[1];
+            format!(
+                "{}\u{200B}{}{}{}",
+                &keyword[..1],
+                &keyword[1..],
+                &c[2],
+                &c[3]
+            )
+        })
+        .into_owned()
+}
+
+/// Removes consecutive duplicate summaries (the same PR, or the same text),
+/// such as a PR merge commit next to its squashed commit. The one with a PR
+/// number is kept.
+fn dedup_summaries(summaries: &mut Vec<(&str, &str, Option<&str>)>) {
+    let mut result: Vec<(&str, &str, Option<&str>)> = Vec::new();
+    for entry in summaries.drain(..) {
+        if let Some(last) = result.last_mut() {
+            let same_pr = last.2.is_some() && last.2 == entry.2;
+            if same_pr || last.1.trim() == entry.1.trim() {
+                if last.2.is_none() {
+                    *last = entry;
+                }
+                continue;
+            }
+        }
+        result.push(entry);
+    }
+    *summaries = result;
+}
+
+/// Cleans up a summary for the changelog: collapses whitespace, removes a
+/// trailing period, shortens very long summaries at a word boundary, and
+/// optionally removes a conventional-commit prefix such as `fix(ui):`.
+fn normalize_summary(summary: &str, strip_prefix: bool) -> String {
+    lazy_static! {
+        static ref PREFIX_RE: Regex = Regex::new(
+            r"(?i)^(build|chore|ci|docs|feat|fix|perf|refactor|revert|style|test)(\([^)]*\))?!?:\s+"
+        )
+        .unwrap();
+    }
+    let mut summary = summary.split_whitespace().collect::<Vec<_>>().join(" ");
+    if strip_prefix {
+        let end = PREFIX_RE.find(&summary).map_or(0, |m| m.end());
+        if end < summary.len() {
+            summary.replace_range(..end, "");
+        }
+    }
+    // Keep ellipses.
+    if summary.ends_with('.') && !summary.ends_with("..") {
+        summary.pop();
+    }
+    if summary.chars().count() > MAX_SUMMARY_CHARS {
+        let cut: String = summary.chars().take(MAX_SUMMARY_CHARS).collect();
+        let cut = match cut.rfind(' ') {
+            Some(space) => &cut[..space],
+            None => &cut,
+        };
+        summary = format!(
+            "{}…",
+            cut.trim_end_matches(|c: char| c.is_ascii_punctuation())
+        );
+    }
+    summary
+}
+
+/// Fetches the given commit from `origin` if it is not in the submodule.
+fn fetch_if_missing(cli: &Cli, path: &str, hash: &str) -> Result<(), Error> {
+    if repo::try_hash(path, &format!("{}^{{commit}}", hash))?.is_none() {
+        cli.info(&format!(
+            "Commit `{}` not found in `{}`, fetching from origin.",
+            hash, path
+        ))?;
+        cli.git_cmd(["fetch", "origin", hash])
+            .dir(path)
+            .read_only()
+            .run(format!("Failed to fetch `{}` in module `{}`.", hash, path))?;
+    }
+    Ok(())
+}
+
+/// The git commands for the changelog of one submodule, running in the
+/// background.
+struct PendingLog {
+    log: Spawned,
+    tags: Spawned,
+    /// The dates of the start and end commits, one per line.
+    dates: Spawned,
+    changed: Spawned,
+}
+
+impl PendingLog {
+    fn start(cli: &Cli, path: &str, start_hash: &str, end_hash: &str) -> Result<PendingLog, Error> {
+        Ok(PendingLog {
+            log: cli
+                .git_cmd([
+                    "log",
+                    "--first-parent",
+                    &format!("{}..{}", start_hash, end_hash),
+                ])
+                .dir(path)
+                .spawn("Failed to get log for submodule.")?,
+            tags: cli
+                .git_cmd(["tag", "--points-at", end_hash])
+                .dir(path)
+                .spawn("Failed to get tags for submodule.")?,
+            dates: cli
+                .git_cmd([
+                    "log",
+                    "--no-walk=unsorted",
+                    "--format=%ci",
+                    start_hash,
+                    end_hash,
+                ])
+                .dir(path)
+                .spawn("Failed to get dates for submodule.")?,
+            changed: cli
+                .git_cmd(["diff", "--name-only", start_hash, end_hash])
+                .dir(path)
+                .spawn("Failed to get changed files for submodule.")?,
+        })
+    }
+}
+
+/// Waits for `command`, returning its trimmed stdout.
+fn stdout(command: Spawned) -> Result<String, Error> {
+    Ok(command.wait()?.stdout.trim().to_string())
+}
+
+/// The `owner/name` of the GitHub repository of the submodule at `path`,
+/// remembered for the rest of the run.
+pub fn git_origin(cli: &Cli, path: &str) -> Result<String, Error> {
+    cli.cached_origin(path, || {
+        let origin = cli
+            .git("config --get remote.origin.url")
+            .dir(path)
+            .capture_stdout("Failed to get origin")?;
+        github_repo(&origin)
+    })
+}
+
+/// The `owner/name` of a GitHub repository URL.
+pub fn github_repo(url: &str) -> Result<String, Error> {
+    let remote = RemoteUrl::parse(url)?;
+    if !remote.is_github() {
+        bail!("`{}` is not a GitHub repository", url);
+    }
+    Ok(remote.slug())
+}
+
+/// Lines of context shown around each change by `diff_lines`.
+const DIFF_CONTEXT: usize = 2;
+
+/// A line diff from `old` to `new`, with `-`, `+`, or ` ` before each line
+/// and `...` where unchanged lines are left out. Empty if they are the same.
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // lcs[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', old[i]));
+            i += 1;
+        } else {
+            ops.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    let mut lines = Vec::new();
+    let mut next = 0;
+    for (k, (op, line)) in ops.iter().enumerate() {
+        let near_change = changed
+            .iter()
+            .any(|&c| k + DIFF_CONTEXT >= c && k <= c + DIFF_CONTEXT);
+        if !near_change {
+            continue;
+        }
+        if k > next {
+            lines.push("...".to_string());
         }
+        lines.push(format!("{}{}", op, line));
+        next = k + 1;
+    }
+    if !lines.is_empty() && next < ops.len() {
+        lines.push("...".to_string());
     }
-    bail!("Could not find summary in {:?}", message);
+    lines
 }
 
-fn git_date(cli: &Cli, path: &str, hash: &str) -> Result<String, Error> {
-    Ok(cli
-        .git(&format!("show -s --format=%ci {}", hash))
-        .dir(path)
-        .capture_stdout("Failed to get date for hash")?)
+/// The changelog at the start of a PR `body`, without the notes, triagebot
+/// lines, and DCO attestation that `subup update` adds after it.
+pub fn strip_pr_extras(body: &str) -> String {
+    lazy_static! {
+        static ref HEADER_RE: Regex = Regex::new(r"(?m)^[0-9]+ commits in \S+\.\.").unwrap();
+    }
+    let is_changelog = |paragraph: &&str| {
+        paragraph.starts_with("## ")
+            || paragraph.starts_with("See ")
+            || paragraph.lines().any(|line| line.starts_with("- "))
+            || HEADER_RE.is_match(paragraph)
+    };
+    let paragraphs: Vec<&str> = body.trim_end().split("\n\n").collect();
+    match paragraphs.iter().rposition(is_changelog) {
+        Some(last) => paragraphs[..=last].join("\n\n") + "\n",
+        None => body.to_string(),
+    }
 }
 
-fn git_origin(cli: &Cli, path: &str) -> Result<String, Error> {
-    let re = Regex::new(r"github.com[:/]([^/]+/[^.]+)(\.git)?").unwrap();
-    let origin = cli
-        .git("config --get remote.origin.url")
-        .dir(path)
-        .capture_stdout("Failed to get origin")?;
-    let c = re
-        .captures(&origin)
-        .ok_or_else(|| format_err!("Could not find github relative in `{}`", origin))?;
-    Ok(c.get(1).unwrap().as_str().to_string())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Extracts the summaries from `git log` output.
+    fn summaries(output: &str) -> Vec<(&str, Option<&str>)> {
+        split_log(output)
+            .into_iter()
+            .flat_map(|(_hash, headers, message)| find_summary(headers, message))
+            .collect()
+    }
+
+    #[test]
+    fn merges() {
+        let output = "\
+commit 1111111111111111111111111111111111111111
+Merge: aaaaaaa bbbbbbb
+Author: bors <bors@rust-lang.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Auto merge of #6543 - ehuss:fix, r=alexcrichton
+
+    Fix a thing
+
+commit 2222222222222222222222222222222222222222
+Merge: ccccccc ddddddd
+Author: Eric Huss <eric@huss.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Merge pull request #1701 from rust-lang/fix
+
+    Fix <T> in #1700
+";
+        assert_eq!(
+            summaries(output),
+            vec![
+                ("Fix a thing", Some("6543")),
+                ("Fix <T> in #1700", Some("1701"))
+            ]
+        );
+    }
+
+    #[test]
+    fn bors_ng() {
+        let output = "\
+commit 3333333333333333333333333333333333333333
+Merge: aaaaaaa bbbbbbb
+Author: bors[bot] <26634292+bors[bot]@users.noreply.github.com>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Merge #3717 #3722
+
+    3717: Add a test r=matklad a=ehuss
+    \n    3722: Fix the build r=matklad a=kjeremy
+    \n    Co-authored-by: Eric Huss <eric@huss.org>
+";
+        assert_eq!(
+            summaries(output),
+            vec![
+                ("Add a test", Some("3717")),
+                ("Fix the build", Some("3722"))
+            ]
+        );
+        // A bors-ng merge without the usual lines falls back to the first
+        // line.
+        let output = output.replace(" r=matklad a=", " ");
+        assert_eq!(summaries(&output), vec![("Merge #3717 #3722", None)]);
+    }
+
+    #[test]
+    fn squash_and_merge_queue() {
+        let output = "\
+commit 6666666666666666666666666666666666666666
+Author: Eric Huss <eric@huss.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Fix the (other) thing (#1234)
+
+    * Fix it (#1233)
+
+commit 7777777777777777777777777777777777777777
+Merge: aaaaaaa bbbbbbb
+Author: GitHub <noreply@github.com>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Merge pull request #1235 from ehuss/gh-readonly-queue/main/pr-1235-abc
+
+    Queued change
+
+commit 8888888888888888888888888888888888888888
+Author: Eric Huss <eric@huss.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Mention (#1234) in the middle
+";
+        assert_eq!(
+            summaries(output),
+            vec![
+                ("Fix the (other) thing", Some("1234")),
+                ("Queued change", Some("1235")),
+                ("Mention (#1234) in the middle", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn gpg_signed() {
+        let output = "\
+commit 4444444444444444444444444444444444444444
+gpg: Signature made Mon Jan  1 00:00:00 2019 UTC
+gpg:                using RSA key 0123456789ABCDEF
+gpg: Good signature from \"Eric Huss <eric@huss.org>\" [ultimate]
+Author: Eric Huss <eric@huss.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+    Signed change
+";
+        assert_eq!(summaries(output), vec![("Signed change", None)]);
+    }
+
+    #[test]
+    fn empty_messages() {
+        let output = "\
+commit 5555555555555555555555555555555555555555
+Author: Eric Huss <eric@huss.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+
+commit 6666666666666666666666666666666666666666
+Author: Eric Huss <eric@huss.org>
+Date:   Mon Jan 1 00:00:00 2019 +0000
+";
+        assert_eq!(summaries(output), vec![("", None), ("", None)]);
+        assert!(summaries("").is_empty());
+    }
+
+    #[test]
+    fn crlf() {
+        let output = "commit 7777777777777777777777777777777777777777\r\n\
+            Author: Eric Huss <eric@huss.org>\r\n\
+            Date:   Mon Jan 1 00:00:00 2019 +0000\r\n\
+            \r\n    \
+            Merge pull request #12 from a/b\r\n\
+            \r\n    \
+            Windows line endings\r\n";
+        assert_eq!(
+            summaries(output),
+            vec![("Windows line endings", Some("12"))]
+        );
+    }
+
+    #[test]
+    fn non_utf8() {
+        let bytes = b"commit 8888888888888888888888888888888888888888\n\
+            Author: Eric Huss <eric@huss.org>\n\
+            Date:   Mon Jan 1 00:00:00 2019 +0000\n\
+            \n    \
+            Caf\xe9 \xff\xfe\n";
+        let output = String::from_utf8_lossy(bytes);
+        assert_eq!(
+            summaries(&output),
+            vec![("Caf\u{fffd} \u{fffd}\u{fffd}", None)]
+        );
+    }
+
+    #[test]
+    fn defang_mentions_and_keywords() {
+        assert_eq!(defang("Thanks @ehuss!"), "Thanks @\u{200B}ehuss!");
+        assert_eq!(defang("@bors r+"), "@\u{200B}bors r+");
+        assert_eq!(defang("Mail a@b.com, `@x`"), "Mail a@b.com, `@x`");
+        assert_eq!(defang("Fixes #123"), "F\u{200B}ixes #123");
+        assert_eq!(
+            defang("closes: rust-lang/cargo#1 and fix https://github.com/a/b/issues/2"),
+            "c\u{200B}loses: rust-lang/cargo#1 and f\u{200B}ix https://github.com/a/b/issues/2"
+        );
+        assert_eq!(defang("Fix the fixes"), "Fix the fixes");
+        assert_eq!(defang("r? ghost"), "r\u{200B}? ghost");
+        assert_eq!(defang("Is it a bar? no"), "Is it a bar? no");
+    }
+
+    #[test]
+    fn dedup() {
+        let mut summaries = vec![
+            ("a", "Fix it", None),
+            ("b", "Fix it", Some("12")),
+            ("c", "Fix it (#12)", Some("12")),
+            ("d", "Other", None),
+            ("e", "Fix it", None),
+        ];
+        dedup_summaries(&mut summaries);
+        assert_eq!(
+            summaries,
+            [
+                ("b", "Fix it", Some("12")),
+                ("d", "Other", None),
+                ("e", "Fix it", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_summary("Fix  the\tthing.", false),
+            "Fix the thing"
+        );
+        assert_eq!(normalize_summary("Wait for it...", false), "Wait for it...");
+        assert_eq!(
+            normalize_summary("fix(ui): Colors", false),
+            "fix(ui): Colors"
+        );
+        assert_eq!(normalize_summary("fix(ui): Colors", true), "Colors");
+        assert_eq!(normalize_summary("chore!: Bump", true), "Bump");
+        assert_eq!(normalize_summary("fix: ", true), "fix:");
+        assert_eq!(normalize_summary("Fixes: #123", true), "Fixes: #123");
+        let long = "word ".repeat(100);
+        let short = normalize_summary(&long, false);
+        assert!(short.chars().count() <= MAX_SUMMARY_CHARS + 1);
+        assert!(short.ends_with("word…"), "{}", short);
+    }
+
+    #[test]
+    fn github_repos() {
+        for url in &[
+            "https://github.com/rust-lang/cargo",
+            "https://github.com/rust-lang/cargo.git",
+            "git@github.com:rust-lang/cargo.git",
+        ] {
+            assert_eq!(github_repo(url).unwrap(), "rust-lang/cargo");
+        }
+        assert!(github_repo("https://gitlab.com/rust-lang/cargo").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(message in "\\PC*(\r?\n\\PC*)*", bors in any::<bool>()) {
+            let author = if bors { "bors[bot]" } else { "bors" };
+            let output = format!(
+                "commit 9999999999999999999999999999999999999999\n\
+                 Author: {} <bors@rust-lang.org>\n\n{}",
+                author, message
+            );
+            let summaries = summaries(&output);
+            prop_assert!(!summaries.is_empty());
+        }
+    }
+
+    #[test]
+    fn diff() {
+        assert!(diff_lines("a\nb\n", "a\nb").is_empty());
+        assert_eq!(
+            diff_lines("1\n2\n3\n4\n5\n6\n7\n8\n", "1\n2\n3\n4\nfive\n6\n7\n8\n"),
+            ["...", " 3", " 4", "-5", "+five", " 6", " 7", "..."]
+        );
+        assert_eq!(diff_lines("a\n", "a\nb\n"), [" a", "+b"]);
+        assert_eq!(diff_lines("x\na\n", "a\n"), ["-x", " a"]);
+    }
+
+    #[test]
+    fn qualified_refs() {
+        assert_eq!(
+            qualify_refs("Fix #12 and #34, not a#5 or #x", "rust-lang/cargo"),
+            "Fix rust-lang/cargo#12 and rust-lang/cargo#34, not a#5 or #x"
+        );
+        assert_eq!(qualify_refs("#7", "rust-lang/cargo"), "rust-lang/cargo#7");
+    }
+
+    #[test]
+    fn full_list_links() {
+        let mut origin = Origin {
+            repo: "rust-lang/miri".to_string(),
+            rewritten: false,
+        };
+        assert_eq!(
+            full_list(&origin, "abc", "def"),
+            "See https://github.com/rust-lang/miri/compare/abc...def for the full list."
+        );
+        // Josh hashes are not in the upstream repository.
+        origin.rewritten = true;
+        assert_eq!(
+            full_list(&origin, "abc", "def"),
+            "See `git log abc..def` for the full list."
+        );
+    }
+
+    #[test]
+    fn pr_extras() {
+        let changelog = "2 commits in abc..def\n\
+                         2024-01-01 to 2024-01-02\n\
+                         - Fix a bug (rust-lang/cargo#1)\n\
+                         - Add a feature (rust-lang/cargo#2)\n";
+        assert_eq!(strip_pr_extras(changelog), changelog);
+        let body = format!(
+            "{}\nAdds workspace members `cargo-util`.\n\n\
+             @rustbot label +A-cargo\ncc @someone\nr? ghost\n\n\
+             By submitting this pull request, I certify that my contribution is made under\n\
+             the terms of the [Developer Certificate of Origin](https://developercertificate.org/).\n",
+            changelog
+        );
+        assert_eq!(strip_pr_extras(&body), changelog);
+        let collapsed = "## cargo\n\n\
+                         900 commits in abc..def\n2024-01-01 to 2024-01-02\n\n\
+                         See https://github.com/rust-lang/cargo/compare/abc...def for the full list.\n";
+        assert_eq!(
+            strip_pr_extras(&format!("{}\ncc @someone\n", collapsed)),
+            collapsed
+        );
+    }
 }
@@ -0,0 +1,140 @@
+//! The `rust-version` of updated tools, compared with the stage0 compiler
+//! that bootstrap builds them with.
+
+use anyhow::{Context, Error};
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+/// Parses a version such as `1.70` or `1.70.0` into its numbers, a missing
+/// patch version is 0.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim().splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = match parts.next() {
+        Some(patch) => patch.parse().ok()?,
+        None => 0,
+    };
+    Some((major, minor, patch))
+}
+
+/// The version of the stage0 compiler from the `src/stage0` file of the
+/// rust checkout at `dir`, or the older `src/stage0.json` or
+/// `src/stage0.txt`.
+///
+/// This may be `beta` in old checkouts, and is `None` if none of the files
+/// are there.
+pub fn stage0_version(dir: &Path) -> Result<Option<String>, Error> {
+    let read = |name: &str| -> Result<Option<String>, Error> {
+        let path = dir.join("src").join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        fs::read_to_string(&path)
+            .map(Some)
+            .with_context(|| format!("Failed to read {}", path.display()))
+    };
+    if let Some(text) = read("stage0")? {
+        return Ok(find_value(&text, "compiler_version="));
+    }
+    if let Some(text) = read("stage0.json")? {
+        let json: serde_json::Value =
+            serde_json::from_str(&text).context("Failed to parse src/stage0.json")?;
+        return Ok(json["compiler"]["version"].as_str().map(str::to_string));
+    }
+    if let Some(text) = read("stage0.txt")? {
+        return Ok(find_value(&text, "rustc:"));
+    }
+    Ok(None)
+}
+
+/// The value of the first line that starts with `key`.
+fn find_value(text: &str, key: &str) -> Option<String> {
+    text.lines()
+        .find_map(|line| line.trim().strip_prefix(key))
+        .map(|value| value.trim().to_string())
+}
+
+/// The `rust-version` of the package in `manifest`, looked up in the
+/// `[workspace.package]` of `workspace_manifest` when it is inherited.
+pub fn rust_version(
+    manifest: &str,
+    workspace_manifest: Option<&str>,
+) -> Result<Option<String>, Error> {
+    let manifest: Value = toml::from_str(manifest).context("Invalid Cargo.toml")?;
+    match manifest.get("package").and_then(|p| p.get("rust-version")) {
+        Some(Value::String(version)) => Ok(Some(version.clone())),
+        Some(Value::Table(t)) if t.get("workspace") == Some(&Value::Boolean(true)) => {
+            let workspace = match manifest.get("workspace") {
+                // The package is the root of its own workspace.
+                Some(_) => manifest.clone(),
+                None => match workspace_manifest {
+                    Some(text) => toml::from_str(text).context("Invalid workspace Cargo.toml")?,
+                    None => return Ok(None),
+                },
+            };
+            Ok(workspace
+                .get("workspace")
+                .and_then(|w| w.get("package"))
+                .and_then(|p| p.get("rust-version"))
+                .and_then(Value::as_str)
+                .map(str::to_string))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Whether a package with `rust_version` fails to build with the `stage0`
+/// compiler. Versions that cannot be compared (such as `beta`) are fine.
+pub fn is_too_new(rust_version: &str, stage0: &str) -> bool {
+    match (parse_version(rust_version), parse_version(stage0)) {
+        (Some(needed), Some(stage0)) => needed > stage0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(parse_version("1.70"), Some((1, 70, 0)));
+        assert_eq!(parse_version(" 1.79.1\n"), Some((1, 79, 1)));
+        assert_eq!(parse_version("beta"), None);
+        assert_eq!(parse_version("1"), None);
+        assert!(is_too_new("1.80", "1.79.0"));
+        assert!(is_too_new("1.79.1", "1.79.0"));
+        assert!(!is_too_new("1.79", "1.79.0"));
+        assert!(!is_too_new("1.80", "beta"));
+        assert_eq!(
+            find_value(
+                "dist_server=x\ncompiler_version=1.79.0\n",
+                "compiler_version="
+            ),
+            Some("1.79.0".to_string())
+        );
+        assert_eq!(
+            find_value("date: 2020-01-01\nrustc: beta\n", "rustc:"),
+            Some("beta".to_string())
+        );
+    }
+
+    #[test]
+    fn manifests() {
+        let direct = "[package]\nname = \"a\"\nrust-version = \"1.75\"\n";
+        assert_eq!(rust_version(direct, None).unwrap().unwrap(), "1.75");
+        let inherited = "[package]\nname = \"b\"\nrust-version.workspace = true\n";
+        let workspace = "[workspace]\n[workspace.package]\nrust-version = \"1.78\"\n";
+        assert_eq!(
+            rust_version(inherited, Some(workspace)).unwrap().unwrap(),
+            "1.78"
+        );
+        assert_eq!(rust_version(inherited, None).unwrap(), None);
+        let root = format!("{}{}", inherited, workspace);
+        assert_eq!(rust_version(&root, None).unwrap().unwrap(), "1.78");
+        assert_eq!(rust_version("[package]\nname = \"c\"", None).unwrap(), None);
+        assert!(rust_version("[package", None).is_err());
+    }
+}
@@ -0,0 +1,5764 @@
+//! The `subup` command: the update pipeline and the other subcommands.
+
+use crate::cli::{
+    color_arg, isolate_git_arg, push_remote_arg, upstream_remote_arg, Cli, TestsFailed,
+};
+use crate::event::{Event, JsonLines};
+use crate::forge::{self, Forge, PullRequest};
+use crate::github::{CiStatus, GitHubApi, PendingCiStatus};
+use crate::plan::{self, Step};
+use crate::remote::{RemoteUrl, Scheme};
+use crate::runner::Spawned;
+use crate::spec::{
+    expand_submodule_args, parse_blocked_by, parse_submodule_spec, resolve_submodule_path,
+    submodule_candidates, Blocker,
+};
+use crate::{credentials, lock, log, msrv, profile, repo, schedule, title, triagebot};
+use anyhow::{bail, format_err, Context, Error};
+use cargo_metadata::{Metadata, Package, PackageId};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// Release tags, such as `0.1.2` or `v0.1.2`.
+    static ref SEMVER_RE: Regex = Regex::new(r"^v?[0-9]+\.[0-9]+\.[0-9]+$").unwrap();
+}
+
+/// Submodules that the compiler itself builds against.
+const COMPILER_SUBMODULES: &[&str] = &["src/llvm-project", "library/backtrace", "library/stdarch"];
+
+/// What `--books` expands to, every documentation book submodule.
+const BOOKS_PATTERN: &str = "src/doc/*";
+
+/// Checks the links in the books, run when a book is updated. This covers
+/// all of the generated docs, including the error index.
+const LINKCHECK_TEST: &str = "src/tools/linkchecker";
+
+/// Appended to the PR body with `--signoff-dco`.
+const DCO_ATTESTATION: &str = "
+By submitting this pull request, I certify that my contribution is made under
+the terms of the [Developer Certificate of Origin](https://developercertificate.org/).
+";
+
+/// Where the progress of an update is saved, for `--resume`.
+const STATE_PATH: &str = ".subup-state.json";
+
+/// Cargo workspace member.
+#[derive(Debug, Hash, Eq, PartialEq)]
+struct Member {
+    name: String,
+    version: String,
+    /// Absolute path to a workspace member.
+    path: PathBuf,
+    /// Absolute path to the root of the member's workspace, where its
+    /// `Cargo.lock` is.
+    workspace_root: PathBuf,
+}
+
+/// How a tool's source is kept in the rust repo.
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+enum SourceKind {
+    /// A git submodule.
+    #[default]
+    Submodule,
+    /// A subtree synced with josh from the `owner/name` GitHub repository.
+    Josh { repo: String },
+}
+
+/// Git submodule, or a subtree updated like one.
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+struct Submodule {
+    /// Name of the submodule in `.gitmodules`.
+    name: String,
+    /// Relative path to the submodule.
+    path: String,
+    #[serde(default)]
+    kind: SourceKind,
+    /// The branch or revision it should update to.
+    rev: String,
+    /// True if the submodule was listed on command line.
+    wants_update: bool,
+    /// This is set to `true` if the submodule was updated and new changes
+    /// were found.
+    was_updated: bool,
+    /// The original git hash for the submodule before updating. For a josh
+    /// subtree, this is the last synced commit, as rewritten by josh.
+    original_hash: String,
+    /// The git hash the submodule is updated to, set once the update is
+    /// checked.
+    new_hash: Option<String>,
+    /// Set if the rev is an ancestor of `original_hash`, an update that goes
+    /// back in history, with `--allow-downgrade`.
+    #[serde(default)]
+    downgraded: bool,
+    /// Cargo workspace members found within this submodule.
+    #[serde(skip)]
+    members: Vec<Member>,
+}
+
+struct SubUp<'a> {
+    cli: &'a Cli<'a>,
+    /// Submodules to update, as given on the command line
+    /// (`[branch:]path`).
+    submodule_args: Vec<String>,
+    /// All submodules in the repo.
+    submodules: Vec<Submodule>,
+    /// The target branch of the rust repo (master/beta/stable)
+    rust_branch: String,
+    /// The branch name to create for the pull request.
+    up_branch: String,
+    /// Metadata of workspace before updating submodules.
+    /// None until after base branch is updated.
+    orig_metadata: Option<Metadata>,
+    /// Title of the commit and PR.
+    commit_title: Option<String>,
+    /// Results collected for the final summary.
+    report: Report,
+    /// Extra notes for the commit and PR body.
+    notes: Vec<String>,
+    /// Set if `.gitmodules` was changed and needs to be committed.
+    gitmodules_changed: bool,
+    /// `cargo metadata` started after the update, finished in `update_lock`.
+    pending_metadata: Option<Spawned>,
+    /// CI status queries `(path, hash, status)` started in `check_ci`,
+    /// reported after the tests.
+    pending_ci: Vec<(String, String, PendingCiStatus)>,
+    /// Names of the steps that have finished, including those from a
+    /// previous run with `--resume`.
+    completed: Vec<String>,
+    /// Paths of in-tree tools that depend on packages changed in
+    /// Cargo.lock, tested along with the updated submodules.
+    affected_tests: Vec<String>,
+    /// Redoing the update of an existing branch with `subup refresh`, the
+    /// branch is recreated and pushed with `--force-with-lease`.
+    refresh: bool,
+    /// The remote of the user's fork found by `check_push_remote`, when
+    /// `origin` is not a fork.
+    push_remote: Option<String>,
+    /// The `x.py test` paths that failed, offered to be re-run with
+    /// `--resume`.
+    failed_tests: Vec<String>,
+}
+
+/// The progress of an update, saved to `STATE_PATH` after each step.
+#[derive(Serialize, Deserialize)]
+struct State {
+    submodule_args: Vec<String>,
+    rust_branch: String,
+    up_branch: String,
+    completed: Vec<String>,
+    submodules: Vec<Submodule>,
+    commit_title: Option<String>,
+    notes: Vec<String>,
+    gitmodules_changed: bool,
+    lock_changed: bool,
+    lock_packages: Vec<String>,
+    tests: Option<Vec<String>>,
+    #[serde(default)]
+    affected_tests: Vec<String>,
+    #[serde(default)]
+    refresh: bool,
+    #[serde(default)]
+    push_remote: Option<String>,
+    #[serde(default)]
+    failed_tests: Vec<String>,
+}
+
+/// Results of the run, shown in the final summary.
+#[derive(Default)]
+struct Report {
+    /// Whether or not Cargo.lock changed.
+    lock_changed: bool,
+    /// Names of packages added, removed, or changed in Cargo.lock.
+    lock_packages: Vec<String>,
+    /// Tests that passed, `None` if tests were skipped.
+    tests: Option<Vec<String>>,
+    /// Hash of the new commit, `None` if the commit was skipped.
+    commit_hash: Option<String>,
+    /// URL of the created PR, or the URL to create it manually.
+    pr_url: Option<String>,
+    /// How long each step took.
+    timings: Vec<(&'static str, Duration)>,
+    /// How long the entire run took.
+    total: Duration,
+}
+
+impl<'a> SubUp<'a> {
+    /// Git submodules to update. Josh subtrees are pulled separately by
+    /// `pull_subtrees`.
+    fn submodules_to_up(&self) -> impl Iterator<Item = &Submodule> {
+        self.submodules
+            .iter()
+            .filter(|s| s.wants_update && s.kind == SourceKind::Submodule)
+    }
+
+    fn updated_submodules(&self) -> impl Iterator<Item = &Submodule> {
+        self.submodules.iter().filter(|s| s.was_updated)
+    }
+
+    fn has_changes(&self, path: &str) -> Result<bool, Error> {
+        repo::has_changes(".", path)
+    }
+
+    /// The paths of the submodules given on the command line, resolved
+    /// against the `.gitmodules` `entries` where possible.
+    fn updating_paths(&self, entries: &[(String, String)]) -> Result<Vec<String>, Error> {
+        let mut updating = Vec::new();
+        for arg in &self.submodule_args {
+            let spec = parse_submodule_spec(arg)?;
+            updating.push(
+                resolve_submodule_path(&spec.path, entries)
+                    .map(|path| path.to_string())
+                    .unwrap_or(spec.path),
+            );
+        }
+        Ok(updating)
+    }
+
+    /// Checks for changes in the working tree, ignoring dirty content in
+    /// submodules excluded with `--no-submodule-dirty-check`.
+    fn has_tree_changes(&self) -> Result<bool, Error> {
+        if !self.cli.matches.is_present("no-submodule-dirty-check") {
+            return self.has_changes(".");
+        }
+        // With no paths, every submodule not being updated is excluded.
+        let excluded: Vec<&str> = self
+            .cli
+            .matches
+            .values_of("no-submodule-dirty-check")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let entries = repo::submodule_entries(".")?;
+        let updating = self.updating_paths(&entries)?;
+        let mut args = Vec::new();
+        for (name, path) in entries {
+            let is_excluded = if excluded.is_empty() {
+                !updating.contains(&path)
+            } else {
+                excluded.contains(&path.as_str())
+            };
+            if is_excluded {
+                args.push("-c".to_string());
+                args.push(format!("submodule.{}.ignore=dirty", name));
+            }
+        }
+        args.extend(
+            ["diff-index", "--quiet", "HEAD", "."]
+                .iter()
+                .map(|s| s.to_string()),
+        );
+        Ok(!self
+            .cli
+            .git_cmd(&args)
+            .status("Failed to check for changes.")?
+            .success())
+    }
+
+    fn update_submodules_base(&self) -> Result<(), Error> {
+        self.cli.status("Updating submodules to base.")?;
+        self.cli
+            .git("submodule update --init --recursive")
+            .run("Failed to init/update submodules.")?;
+        Ok(())
+    }
+
+    fn check_environment(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking working tree.")?;
+        if !Path::new(".git").exists() {
+            bail!(".git not found, are you in the root directory?");
+        }
+        // Make sure index is set up (otherwise diff-index compares against
+        // all zero hashes).
+        self.cli
+            .git("update-index -q --refresh")
+            .run("Failed to update-index.")?;
+
+        // Check for changes.
+        if self.has_tree_changes()? {
+            // TODO: This will probably have problems. It would be nice to
+            // support it.
+            self.cli.warning("Working tree has changes.")?;
+            self.cli
+                .git("status --porcelain")
+                .read_only()
+                .run("Failed to get git status.")?;
+            if !self.cli.matches.is_present("allow-changes") {
+                let reset = || -> Result<(), Error> {
+                    self.cli.git("reset --hard").run("Failed to reset.")?;
+                    Ok(())
+                };
+                if self.cli.matches.is_present("always-reset") {
+                    reset()?;
+                } else {
+                    let choice = self.cli.select(
+                        "How do you wish to proceed?",
+                        &["Abort", "Reset changes", "Continue with changes"],
+                        Some(0),
+                    )?;
+                    match choice {
+                        None | Some(0) => self.cli.exit_err(),
+                        Some(1) => reset()?,
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+
+        // Check rust_branch.
+        if self.rust_branch == "master" {
+            self.cli.info("Using branch `master`")?;
+        } else {
+            self.cli
+                .warning(&format!("Using non-master branch `{}`", self.rust_branch))?;
+        }
+
+        // Check upstream.
+        let remote = self.cli.upstream_remote();
+        let has_upstream = self
+            .cli
+            .git_cmd(["config", &format!("remote.{}.url", remote)])
+            .status("Failed to get upstream url.")?
+            .success();
+        if !has_upstream {
+            if self.cli.is_interactive() {
+                self.cli
+                    .warning(&format!("`{}` is not configured.", remote))?;
+                let upstream = self
+                    .cli
+                    .input(
+                        "Please enter the upstream url",
+                        Some("https://github.com/rust-lang/rust.git"),
+                    )?
+                    .unwrap();
+                self.cli
+                    .git_cmd(["remote", "add", remote, &upstream])
+                    .run("Failed to add upstream.")?;
+            } else {
+                bail!("`{}` remote is not configured.", remote);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_hash(&self, rev: &str, path: &str) -> Result<String, Error> {
+        repo::hash(path, rev)
+    }
+
+    fn check_args(&mut self) -> Result<(), Error> {
+        for spec in self
+            .cli
+            .matches
+            .values_of("pr-snippet")
+            .into_iter()
+            .flatten()
+        {
+            triagebot::Snippet::parse(spec)?;
+        }
+        test_env(self.cli, &[])?;
+        self.cli.status("Checking module names.")?;
+        // Get information about every submodule, and the Cargo workspace
+        // members it has.
+        let gitlinks = repo::gitlink_paths(".")?;
+        let mut inconsistent = HashMap::new();
+        let entries = repo::submodule_entries(".")?;
+        for (_name, path) in &entries {
+            if !gitlinks.contains(path) {
+                inconsistent.insert(
+                    path.clone(),
+                    "is listed in .gitmodules, but is not a submodule in HEAD \
+                     (it may have been converted to a subtree). Update it with \
+                     its subtree tooling, or remove the stale .gitmodules entry.",
+                );
+            }
+        }
+        for path in &gitlinks {
+            if !entries.iter().any(|(_name, p)| p == path) {
+                inconsistent.insert(
+                    path.clone(),
+                    "is a submodule in HEAD, but is not listed in .gitmodules \
+                     (it may have been partially removed). Fix .gitmodules \
+                     before updating it.",
+                );
+            }
+        }
+        if self.cli.is_verbose() {
+            for (path, reason) in &inconsistent {
+                self.cli.info(&format!("`{}` {}", path, reason))?;
+            }
+        }
+        for (name, path) in &entries {
+            if inconsistent.contains_key(path) {
+                continue;
+            }
+            let path = path.as_str();
+            let members = SubUp::compute_members(self.orig_metadata.as_ref().unwrap(), path)?;
+            let original_hash = repo::head_gitlink(".", path)?;
+            let submodule = Submodule {
+                name: name.clone(),
+                path: path.to_string(),
+                kind: SourceKind::Submodule,
+                rev: "HEAD".to_string(), // Will set below.
+                wants_update: false,     // Will set below.
+                was_updated: false,
+                original_hash,
+                new_hash: None,
+                downgraded: false,
+                members,
+            };
+            self.submodules.push(submodule);
+        }
+        let subtrees = josh_subtrees(self.cli)?;
+        // Check user arguments.
+        for arg in &self.submodule_args {
+            let spec = parse_submodule_spec(arg)?;
+            if let Some(repo) = subtrees.get(&spec.path) {
+                self.submodules.push(Submodule {
+                    name: spec.path.clone(),
+                    path: spec.path.clone(),
+                    kind: SourceKind::Josh { repo: repo.clone() },
+                    rev: spec.rev.unwrap_or_else(|| "HEAD".to_string()),
+                    wants_update: true,
+                    was_updated: false,
+                    // Set by `pull_subtrees`.
+                    original_hash: String::new(),
+                    new_hash: None,
+                    downgraded: false,
+                    members: SubUp::compute_members(
+                        self.orig_metadata.as_ref().unwrap(),
+                        &spec.path,
+                    )?,
+                });
+                continue;
+            }
+            if let Some(reason) = inconsistent.get(&spec.path) {
+                bail!("Refusing to update `{}`: it {}", spec.path, reason);
+            }
+            let path = pick_submodule_path(self.cli, &spec.path, &entries)?;
+            let rev = match spec.rev {
+                Some(rev) => rev,
+                None if self.rust_branch != "master" => {
+                    self.cli
+                        .warning(&format!("Did not specify a branch for module `{}`.", path))?;
+                    self.cli
+                        .input(
+                            &format!("Which branch or revision should `{}` use?", path),
+                            None,
+                        )?
+                        .ok_or_else(|| {
+                            format_err!("You must specify a branch or rev for module `{}`", path)
+                        })?
+                }
+                None => "HEAD".to_string(),
+            };
+            if let Some(reason) = inconsistent.get(&path) {
+                bail!("Refusing to update `{}`: it {}", path, reason);
+            }
+            let submodule = self
+                .submodules
+                .iter_mut()
+                .find(|submodule| submodule.path == path)
+                .ok_or_else(|| {
+                    format_err!("Could not find submodule `{}` in git modules.", path)
+                })?;
+            submodule.rev = rev;
+            submodule.wants_update = true;
+        }
+        Ok(())
+    }
+
+    /// Makes sure the submodules to update are in the sparse checkout,
+    /// offering to add them.
+    fn check_sparse_checkout(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking sparse checkout.")?;
+        let paths: Vec<&str> = self
+            .submodules_to_up()
+            .map(|submodule| submodule.path.as_str())
+            .collect();
+        let missing = repo::outside_sparse_checkout(".", &paths)?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        self.cli.warning(&format!(
+            "These submodules are outside of the sparse checkout: {}",
+            missing.join(", ")
+        ))?;
+        if !self.cli.confirm("Add them to the sparse checkout?", true)? {
+            bail!("Submodules to update must be in the sparse checkout, aborting...");
+        }
+        self.cli
+            .git_cmd(["sparse-checkout", "add"].iter().chain(&missing))
+            .run("Failed to add to the sparse checkout.")?;
+        self.cli
+            .git_cmd(
+                ["submodule", "update", "--init", "--recursive", "--"]
+                    .iter()
+                    .chain(&missing),
+            )
+            .run("Failed to init submodules.")?;
+        Ok(())
+    }
+
+    /// Reports the `.gitmodules` settings of the submodules to update that
+    /// change how they are checked out, and checks out the ones with
+    /// `update = none`.
+    fn check_submodule_settings(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking submodule settings.")?;
+        for submodule in self.submodules_to_up() {
+            if submodule.kind != SourceKind::Submodule {
+                continue;
+            }
+            let update = repo::submodule_setting(".", &submodule.name, "update")?;
+            self.check_update_setting(submodule, update.as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Handles the `update` setting of `submodule` in `.gitmodules`.
+    fn check_update_setting(
+        &self,
+        submodule: &Submodule,
+        update: Option<&str>,
+    ) -> Result<(), Error> {
+        match update {
+            None | Some("checkout") => {}
+            Some("none") => {
+                self.cli.warning(&format!(
+                    "`{}` has `update = none` in .gitmodules, so \
+                     `git submodule update` leaves it alone, it will be \
+                     checked out to update it.",
+                    submodule.path
+                ))?;
+                if repo::worktree_head(&submodule.path).is_err() {
+                    self.cli
+                        .git_cmd([
+                            "submodule",
+                            "update",
+                            "--init",
+                            "--checkout",
+                            "--",
+                            &submodule.path,
+                        ])
+                        .run(format!("Failed to check out `{}`.", submodule.path))?;
+                }
+            }
+            Some(update) => self.cli.warning(&format!(
+                "`{}` has `update = {}` in .gitmodules, subup updates it \
+                 with a checkout.",
+                submodule.path, update
+            ))?,
+        }
+        Ok(())
+    }
+
+    /// Checks the `--blocked-by` PRs of the submodules to update, before
+    /// anything is changed.
+    fn check_blockers(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking for blocking PRs.")?;
+        let entries = repo::submodule_entries(".")?;
+        let updating = self.updating_paths(&entries)?;
+        let api = GitHubApi::new(self.cli)?;
+        let mut open = Vec::new();
+        for value in self
+            .cli
+            .matches
+            .values_of("blocked-by")
+            .into_iter()
+            .flatten()
+        {
+            let blocked_by = parse_blocked_by(value)?;
+            let submodule = if blocked_by.submodule == "*" {
+                "*"
+            } else {
+                resolve_submodule_path(&blocked_by.submodule, &entries)?
+            };
+            if submodule != "*" && !updating.iter().any(|path| path == submodule) {
+                continue;
+            }
+            match blocked_by.blocker {
+                Blocker::Prs(prs) => {
+                    for (repo, number) in prs {
+                        let (is_open, title) = api.pr_state(&repo, number)?;
+                        if is_open {
+                            open.push(format!("{}: {}#{} {}", submodule, repo, number, title));
+                        }
+                    }
+                }
+                Blocker::Search(query) => {
+                    for (url, title) in api.search_open_prs(&query)? {
+                        open.push(format!("{}: {} {}", submodule, url, title));
+                    }
+                }
+            }
+        }
+        if open.is_empty() {
+            return Ok(());
+        }
+        self.cli.boxed("Blocking PRs are open", &open)?;
+        if !self.cli.confirm("Continue anyway?", false)? {
+            bail!("Updates are blocked by open PRs, aborting...");
+        }
+        Ok(())
+    }
+
+    fn fetch_submodules(&self) -> Result<(), Error> {
+        self.cli.status("Fetching submodules.")?;
+        // TODO: This may not be necessary after `submodule update`?
+        // The fetches run in parallel (up to `--jobs`), they mostly wait on
+        // the network.
+        let to_fetch: Vec<&Submodule> = self.submodules_to_up().collect();
+        let total = to_fetch.len();
+        let jobs = jobs(self.cli)?;
+        let finish = |(submodule, fetch): (&Submodule, Spawned), done: usize| {
+            fetch.wait()?;
+            self.cli
+                .git("remote set-head origin -a")
+                .dir(&submodule.path)
+                .quiet()
+                .read_only()
+                .run(format!(
+                    "Failed to set-head in module `{}`.",
+                    submodule.path
+                ))?;
+            self.cli
+                .progress(done, total, &format!("Fetched `{}`.", submodule.path))
+        };
+        let mut fetches = VecDeque::new();
+        let mut done = 0;
+        for submodule in to_fetch {
+            if fetches.len() == jobs {
+                done += 1;
+                finish(fetches.pop_front().unwrap(), done)?;
+            }
+            let mut args = vec!["fetch".to_string(), "--tags".to_string()];
+            if repo::is_shallow(&submodule.path)? {
+                if repo::is_shallow_submodule(".", &submodule.name)? {
+                    // Only the new commits are needed for the log, the full
+                    // history of these (such as LLVM) is very large.
+                    self.cli.info(&format!(
+                        "`{}` is shallow in .gitmodules, fetching the commits after {}.",
+                        submodule.path, submodule.original_hash
+                    ))?;
+                    args.push(format!("--shallow-exclude={}", submodule.original_hash));
+                } else {
+                    self.cli.status(&format!(
+                        "Found shallow submodule `{}`, unshallowing.",
+                        submodule.path
+                    ))?;
+                    args.push("--unshallow".to_string());
+                }
+            }
+            let fetch = self
+                .cli
+                .git_cmd(&args)
+                .dir(&submodule.path)
+                .spawn(format!("Failed to fetch in module `{}`.", submodule.path))?;
+            fetches.push_back((submodule, fetch));
+        }
+        for fetch in fetches {
+            done += 1;
+            finish(fetch, done)?;
+        }
+        Ok(())
+    }
+
+    fn check_submodule_rev(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking submodule revs.")?;
+        let mut to_change = HashMap::new();
+        for submodule in self.submodules_to_up() {
+            if let Some(rev) = self.resolve_special_rev(submodule)? {
+                to_change.insert(submodule.path.clone(), rev);
+                continue;
+            }
+            // Verify the rev name is correct.
+            let origin = format!("origin/{}", submodule.rev);
+            if self.get_hash(&origin, &submodule.path).is_ok() {
+                to_change.insert(submodule.path.clone(), origin);
+            } else {
+                self.get_hash(&submodule.rev, &submodule.path)?;
+            }
+        }
+        for (path, rev) in to_change {
+            let submodule = self
+                .submodules
+                .iter_mut()
+                .find(|submodule| submodule.path == path)
+                .ok_or_else(|| {
+                    format_err!("Could not find submodule `{}` in git modules.", path)
+                })?;
+            submodule.rev = rev;
+        }
+        for submodule in self.submodules_to_up() {
+            // Branches of origin are official by definition.
+            if submodule.rev.starts_with("origin/") {
+                continue;
+            }
+            let official = match repo::submodule_branch(".", &submodule.name)? {
+                Some(branch) => format!("origin/{}", branch),
+                None => "origin/HEAD".to_string(),
+            };
+            if let Some(warning) = self.unofficial_rev_warning(submodule, &official)? {
+                self.cli.warning(&warning)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the rev of `submodule` is on the `official` branch, to
+    /// catch commits from a fork, or ones that were force-pushed away.
+    ///
+    /// Beta and stable updates usually come from release branches, so there
+    /// it only needs to be on some branch of origin.
+    fn unofficial_rev_warning(
+        &self,
+        submodule: &Submodule,
+        official: &str,
+    ) -> Result<Option<String>, Error> {
+        let is_ancestor = self
+            .cli
+            .git_cmd(["merge-base", "--is-ancestor", &submodule.rev, official])
+            .dir(&submodule.path)
+            .status(format!(
+                "Failed to check the ancestry of `{}` in module `{}`.",
+                submodule.rev, submodule.path
+            ))?
+            .success();
+        if is_ancestor {
+            return Ok(None);
+        }
+        let branches = self
+            .cli
+            .git_cmd([
+                "branch",
+                "--remotes",
+                "--format=%(refname:short)",
+                "--contains",
+                &submodule.rev,
+                "origin/*",
+            ])
+            .dir(&submodule.path)
+            .capture_stdout(format!(
+                "Failed to find the branches with `{}` in module `{}`.",
+                submodule.rev, submodule.path
+            ))?;
+        let branches: Vec<&str> = branches.lines().collect();
+        if branches.is_empty() {
+            Ok(Some(format!(
+                "`{}` of `{}` is not on `{}` or any other branch of origin! \
+                 It may be from a fork, or a commit that was force-pushed away.",
+                submodule.rev, submodule.path, official
+            )))
+        } else if self.rust_branch == "master" {
+            Ok(Some(format!(
+                "`{}` of `{}` is not on `{}`, only on {}.",
+                submodule.rev,
+                submodule.path,
+                official,
+                branches.join(", ")
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// On beta/stable, offers to use a release tag instead of the tip of the
+    /// chosen branch.
+    fn choose_release_tags(&mut self) -> Result<(), Error> {
+        let picks = cherry_picks(self.cli)?;
+        let mut to_change = HashMap::new();
+        for submodule in self
+            .submodules_to_up()
+            .filter(|submodule| !picks.contains_key(&submodule.path))
+        {
+            let output = self
+                .cli
+                .git_cmd(["tag", "--merged", &submodule.rev, "--sort=-v:refname"])
+                .dir(&submodule.path)
+                .capture_stdout(format!(
+                    "Failed to list tags in module `{}`.",
+                    submodule.path
+                ))?;
+            let tags: Vec<&str> = output
+                .lines()
+                .filter(|tag| SEMVER_RE.is_match(tag))
+                .take(10)
+                .collect();
+            if tags.is_empty() {
+                continue;
+            }
+            let tip = format!("{} (branch tip)", submodule.rev);
+            let mut items = vec![tip.as_str()];
+            items.extend(&tags);
+            let choice = self.cli.select(
+                &format!("Which revision should `{}` use?", submodule.path),
+                &items,
+                Some(0),
+            )?;
+            if let Some(i) = choice.filter(|i| *i > 0) {
+                to_change.insert(submodule.path.clone(), tags[i - 1].to_string());
+            }
+        }
+        for submodule in &mut self.submodules {
+            if let Some(tag) = to_change.remove(&submodule.path) {
+                submodule.rev = tag;
+            }
+        }
+        Ok(())
+    }
+
+    /// With `--cherry-pick`, backports the given commits onto the branch of
+    /// each submodule, pushes it, and updates to the result.
+    fn cherry_pick(&mut self) -> Result<(), Error> {
+        let picks = cherry_picks(self.cli)?;
+        let mut to_change = HashMap::new();
+        for (path, commits) in &picks {
+            let submodule = self
+                .submodules_to_up()
+                .find(|submodule| &submodule.path == path)
+                .ok_or_else(|| {
+                    format_err!(
+                        "--cherry-pick was given for `{}`, which is not being updated.",
+                        path
+                    )
+                })?;
+            let branch = submodule
+                .rev
+                .strip_prefix("origin/")
+                .filter(|branch| *branch != "HEAD")
+                .ok_or_else(|| {
+                    format_err!(
+                        "Cherry-picks for `{}` need a branch to backport to, such as \
+                     `rust-1.50.0:{}`, not `{}`.",
+                        path,
+                        path,
+                        submodule.rev
+                    )
+                })?;
+            for commit in commits {
+                self.get_hash(&format!("{}^{{commit}}", commit), path)
+                    .with_context(|| format!("Commit `{}` not found in `{}`.", commit, path))?;
+            }
+            self.cli.status(&format!(
+                "Cherry-picking {} onto `{}` in `{}`.",
+                commits.join(" "),
+                branch,
+                path
+            ))?;
+            self.cli
+                .git_cmd(["checkout", "-B", branch, &submodule.rev])
+                .dir(path)
+                .run(format!(
+                    "Failed to create branch `{}` in `{}`.",
+                    branch, path
+                ))?;
+            self.cli
+                .git_cmd(
+                    ["cherry-pick", "-x"]
+                        .iter()
+                        .copied()
+                        .chain(commits.iter().map(String::as_str)),
+                )
+                .dir(path)
+                .run(format!(
+                    "Failed to cherry-pick in `{}`, resolve the conflicts there and \
+                     push `{}`, or run `git cherry-pick --abort`.",
+                    path, branch
+                ))?;
+            if self
+                .cli
+                .confirm_remote(&format!("push the backport to `{}` of `{}`", branch, path))?
+            {
+                self.cli
+                    .git_cmd(["push", "origin", &format!("HEAD:refs/heads/{}", branch)])
+                    .dir(path)
+                    .remote()
+                    .run(format!("Failed to push `{}` in `{}`.", branch, path))?;
+            } else {
+                self.cli.warning(&format!(
+                    "The backport to `{}` of `{}` was not pushed, push it before the PR is merged.",
+                    branch, path
+                ))?;
+            }
+            self.notes.push(format!(
+                "Backports {} to `{}` of `{}`.",
+                commits.join(", "),
+                branch,
+                path
+            ));
+            if !self.cli.is_dry_run() {
+                to_change.insert(path.clone(), self.get_hash("HEAD", path)?);
+            }
+        }
+        for submodule in &mut self.submodules {
+            if let Some(hash) = to_change.remove(&submodule.path) {
+                submodule.rev = hash;
+            }
+        }
+        Ok(())
+    }
+
+    /// With `--since` or `--max-commits`, moves each rev back to the newest
+    /// commit within the limits.
+    fn apply_limits(&mut self) -> Result<(), Error> {
+        let since = self.cli.matches.value_of("since");
+        let max_commits = match self.cli.matches.value_of("max-commits") {
+            Some(max) => match max.parse::<usize>() {
+                Ok(0) => bail!("--max-commits must be at least 1"),
+                Ok(max) => Some(max),
+                Err(_) => bail!("Invalid --max-commits `{}`, expected a number", max),
+            },
+            None => None,
+        };
+        if since.is_none() && max_commits.is_none() {
+            return Ok(());
+        }
+        let mut limits = Vec::new();
+        if let Some(since) = since {
+            limits.push(format!("commits made before {}", since));
+        }
+        match max_commits {
+            Some(1) => limits.push("at most 1 commit".to_string()),
+            Some(max) => limits.push(format!("at most {} commits", max)),
+            None => {}
+        }
+        let limits = limits.join(" and ");
+        let mut to_change = HashMap::new();
+        let mut notes = Vec::new();
+        for submodule in self.submodules_to_up() {
+            let mut args = vec!["rev-list".to_string(), "--first-parent".to_string()];
+            if let Some(since) = since {
+                args.push(format!("--before={}", since));
+            }
+            args.push(submodule.rev.clone());
+            args.push(format!("^{}", submodule.original_hash));
+            let output = self
+                .cli
+                .git_cmd(&args)
+                .dir(&submodule.path)
+                .capture_stdout(format!(
+                    "Failed to list commits in module `{}`.",
+                    submodule.path
+                ))?;
+            // Newest first.
+            let commits: Vec<&str> = output.lines().collect();
+            let target = match max_commits {
+                Some(max) if commits.len() > max => commits[commits.len() - max],
+                _ => match commits.first() {
+                    Some(hash) => hash,
+                    None => submodule.original_hash.as_str(),
+                },
+            };
+            let note = format!("`{}` was limited to {}.", submodule.path, limits);
+            self.cli.info(&note)?;
+            notes.push(note);
+            to_change.insert(submodule.path.clone(), target.to_string());
+        }
+        for submodule in &mut self.submodules {
+            if let Some(rev) = to_change.remove(&submodule.path) {
+                submodule.rev = rev;
+            }
+        }
+        self.notes.extend(notes);
+        Ok(())
+    }
+
+    /// With `--latest-green`, moves each rev back along its first-parent
+    /// history to the newest commit with passing CI.
+    fn pick_latest_green(&mut self) -> Result<(), Error> {
+        self.cli.status("Finding latest commits with passing CI.")?;
+        let mut api = None;
+        let mut to_change = HashMap::new();
+        // Only git submodules, josh subtrees have no CI status to check.
+        for submodule in self.submodules_to_up() {
+            // Don't go further back than the current commit.
+            let output = self
+                .cli
+                .git_cmd([
+                    "rev-list",
+                    "--first-parent",
+                    "--max-count=50",
+                    &submodule.rev,
+                    &format!("^{}", submodule.original_hash),
+                ])
+                .dir(&submodule.path)
+                .capture_stdout(format!(
+                    "Failed to list commits in module `{}`.",
+                    submodule.path
+                ))?;
+            if output.is_empty() {
+                // Nothing new, `check_for_updates` reports it.
+                continue;
+            }
+            let repo = log::git_origin(self.cli, &submodule.path)?;
+            let api = match &mut api {
+                Some(api) => api,
+                None => api.insert(GitHubApi::new(self.cli)?),
+            };
+            let mut skipped = 0;
+            let mut green = None;
+            for hash in output.lines() {
+                if api.ci_status(&repo, hash)? == CiStatus::Success {
+                    green = Some(hash.to_string());
+                    break;
+                }
+                skipped += 1;
+            }
+            let green = green.ok_or_else(|| {
+                format_err!(
+                    "No commit with passing CI found in module `{}` after {} commits.",
+                    submodule.path,
+                    skipped
+                )
+            })?;
+            self.cli.info(&format!(
+                "Using `{}` for module `{}`, skipped {} newer commits without passing CI.",
+                green, submodule.path, skipped
+            ))?;
+            to_change.insert(submodule.path.clone(), green);
+        }
+        for submodule in &mut self.submodules {
+            if let Some(rev) = to_change.remove(&submodule.path) {
+                submodule.rev = rev;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves revs that git does not understand directly:
+    ///
+    /// * `@{DATE}`: the last commit on the default branch before the date.
+    /// * `latest-release-tag`: the most recent tag on the default branch.
+    fn resolve_special_rev(&self, submodule: &Submodule) -> Result<Option<String>, Error> {
+        let rev = submodule.rev.as_str();
+        let resolved = if rev == "latest-release-tag" {
+            self.cli
+                .git("describe --tags --abbrev=0 origin/HEAD")
+                .dir(&submodule.path)
+                .capture_stdout(format!(
+                    "Failed to find the latest tag in module `{}`.",
+                    submodule.path
+                ))?
+        } else if let Some(date) = rev
+            .strip_prefix("@{")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .filter(|date| !["upstream", "u", "push"].contains(date))
+        {
+            let before = format!("--before={}", date);
+            let hash = self
+                .cli
+                .git_cmd(["rev-list", "-1", "--first-parent", &before, "origin/HEAD"])
+                .dir(&submodule.path)
+                .capture_stdout(format!(
+                    "Failed to find commit before `{}` in module `{}`.",
+                    date, submodule.path
+                ))?;
+            if hash.is_empty() {
+                bail!(
+                    "No commit before `{}` in module `{}`.",
+                    date,
+                    submodule.path
+                );
+            }
+            hash
+        } else {
+            return Ok(None);
+        };
+        self.cli.info(&format!(
+            "Resolved `{}` to `{}` in module `{}`.",
+            rev, resolved, submodule.path
+        ))?;
+        Ok(Some(resolved))
+    }
+
+    /// Fetches josh subtrees through the josh proxy, and merges them without
+    /// committing. The merge is concluded by `commit`.
+    fn pull_subtrees(&mut self) -> Result<(), Error> {
+        let proxy = self.cli.matches.value_of("josh-proxy").unwrap();
+        let mut pulled = Vec::new();
+        for submodule in self.submodules.iter().filter(|s| s.wants_update) {
+            let repo = match &submodule.kind {
+                SourceKind::Josh { repo } => repo,
+                SourceKind::Submodule => continue,
+            };
+            self.cli.status(&format!(
+                "Pulling `{}` from {} with josh.",
+                submodule.path, repo
+            ))?;
+            // Josh rewrites the history as if it were always in the subtree.
+            let url = format!(
+                "{}/{}.git:prefix={}.git",
+                proxy.trim_end_matches('/'),
+                repo,
+                submodule.path
+            );
+            self.cli
+                .git_cmd(["fetch", &url, &submodule.rev])
+                .read_only()
+                .run(format!("Failed to fetch `{}` from josh.", submodule.path))?;
+            let new_hash = repo::hash(".", "FETCH_HEAD")?;
+            let base = self
+                .cli
+                .git_cmd(["merge-base", "HEAD", &new_hash])
+                .capture_stdout(format!(
+                    "`{}` has no history in common with {}, it must be synced with josh \
+                     before subup can update it.",
+                    submodule.path, repo
+                ))?;
+            pulled.push((submodule.path.clone(), base, new_hash));
+        }
+        let mut to_merge = Vec::new();
+        for (path, base, new_hash) in pulled {
+            let submodule = self
+                .submodules
+                .iter_mut()
+                .find(|submodule| submodule.path == path)
+                .unwrap();
+            submodule.was_updated = base != new_hash;
+            if submodule.was_updated {
+                to_merge.push(new_hash.clone());
+            } else {
+                self.cli
+                    .warning(&format!("Module `{}` did not have any changes.", path))?;
+            }
+            submodule.original_hash = base;
+            submodule.new_hash = Some(new_hash);
+        }
+        if !to_merge.is_empty() {
+            self.cli
+                .git_cmd(
+                    ["merge", "--no-ff", "--no-commit"]
+                        .iter()
+                        .copied()
+                        .chain(to_merge.iter().map(String::as_str)),
+                )
+                .run("Failed to merge josh subtrees.")?;
+        }
+        Ok(())
+    }
+
+    fn check_for_updates(&mut self) -> Result<(), Error> {
+        // Check if any of the submodules were actually modified. This
+        // compares against the commit recorded in the superproject rather
+        // than the submodule's checkout, since a previous run may have
+        // already moved the submodule without committing.
+        let mut found = false;
+        let mut downgrades = Vec::new();
+        for submodule in self.submodules_to_up() {
+            let target =
+                self.get_hash(&format!("{}^{{commit}}", submodule.rev), &submodule.path)?;
+            if target == submodule.original_hash {
+                continue;
+            }
+            found = true;
+            if self.is_downgrade(submodule, &target)? {
+                downgrades.push((submodule.path.clone(), target.clone()));
+            }
+            if repo::worktree_head(&submodule.path)? == target {
+                self.cli.info(&format!(
+                    "Module `{}` is already at `{}`, continuing.",
+                    submodule.path, submodule.rev
+                ))?;
+            }
+        }
+        if !found && self.updated_submodules().next().is_none() {
+            self.cli
+                .warning("Submodules do not have any changes, exiting...")?;
+            self.cli.exit_no_changes();
+        }
+        if !downgrades.is_empty() && !self.cli.matches.is_present("allow-downgrade") {
+            let paths: Vec<&str> = downgrades.iter().map(|(path, _)| path.as_str()).collect();
+            bail!(
+                "The new revs of {} are older than the current commits, which would \
+                 go back in history. Pass --allow-downgrade if that is intended.",
+                paths.join(", ")
+            );
+        }
+        for (path, target) in downgrades {
+            let submodule = self
+                .submodules
+                .iter_mut()
+                .find(|submodule| submodule.path == path)
+                .unwrap();
+            submodule.downgraded = true;
+            self.cli.warning(&format!(
+                "Downgrading `{}` from {} to {}.",
+                path, submodule.original_hash, target
+            ))?;
+            self.notes.push(format!(
+                "This is a downgrade, it reverts the commits listed for `{}`.",
+                path
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether `target` is an ancestor of the commit `submodule` is at now.
+    fn is_downgrade(&self, submodule: &Submodule, target: &str) -> Result<bool, Error> {
+        if submodule.kind != SourceKind::Submodule {
+            return Ok(false);
+        }
+        Ok(self
+            .cli
+            .git_cmd([
+                "merge-base",
+                "--is-ancestor",
+                target,
+                &submodule.original_hash,
+            ])
+            .dir(&submodule.path)
+            .status(format!(
+                "Failed to check the ancestry of `{}` in module `{}`.",
+                target, submodule.path
+            ))?
+            .success())
+    }
+
+    fn make_branch(&mut self) -> Result<(), Error> {
+        self.cli.status("Fetching upstream.")?;
+        self.cli
+            .git_cmd(["fetch", self.cli.upstream_remote()])
+            .read_only()
+            .run("Failed to fetch upstream.")?;
+
+        self.cli.status("Creating branch.")?;
+        self.cli
+            .git_cmd([
+                "checkout",
+                "-B",
+                &self.up_branch,
+                &self.cli.upstream_branch(&self.rust_branch),
+            ])
+            .run("Failed to create branch.")?;
+
+        // TODO: Is there a better way to do this?
+        let (remote, branch) = self.push_target();
+        self.cli
+            .git_cmd([
+                "config",
+                &format!("branch.{}.remote", self.up_branch),
+                remote,
+            ])
+            .run("Failed to configure remote.")?;
+        self.cli
+            .git_cmd([
+                "config",
+                &format!("branch.{}.merge", self.up_branch),
+                &format!("refs/heads/{}", branch),
+            ])
+            .run("Failed to configure head.")?;
+
+        self.update_submodules_base()?;
+        Ok(())
+    }
+
+    /// The remote and branch name to push the update branch to. These are
+    /// `--push-remote` (or the fork found by `check_push_remote`, or
+    /// `origin`) and the same name, unless set with
+    /// `--push-to REMOTE[:NAMESPACE/]`.
+    fn push_target(&self) -> (&str, String) {
+        match self.cli.matches.value_of("push-to") {
+            Some(value) => {
+                let (remote, namespace) = value.split_once(':').unwrap_or((value, ""));
+                let namespace = namespace.trim_end_matches('/');
+                if namespace.is_empty() {
+                    (remote, self.up_branch.clone())
+                } else {
+                    (remote, format!("{}/{}", namespace, self.up_branch))
+                }
+            }
+            None => (
+                self.cli
+                    .matches
+                    .value_of("push-remote")
+                    .or(self.push_remote.as_deref())
+                    .unwrap_or("origin"),
+                self.up_branch.clone(),
+            ),
+        }
+    }
+
+    /// Makes sure there is a fork to push the update branch to. `origin`
+    /// is used when it is not the upstream repo itself. Otherwise the
+    /// user's fork is looked up with the GitHub API, created if they agree,
+    /// and added as a remote if none points to it.
+    fn check_push_remote(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking push remote.")?;
+        let upstream_url = self
+            .cli
+            .git_cmd(["remote", "get-url", self.cli.upstream_remote()])
+            .capture_stdout("Failed to get upstream url.")?;
+        let upstream = RemoteUrl::parse(&upstream_url)?;
+        if !upstream.is_github() {
+            return Ok(());
+        }
+        let origin = self
+            .cli
+            .git("remote get-url origin")
+            .status("Failed to get origin url.")?
+            .success();
+        let origin_is_upstream = match self.remote_for(&upstream.slug())? {
+            Some(remote) => remote == "origin",
+            None => false,
+        };
+        if origin && !origin_is_upstream {
+            return Ok(());
+        }
+        let api = GitHubApi::new(self.cli)?;
+        let user = api.get("/user")?;
+        let login = user["login"]
+            .as_str()
+            .ok_or_else(|| format_err!("Expected the login in the response, got:\n{}", user))?;
+        let fork = format!("{}/{}", login, upstream.repo);
+        if let Some(remote) = self.remote_for(&fork)? {
+            self.cli
+                .info(&format!("Pushing to `{}`, the remote of {}.", remote, fork))?;
+            self.push_remote = Some(remote);
+            return Ok(());
+        }
+        if api.get(&format!("/repos/{}", fork)).is_err() {
+            self.cli
+                .warning(&format!("You do not have a fork of {}.", upstream.slug()))?;
+            if !self
+                .cli
+                .confirm_remote(&format!("create the fork {}", fork))?
+            {
+                bail!(
+                    "There is no fork to push to, create one, or use --push-to \
+                     to choose the remote."
+                );
+            }
+            if !self.cli.is_dry_run() {
+                // GitHub creates the fork in the background, which is done
+                // long before the branch is pushed.
+                api.request("POST", &format!("/repos/{}/forks", upstream.slug()), None)?;
+            }
+        }
+        let remote = if origin { "fork" } else { "origin" };
+        let url = fork_url(&upstream, login);
+        self.cli
+            .git_cmd(["remote", "add", remote, &url])
+            .run(format!("Failed to add remote `{}`.", remote))?;
+        self.cli.info(&format!(
+            "Added remote `{}` for your fork {}.",
+            remote, fork
+        ))?;
+        self.push_remote = Some(remote.to_string());
+        Ok(())
+    }
+
+    /// The name of the remote whose URL is the GitHub repo `slug`
+    /// (`owner/name`), if there is one.
+    fn remote_for(&self, slug: &str) -> Result<Option<String>, Error> {
+        let output = self
+            .cli
+            .git("remote -v")
+            .capture_stdout("Failed to list remotes.")?;
+        Ok(output
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                Some((parts.next()?, parts.next()?))
+            })
+            .find(|(_, url)| {
+                RemoteUrl::parse(url)
+                    .is_ok_and(|url| url.is_github() && url.slug().eq_ignore_ascii_case(slug))
+            })
+            .map(|(name, _)| name.to_string()))
+    }
+
+    fn check_branch(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking head branch.")?;
+        // Check if the branch already exists.
+        if self.refresh {
+            self.cli.info(&format!(
+                "Recreating branch `{}` on `{}`.",
+                self.up_branch,
+                self.cli.upstream_branch(&self.rust_branch)
+            ))?;
+        } else if repo::has_branch(".", &self.up_branch)? {
+            self.cli.warning(&format!(
+                "Branch `{}` already exists.  It will be reset.",
+                self.up_branch
+            ))?;
+            if self.cli.is_interactive() && !self.cli.confirm("Do you want to continue?", true)? {
+                self.cli.exit_err();
+            }
+        }
+        Ok(())
+    }
+
+    fn update_submodules(&self) -> Result<(), Error> {
+        self.check_submodule_status(false)?;
+        self.cli.status("Updating submodules.")?;
+        let total = self.submodules_to_up().count();
+        for (i, submodule) in self.submodules_to_up().enumerate() {
+            self.cli
+                .git_cmd(["checkout", &submodule.rev])
+                .dir(&submodule.path)
+                .run(format!(
+                    "Failed to checkout rev `{}` in module `{}`.",
+                    submodule.rev, submodule.path
+                ))?;
+            self.cli
+                .progress(i + 1, total, &format!("Updated `{}`.", submodule.path))?;
+        }
+        Ok(())
+    }
+
+    fn check_submodule_updated(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking for updated submodules.")?;
+
+        if self.cli.is_dry_run() {
+            // Nothing was checked out, so compare against the target.
+            let targets: Vec<String> = self
+                .submodules_to_up()
+                .map(|m| self.get_hash(&format!("{}^{{commit}}", m.rev), &m.path))
+                .collect::<Result<_, Error>>()?;
+            for (submodule, target) in self
+                .submodules
+                .iter_mut()
+                .filter(|s| s.wants_update && s.kind == SourceKind::Submodule)
+                .zip(targets)
+            {
+                submodule.was_updated = target != submodule.original_hash;
+                submodule.new_hash = Some(target);
+            }
+        } else {
+            let mods_updated: Vec<(bool, String)> = self
+                .submodules_to_up()
+                .map(|m| Ok((self.has_changes(&m.path)?, repo::worktree_head(&m.path)?)))
+                .collect::<Result<_, Error>>()?;
+            for (submodule, (updated, new_hash)) in &mut self
+                .submodules
+                .iter_mut()
+                .filter(|s| s.wants_update && s.kind == SourceKind::Submodule)
+                .zip(mods_updated)
+            {
+                submodule.was_updated = updated;
+                submodule.new_hash = Some(new_hash);
+            }
+            self.check_submodule_status(true)?;
+        }
+        // In case the members changes in this update, recompute. Members can
+        // only change if a manifest changed, so skip reloading the metadata
+        // (which is slow on the rust workspace) otherwise.
+        let manifests_changed = self
+            .updated_submodules()
+            .map(|submodule| self.manifests_changed(submodule))
+            .collect::<Result<Vec<bool>, Error>>()?
+            .contains(&true);
+        if manifests_changed && self.cli.is_dry_run() {
+            self.cli
+                .info("Manifests changed, but the dry run reuses the old cargo metadata.")?;
+        } else if manifests_changed {
+            // This is finished in `update_lock`, so that it runs while the CI
+            // status is checked and the changelog is generated.
+            self.pending_metadata = Some(start_metadata(self.cli)?);
+        } else if self.cli.is_verbose() {
+            self.cli
+                .info("No manifests changed, reusing cargo metadata.")?;
+        }
+
+        for submodule in self.submodules_to_up() {
+            if !submodule.was_updated {
+                self.cli.warning(&format!(
+                    "Module `{}` did not have any changes.",
+                    submodule.path
+                ))?;
+            }
+        }
+        if self.updated_submodules().next().is_none() {
+            self.cli.warning("No submodules were updated, exiting...")?;
+            self.cli.exit_no_changes();
+        }
+        Ok(())
+    }
+
+    /// Compares `git submodule status --recursive` with the expected hash of
+    /// every submodule, before or `after` the update. This catches
+    /// submodules that moved on their own, including nested ones (like
+    /// those of cargo) that are checked out at a different commit than the
+    /// one their parent records.
+    fn check_submodule_status(&self, after: bool) -> Result<(), Error> {
+        let output = self
+            .cli
+            .git("submodule status --recursive")
+            .capture("Failed to get submodule status.")?
+            .stdout;
+        let mut problems = Vec::new();
+        for (state, hash, path) in parse_submodule_status(&output) {
+            match self.submodules.iter().find(|s| s.path == path) {
+                Some(submodule) => {
+                    let expected = match &submodule.new_hash {
+                        Some(new_hash) if after && submodule.was_updated => new_hash,
+                        _ => &submodule.original_hash,
+                    };
+                    if state != '-' && hash != *expected {
+                        problems.push(format!("`{}` is at {}, expected {}", path, hash, expected));
+                    }
+                }
+                None if state == '+' || state == 'U' => {
+                    problems.push(format!(
+                        "nested `{}` is at {}, not the commit recorded by its parent",
+                        path, hash
+                    ));
+                }
+                None => {}
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let when = if after { "after" } else { "before" };
+        self.cli
+            .boxed(&format!("Submodule drift {} update", when), &problems)?;
+        self.cli.info(
+            "Run `git submodule update --init --recursive` in the affected submodules to fix.",
+        )?;
+        if !self.cli.confirm("Continue anyway?", false)? {
+            bail!("Submodules are not at the expected commits, aborting...");
+        }
+        Ok(())
+    }
+
+    /// Whether any `Cargo.toml` in the submodule changed in the update.
+    fn manifests_changed(&self, submodule: &Submodule) -> Result<bool, Error> {
+        Ok(!self
+            .cli
+            .git_cmd([
+                "diff",
+                "--quiet",
+                &submodule.original_hash,
+                submodule.new_hash.as_ref().unwrap(),
+                "--",
+                "*Cargo.toml",
+            ])
+            .dir(&submodule.path)
+            .status("Failed to check for manifest changes.")?
+            .success())
+    }
+
+    /// Starts checking the CI status of the new submodule commits. The
+    /// results are reported by `report_ci` after the tests.
+    fn check_ci(&mut self) -> Result<(), Error> {
+        self.cli.status("Checking submodule CI status.")?;
+        let api = match GitHubApi::new(self.cli) {
+            Ok(api) => api,
+            Err(e) => {
+                self.cli.warning(&format!("Skipping CI check: {}", e))?;
+                return Ok(());
+            }
+        };
+        let mut pending = Vec::new();
+        for submodule in self.updated_submodules() {
+            if submodule.kind != SourceKind::Submodule {
+                // The hashes are rewritten by josh.
+                continue;
+            }
+            // Only GitHub-hosted submodules can be checked.
+            let repo = match log::git_origin(self.cli, &submodule.path) {
+                Ok(repo) => repo,
+                Err(_) => continue,
+            };
+            let hash = submodule.new_hash.clone().unwrap();
+            match api.start_ci_status(&repo, &hash) {
+                Ok(status) => pending.push((submodule.path.clone(), hash, status)),
+                Err(e) => self.cli.warning(&format!(
+                    "Could not check CI for `{}`: {}",
+                    submodule.path, e
+                ))?,
+            }
+        }
+        self.pending_ci = pending;
+        Ok(())
+    }
+
+    /// Warns if CI for the new submodule commits is failing or pending.
+    fn report_ci(&mut self) -> Result<(), Error> {
+        for (path, hash, status) in std::mem::take(&mut self.pending_ci) {
+            match status.wait() {
+                Ok(CiStatus::Success) => {}
+                Ok(CiStatus::Failure) => self
+                    .cli
+                    .warning(&format!("CI for `{}` at {} is failing.", path, hash))?,
+                Ok(CiStatus::Pending) => self
+                    .cli
+                    .warning(&format!("CI for `{}` at {} is still pending.", path, hash))?,
+                Ok(CiStatus::Missing) => self
+                    .cli
+                    .info(&format!("No CI results for `{}` at {}.", path, hash))?,
+                Err(e) => self
+                    .cli
+                    .warning(&format!("Could not check CI for `{}`: {}", path, e))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Determine which members are in a submodule.
+    fn compute_members(metadata: &Metadata, submodule_path: &str) -> Result<Vec<Member>, Error> {
+        let mut members = Vec::new();
+        let package_map: HashMap<&PackageId, &Package> = metadata
+            .packages
+            .iter()
+            .map(|package| (&package.id, package))
+            .collect();
+        let abs_path = env::current_dir()?.join(submodule_path);
+        for member in &metadata.workspace_members {
+            let package = package_map[member];
+            // Pop `Cargo.toml` off path.
+            let member_path = package.manifest_path.parent().unwrap();
+            if member_path.strip_prefix(&abs_path).is_ok() {
+                members.push(Member {
+                    name: package.name.clone(),
+                    version: package.version.to_string(),
+                    path: member_path.to_path_buf(),
+                    workspace_root: metadata.workspace_root.clone(),
+                });
+            }
+        }
+        Ok(members)
+    }
+
+    /// Path of the workspace's `Cargo.lock`, relative to the repo root.
+    fn lock_path(&self) -> Result<String, Error> {
+        lock_path(self.orig_metadata.as_ref().unwrap())
+    }
+
+    fn update_lock_submodule(&self, member: &Member) -> Result<(), Error> {
+        // `-p` matches what is in Cargo.lock, which has the version from
+        // before the update. The version from the metadata is only used if
+        // the member is not in Cargo.lock yet.
+        let lock_path = member.workspace_root.join("Cargo.lock");
+        let lock = fs::read_to_string(&lock_path)
+            .with_context(|| format!("Failed to read {}.", lock_path.display()))?;
+        let version = lock::local_version(&lock, &member.name).unwrap_or(&member.version);
+        self.cli
+            .cargo_cmd(["update", "-p", &format!("{}@{}", member.name, version)])
+            .dir(&member.workspace_root)
+            .run(format!(
+                "Failed to update Cargo.lock for pkg `{}`.",
+                member.name
+            ))?;
+        Ok(())
+    }
+
+    fn update_lock(&mut self) -> Result<(), Error> {
+        self.cli.status("Updating Cargo.lock")?;
+        let mut added = BTreeSet::new();
+        let mut removed = BTreeSet::new();
+        if let Some(metadata) = self.pending_metadata.take() {
+            let new_metadata = wait_metadata(metadata)?;
+            for submodule in self.submodules.iter_mut().filter(|s| s.was_updated) {
+                submodule.members = SubUp::compute_members(&new_metadata, &submodule.path)?;
+            }
+            let old_names = member_names(self.orig_metadata.as_ref().unwrap());
+            let new_names = member_names(&new_metadata);
+            added = &new_names - &old_names;
+            removed = &old_names - &new_names;
+        }
+        let other_locks = self.load_other_workspaces()?;
+        for submodule in self.updated_submodules() {
+            // New members are not in Cargo.lock yet, they are added below.
+            for member in submodule
+                .members
+                .iter()
+                .filter(|m| !added.contains(&m.name))
+            {
+                // Check if Cargo.toml was updated.
+                let was_updated = !self
+                    .cli
+                    .git_cmd([
+                        "diff",
+                        "--quiet",
+                        &submodule.original_hash,
+                        submodule.new_hash.as_ref().unwrap(),
+                        "--",
+                        "Cargo.toml",
+                    ])
+                    .dir(&member.path)
+                    .status("Failed to determine if Cargo.toml changed.")?
+                    .success();
+                if was_updated {
+                    self.update_lock_submodule(member)?;
+                } else {
+                    if self.cli.is_verbose() {
+                        self.cli.info(&format!(
+                            "Skipping member `{}`, manifest was not changed.",
+                            member.name
+                        ))?;
+                    }
+                }
+            }
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            self.update_lock_members(&added, &removed)?;
+        }
+        self.check_nested_locks()?;
+        let root_lock = self.lock_path()?;
+        let mut lock_packages = BTreeSet::new();
+        for lock_path in iter::once(&root_lock).chain(&other_locks) {
+            if !self.has_changes(lock_path)? {
+                continue;
+            }
+            self.cli.warning(&format!("{} has changed.", lock_path))?;
+            let old_lock = self
+                .cli
+                .git_cmd(["show", &format!("HEAD:{}", lock_path)])
+                .capture_stdout(format!("Failed to read original {}.", lock_path))?;
+            let new_lock = fs::read_to_string(lock_path)
+                .with_context(|| format!("Failed to read {}.", lock_path))?;
+            self.report.lock_changed = true;
+            let packages = lock::changed_packages(&old_lock, &new_lock);
+            self.cli.emit(&Event::LockChanged {
+                path: lock_path,
+                packages: &packages,
+            })?;
+            lock_packages.extend(packages);
+            let changes = lock::change_summary(&old_lock, &new_lock);
+            if !changes.is_empty() {
+                let list: Vec<String> = changes.iter().map(|c| format!("- {}", c)).collect();
+                self.notes
+                    .push(format!("{} changes:\n\n{}\n", lock_path, list.join("\n")));
+            }
+            if *lock_path == root_lock {
+                self.affected_tests = self.affected_tools(&new_lock)?;
+            }
+        }
+        self.report.lock_packages = lock_packages.into_iter().collect();
+        if self.report.lock_changed {
+            if !self.cli.is_interactive() && !self.cli.matches.is_present("allow-lock-change") {
+                bail!("Cargo.lock changes requires --allow-lock-change, aborting...");
+            }
+            if self.cli.is_interactive() {
+                self.cli
+                    .info("Please carefully inspect Cargo.lock changes.")?;
+                if !self.cli.confirm("Do you want to continue?", true)? {
+                    bail!("Aborting...");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the metadata of the workspaces other than the root one (such as
+    /// `library`) that contain an updated submodule, and adds their members
+    /// to the submodules. Returns the paths of their lockfiles.
+    fn load_other_workspaces(&mut self) -> Result<Vec<String>, Error> {
+        let root_lock = self.lock_path()?;
+        let mut pending = Vec::new();
+        for lock_path in tracked_locks(self.cli)? {
+            if lock_path == root_lock {
+                continue;
+            }
+            let dir = lock_path.trim_end_matches("Cargo.lock");
+            if !self.updated_submodules().any(|s| s.path.starts_with(dir)) {
+                continue;
+            }
+            let metadata = self
+                .cli
+                .cargo("metadata --format-version 1 --no-deps")
+                .dir(dir)
+                .spawn(format!("Failed to load cargo metadata in `{}`.", dir))?;
+            pending.push((lock_path, metadata));
+        }
+        let mut locks = Vec::new();
+        for (lock_path, metadata) in pending {
+            let metadata = wait_metadata(metadata)?;
+            for submodule in self.submodules.iter_mut().filter(|s| s.was_updated) {
+                submodule
+                    .members
+                    .extend(SubUp::compute_members(&metadata, &submodule.path)?);
+            }
+            locks.push(lock_path);
+        }
+        Ok(locks)
+    }
+
+    /// Warns about updated members whose `rust-version` is newer than the
+    /// stage0 compiler, since bootstrap would fail to build them.
+    fn check_msrv(&self) -> Result<(), Error> {
+        self.cli
+            .status("Checking rust-version of updated members.")?;
+        let stage0 = match msrv::stage0_version(Path::new("."))? {
+            Some(version) if msrv::parse_version(&version).is_some() => version,
+            version => {
+                if self.cli.is_verbose() {
+                    self.cli.info(&format!(
+                        "The stage0 compiler is `{}`, not checking rust-version.",
+                        version.as_deref().unwrap_or("unknown")
+                    ))?;
+                }
+                return Ok(());
+            }
+        };
+        let too_new = self.too_new_members(&stage0)?;
+        if !too_new.is_empty() {
+            self.cli.warning(&format!(
+                "These crates need a newer rust than the stage0 compiler {}, \
+                 bootstrap will fail to build them: {}",
+                stage0,
+                too_new.join(", ")
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// The updated members whose `rust-version` is newer than `stage0`, with
+    /// that version.
+    fn too_new_members(&self, stage0: &str) -> Result<Vec<String>, Error> {
+        let current_dir = env::current_dir()?;
+        let mut too_new = Vec::new();
+        for submodule in self.updated_submodules() {
+            let root = current_dir.join(&submodule.path);
+            let workspace_manifest = self.read_updated(submodule, Path::new("Cargo.toml"))?;
+            for member in &submodule.members {
+                let dir = member.path.strip_prefix(&root).unwrap_or(Path::new(""));
+                let manifest = match self.read_updated(submodule, &dir.join("Cargo.toml"))? {
+                    Some(manifest) => manifest,
+                    None => continue,
+                };
+                let version = msrv::rust_version(&manifest, workspace_manifest.as_deref())
+                    .with_context(|| {
+                        format!("Failed to read the manifest of `{}`.", member.name)
+                    })?;
+                if let Some(version) = version.filter(|v| msrv::is_too_new(v, stage0)) {
+                    too_new.push(format!("{} (rust-version {})", member.name, version));
+                }
+            }
+        }
+        Ok(too_new)
+    }
+
+    /// Reads the file at `path` (relative to the submodule) as of the update,
+    /// `None` if it does not exist.
+    ///
+    /// Submodules are read from the new commit, which also works in dry-run
+    /// mode where nothing is checked out.
+    fn read_updated(&self, submodule: &Submodule, path: &Path) -> Result<Option<String>, Error> {
+        match (&submodule.kind, &submodule.new_hash) {
+            (SourceKind::Submodule, Some(hash)) => {
+                let spec = format!("{}:{}", hash, path.display());
+                let exists = self
+                    .cli
+                    .git_cmd(["cat-file", "-e", &spec])
+                    .dir(&submodule.path)
+                    .quiet()
+                    .status(format!("Failed to check for `{}`.", spec))?
+                    .success();
+                if !exists {
+                    return Ok(None);
+                }
+                let text = self
+                    .cli
+                    .git_cmd(["show", &spec])
+                    .dir(&submodule.path)
+                    .quiet()
+                    .capture_stdout(format!("Failed to read `{}`.", spec))?;
+                Ok(Some(text))
+            }
+            _ => {
+                let path = Path::new(&submodule.path).join(path);
+                if path.exists() {
+                    Ok(Some(fs::read_to_string(&path).with_context(|| {
+                        format!("Failed to read {}", path.display())
+                    })?))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Adds and removes workspace members that the update added or removed
+    /// in Cargo.lock, and checks that the result is consistent.
+    fn update_lock_members(
+        &mut self,
+        added: &BTreeSet<String>,
+        removed: &BTreeSet<String>,
+    ) -> Result<(), Error> {
+        let list = |names: &BTreeSet<String>| {
+            names
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if !added.is_empty() {
+            self.cli
+                .info(&format!("New workspace members: {}", list(added)))?;
+            self.notes
+                .push(format!("Adds workspace members {}.", list(added)));
+        }
+        if !removed.is_empty() {
+            self.cli
+                .info(&format!("Removed workspace members: {}", list(removed)))?;
+            self.notes
+                .push(format!("Removes workspace members {}.", list(removed)));
+        }
+        let workspace_root = &self.orig_metadata.as_ref().unwrap().workspace_root;
+        self.cli
+            .cargo("update --workspace")
+            .dir(workspace_root)
+            .run("Failed to update the workspace members in Cargo.lock.")?;
+        if self.cli.is_dry_run() {
+            return Ok(());
+        }
+        self.cli
+            .cargo("metadata --locked --format-version 1")
+            .dir(workspace_root)
+            .quiet()
+            .capture("Cargo.lock is not up to date after updating the workspace members.")?;
+        Ok(())
+    }
+
+    /// Lockfiles within the updated submodules that the rust build uses
+    /// directly, because they are for a workspace outside of the rust
+    /// workspace (like rust-analyzer's). Returns the paths of those whose
+    /// manifests or lockfile changed in the update.
+    fn nested_locks(&self) -> Result<Vec<String>, Error> {
+        let cwd = env::current_dir()?;
+        let mut locks = Vec::new();
+        for submodule in self.updated_submodules() {
+            let new_hash = submodule.new_hash.as_ref().unwrap();
+            let files = self
+                .cli
+                .git_cmd(["ls-tree", "-r", "--name-only", new_hash])
+                .dir(&submodule.path)
+                .capture_stdout("Failed to list submodule files.")?;
+            let changed = self
+                .cli
+                .git_cmd(["diff", "--name-only", &submodule.original_hash, new_hash])
+                .dir(&submodule.path)
+                .capture_stdout("Failed to list changed files.")?;
+            for file in files.lines() {
+                let dir = match file.strip_suffix("Cargo.lock") {
+                    Some(dir) if dir.is_empty() || dir.ends_with('/') => dir,
+                    _ => continue,
+                };
+                let abs_dir = cwd.join(&submodule.path).join(dir);
+                if submodule
+                    .members
+                    .iter()
+                    .any(|m| m.path.starts_with(&abs_dir))
+                {
+                    // Part of the rust workspace, which uses the top-level
+                    // Cargo.lock.
+                    continue;
+                }
+                let was_changed = changed.lines().any(|path| {
+                    path.starts_with(dir)
+                        && (path.ends_with("Cargo.toml") || path.ends_with("Cargo.lock"))
+                });
+                if was_changed {
+                    locks.push(format!("{}/{}", submodule.path, file));
+                }
+            }
+        }
+        Ok(locks)
+    }
+
+    /// Verifies that the nested lockfiles from `nested_locks` do not need
+    /// to be regenerated. They cannot be fixed here, since they belong to
+    /// the submodule, so a stale one has to be fixed upstream first.
+    fn check_nested_locks(&self) -> Result<(), Error> {
+        let locks = self.nested_locks()?;
+        if locks.is_empty() {
+            return Ok(());
+        }
+        if self.cli.is_dry_run() {
+            for lock in &locks {
+                self.cli.info(&format!(
+                    "Would verify `{}` (the submodule is not checked out in a dry run).",
+                    lock
+                ))?;
+            }
+            return Ok(());
+        }
+        let mut stale = Vec::new();
+        for lock in &locks {
+            self.cli.status(&format!("Verifying `{}`.", lock))?;
+            let manifest = lock.replace("Cargo.lock", "Cargo.toml");
+            let ok = self
+                .cli
+                .cargo_cmd([
+                    "metadata",
+                    "--locked",
+                    "--format-version",
+                    "1",
+                    "--manifest-path",
+                    &manifest,
+                ])
+                .status(format!("Failed to verify `{}`.", lock))?
+                .success();
+            if !ok {
+                stale.push(lock.clone());
+            }
+        }
+        if stale.is_empty() {
+            return Ok(());
+        }
+        self.cli.boxed("Lockfiles needing regeneration", &stale)?;
+        self.cli.warning(
+            "These lockfiles are out of date in the new commits, and will fail CI. \
+             They need to be regenerated upstream.",
+        )?;
+        if !self.cli.confirm("Do you want to continue?", false)? {
+            bail!("Nested lockfiles need to be regenerated, aborting...");
+        }
+        Ok(())
+    }
+
+    /// Paths of the in-tree tools that depend on the packages changed in
+    /// `new_lock`, excluding the updated submodules.
+    fn affected_tools(&self, new_lock: &str) -> Result<Vec<String>, Error> {
+        let names = lock::affected_local_packages(new_lock, &self.report.lock_packages);
+        let cwd = env::current_dir()?;
+        let mut paths = Vec::new();
+        for package in &self.orig_metadata.as_ref().unwrap().packages {
+            if !names.contains(&package.name) {
+                continue;
+            }
+            let dir = package.manifest_path.parent().unwrap();
+            let path = match dir.strip_prefix(&cwd) {
+                Ok(path) if path.starts_with("src/tools") => path,
+                _ => continue,
+            };
+            if self
+                .updated_submodules()
+                .any(|submodule| path.starts_with(&submodule.path))
+            {
+                continue;
+            }
+            let path = path.to_string_lossy().into_owned();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        if !paths.is_empty() {
+            self.cli.info(&format!(
+                "Cargo.lock changes affect {}, they will be tested by default.",
+                paths.join(" ")
+            ))?;
+        }
+        Ok(paths)
+    }
+
+    fn configure(&self) -> Result<(), Error> {
+        // Prevent bootstrap from changing the submodules.
+        let mut opts = vec!["--disable-manage-submodules"];
+        if let Some(configs) = self.cli.matches.values_of("set-config") {
+            for cfg in configs {
+                opts.push("--set");
+                opts.push(cfg);
+            }
+        }
+        self.cli
+            .runner("./configure", &opts)
+            .envs(self.test_env()?)
+            .run("Failed to disable submodules in config.toml.")?;
+        Ok(())
+    }
+
+    /// The `--test-env` variables for `./configure` and `x.py`.
+    fn test_env(&self) -> Result<Vec<(String, String)>, Error> {
+        let paths: Vec<&str> = self
+            .updated_submodules()
+            .map(|submodule| submodule.path.as_str())
+            .collect();
+        test_env(self.cli, &paths)
+    }
+
+    /// Runs a quick `x.py check` if a submodule the compiler builds against
+    /// was updated, so obvious breakage is found before the full tests.
+    fn pre_check(&self) -> Result<(), Error> {
+        let to_check: Vec<_> = self.cli.matches.values_of("pre-check").unwrap().collect();
+        let compiler_updated = self
+            .updated_submodules()
+            .any(|submodule| COMPILER_SUBMODULES.contains(&submodule.path.as_str()));
+        if !compiler_updated {
+            return Ok(());
+        }
+        self.configure()?;
+        self.cli
+            .status(&format!("Checking {}", to_check.join(" ")))?;
+        let mut args = vec!["check"];
+        args.extend(to_check);
+        self.cli
+            .runner("./x.py", &args)
+            .envs(self.test_env()?)
+            .run("Failed to run `x.py check`")?;
+        Ok(())
+    }
+
+    fn test(&mut self) -> Result<(), Error> {
+        if !self.failed_tests.is_empty() {
+            let failed = self.failed_tests.join(" ");
+            self.cli
+                .warning(&format!("These tests failed in the last run: {}", failed))?;
+            let choice = if self.cli.matches.is_present("rerun-all-tests") {
+                Some(1)
+            } else if self.cli.is_interactive() {
+                self.cli.select(
+                    "How do you wish to proceed?",
+                    &[
+                        "Re-run the failed tests",
+                        "Re-run all tests",
+                        "Continue without them",
+                    ],
+                    Some(0),
+                )?
+            } else {
+                self.cli.warning(
+                    "Only re-running the failed tests, \
+                     use --rerun-all-tests to run all of them.",
+                )?;
+                Some(0)
+            };
+            match choice {
+                None | Some(0) => return self.run_tests(self.failed_tests.clone()),
+                Some(1) => self.report.tests = None,
+                Some(_) => {
+                    self.cli
+                        .warning(&format!("Continuing without passing {}.", failed))?;
+                    self.failed_tests.clear();
+                    return Ok(());
+                }
+            }
+        }
+        // TODO: Remove submodules that can't be tested?
+        let mut default = HashSet::new();
+        let cli_test = self
+            .cli
+            .matches
+            .values_of("test")
+            .map(|tests| {
+                tests
+                    .flat_map(|s| s.split_whitespace().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_else(|| vec!["default".to_string()]);
+        for choice in cli_test {
+            if choice == "default" {
+                for submodule in self.updated_submodules() {
+                    default.insert(submodule.path.clone());
+                }
+                default.extend(self.affected_tests.iter().cloned());
+                if self
+                    .updated_submodules()
+                    .any(|submodule| title::is_book(&submodule.path))
+                {
+                    default.insert(LINKCHECK_TEST.to_string());
+                }
+            } else {
+                default.insert(choice.to_string());
+            }
+        }
+        let default: Vec<String> = default.into_iter().collect();
+        // This behavior is a little weird, consider changing.
+        let to_test = if self.cli.is_interactive() {
+            let default = default.join(" ");
+            let input = self
+                .cli
+                .input("Enter the submodules to test", Some(&default))?
+                .unwrap();
+            if input.is_empty() {
+                Vec::new()
+            } else {
+                input.split(' ').map(|s| s.to_string()).collect()
+            }
+        } else {
+            default
+        };
+        // TODO: better way to skip
+        if to_test.is_empty() || to_test == ["skip"] {
+            self.cli.warning("Skipping tests.")?;
+            Ok(())
+        } else {
+            self.run_tests(to_test)
+        }
+    }
+
+    /// Runs `x.py test` for all paths at once. If that fails, each path is
+    /// run on its own to find which failed, so that `--resume` only needs
+    /// to re-run those.
+    fn run_tests(&mut self, to_test: Vec<String>) -> Result<(), Error> {
+        self.configure()?;
+        let test_env = self.test_env()?;
+        self.cli
+            .status(&format!("Running tests for {}", to_test.join(" ")))?;
+        let mut args = vec!["test".to_string()];
+        args.extend(to_test.iter().cloned());
+        let result = self
+            .cli
+            .runner("./x.py", &args)
+            .envs(test_env.clone())
+            .run("Failed to run `x.py test`");
+        let failed = match result {
+            Ok(_) => Vec::new(),
+            Err(e) if to_test.len() == 1 => {
+                self.cli.warning(&format!("{:#}", e))?;
+                to_test.clone()
+            }
+            Err(_) => {
+                self.cli.warning("Tests failed, running each on its own.")?;
+                let mut failed = Vec::new();
+                for (i, path) in to_test.iter().enumerate() {
+                    self.cli.status(&format!("Running tests for {}", path))?;
+                    let result = self
+                        .cli
+                        .runner("./x.py", &["test", path])
+                        .envs(test_env.clone())
+                        .run(format!("Failed to run `x.py test {}`", path));
+                    if let Err(e) = result {
+                        self.cli.warning(&format!("{:#}", e))?;
+                        failed.push(path.clone());
+                    }
+                    self.cli
+                        .progress(i + 1, to_test.len(), &format!("Tested {}.", path))?;
+                }
+                failed
+            }
+        };
+        for path in to_test {
+            if !failed.contains(&path) {
+                self.report.tests.get_or_insert_with(Vec::new).push(path);
+            }
+        }
+        self.failed_tests = failed;
+        if self.failed_tests.is_empty() {
+            return Ok(());
+        }
+        Err(format_err!(
+            "Tests failed for {}, use --resume to run them again.",
+            self.failed_tests.join(" ")
+        ))
+        .context(TestsFailed)
+    }
+
+    /// Checks the links in the docs after a book was updated, when the tests
+    /// did not already, since broken links are the usual reason that book
+    /// updates fail CI.
+    fn check_links(&mut self) -> Result<(), Error> {
+        self.configure()?;
+        self.cli.status("Checking links in the docs.")?;
+        self.cli
+            .runner("./x.py", &["test", LINKCHECK_TEST])
+            .envs(self.test_env()?)
+            .run("Failed to run the link checker, a book may have broken links.")
+            .context(TestsFailed)?;
+        if let Some(tests) = &mut self.report.tests {
+            tests.push(LINKCHECK_TEST.to_string());
+        }
+        Ok(())
+    }
+
+    /// Offers to update the `branch` field in `.gitmodules` for submodules
+    /// that were updated from a different branch.
+    fn update_gitmodules_branch(&mut self) -> Result<(), Error> {
+        let mut notes = Vec::new();
+        for submodule in self.updated_submodules() {
+            let branch = match submodule.rev.strip_prefix("origin/") {
+                Some(branch) if branch != "HEAD" => branch,
+                _ => continue,
+            };
+            let key = format!("submodule.{}.branch", submodule.name);
+            // Only submodules that already track a branch are updated.
+            let current = self
+                .cli
+                .git_cmd([
+                    "config",
+                    "--file",
+                    ".gitmodules",
+                    "--default=",
+                    "--get",
+                    &key,
+                ])
+                .capture_stdout("Failed to read .gitmodules.")?;
+            if current.is_empty() || current == branch {
+                continue;
+            }
+            self.cli.warning(&format!(
+                "`.gitmodules` tracks branch `{}` for `{}`, but it was updated from `{}`.",
+                current, submodule.path, branch
+            ))?;
+            if !self
+                .cli
+                .confirm("Update the branch in .gitmodules?", true)?
+            {
+                continue;
+            }
+            self.cli
+                .git_cmd(["config", "--file", ".gitmodules", &key, branch])
+                .run("Failed to update .gitmodules.")?;
+            notes.push(format!(
+                "Updated the `.gitmodules` branch for `{}` from `{}` to `{}`.",
+                submodule.path, current, branch
+            ));
+        }
+        if !notes.is_empty() {
+            self.gitmodules_changed = true;
+            self.notes.extend(notes);
+        }
+        Ok(())
+    }
+
+    fn git_add(&self) -> Result<(), Error> {
+        self.cli.status("Adding to git index.")?;
+        let mut to_add: Vec<_> = self
+            .updated_submodules()
+            .map(|submodule| submodule.path.clone())
+            .collect();
+        for lock_path in tracked_locks(self.cli)? {
+            if self.has_changes(&lock_path)? {
+                to_add.push(lock_path);
+            }
+        }
+        if self.gitmodules_changed {
+            to_add.push(".gitmodules".to_string());
+        }
+        self.cli
+            .git_cmd(std::iter::once("add").chain(to_add.iter().map(String::as_str)))
+            .run("Failed to add files to git.")?;
+        if !self.cli.is_dry_run() {
+            // Josh subtrees are plain directories, without a gitlink.
+            for submodule in self
+                .updated_submodules()
+                .filter(|submodule| submodule.kind == SourceKind::Submodule)
+            {
+                let staged = repo::staged_gitlink(".", &submodule.path)?;
+                let new_hash = submodule.new_hash.as_ref().unwrap();
+                if staged != *new_hash {
+                    bail!(
+                        "Expected `{}` to be staged at {}, but the index has {}.",
+                        submodule.path,
+                        new_hash,
+                        staged
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the changelog of the updated submodules as the commit
+    /// message.
+    fn generate_changelog(&mut self) -> Result<(), Error> {
+        self.cli.status("Generating changelog.")?;
+        let ups: Vec<_> = self
+            .updated_submodules()
+            .map(|submodule| {
+                (
+                    submodule.path.as_str(),
+                    submodule.original_hash.as_str(),
+                    submodule.new_hash.as_deref().unwrap(),
+                )
+            })
+            .collect();
+        for (path, from, to) in &ups {
+            self.cli.emit(&Event::SubmoduleUpdated { path, from, to })?;
+        }
+        // A downgrade lists the commits it reverts.
+        let ups: Vec<_> = ups
+            .into_iter()
+            .zip(self.updated_submodules())
+            .map(|((path, from, to), submodule)| {
+                if submodule.downgraded {
+                    (path, to, from)
+                } else {
+                    (path, from, to)
+                }
+            })
+            .collect();
+        if let Some(path) = self.cli.matches.value_of("pr-body-file") {
+            // Use the hand-written message for both renderings. It is read
+            // first in case it is one of the generated files.
+            let body = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read PR body file `{}`.", path))?;
+            fs::write(log::COMMIT_MSG_PATH, &body)?;
+            fs::write(log::COMMIT_MSG_PLAIN_PATH, &body)?;
+        } else {
+            let origins = self
+                .updated_submodules()
+                .map(|submodule| match &submodule.kind {
+                    SourceKind::Submodule => Ok(log::Origin {
+                        repo: log::git_origin(self.cli, &submodule.path)?,
+                        rewritten: false,
+                    }),
+                    SourceKind::Josh { repo } => Ok(log::Origin {
+                        repo: repo.clone(),
+                        rewritten: true,
+                    }),
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            log::generate_commit_with_origins(self.cli, &ups, &origins)?;
+        }
+        Ok(())
+    }
+
+    /// Adds notes collected after the changelog was generated.
+    fn prepare_commit_message(&mut self) -> Result<(), Error> {
+        self.cli.status("Preparing commit message.")?;
+        if !self.notes.is_empty() {
+            for path in &[log::COMMIT_MSG_PATH, log::COMMIT_MSG_PLAIN_PATH] {
+                let mut body = fs::read_to_string(path)?;
+                body.push('\n');
+                for note in &self.notes {
+                    body.push_str(note);
+                    body.push('\n');
+                }
+                fs::write(path, body)?;
+            }
+        }
+        // Triagebot directives and mentions only go in the PR body.
+        let snippets = self
+            .cli
+            .matches
+            .values_of("pr-snippet")
+            .into_iter()
+            .flatten()
+            .map(triagebot::Snippet::parse)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let ccs = self
+            .cli
+            .matches
+            .values_of("cc")
+            .into_iter()
+            .flatten()
+            .map(triagebot::Cc::parse)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let paths: Vec<&str> = self
+            .updated_submodules()
+            .map(|submodule| submodule.path.as_str())
+            .collect();
+        let lines = triagebot::body_lines(
+            &snippets,
+            &ccs,
+            &paths,
+            self.cli.matches.is_present("no-review-assign"),
+        );
+        if !lines.is_empty() {
+            let mut body = fs::read_to_string(log::COMMIT_MSG_PATH)?;
+            body.push('\n');
+            for line in &lines {
+                body.push_str(line);
+                body.push('\n');
+            }
+            fs::write(log::COMMIT_MSG_PATH, body)?;
+        }
+        if self.cli.matches.is_present("signoff-dco") {
+            let mut body = fs::read_to_string(log::COMMIT_MSG_PATH)?;
+            body.push_str(DCO_ATTESTATION);
+            fs::write(log::COMMIT_MSG_PATH, body)?;
+        }
+        if self.cli.is_dry_run() {
+            let body = fs::read_to_string(log::COMMIT_MSG_PLAIN_PATH)?;
+            let lines: Vec<String> = body.lines().map(|line| line.to_string()).collect();
+            self.cli.boxed("Commit message", &lines)?;
+        }
+        Ok(())
+    }
+
+    fn default_commit_title(&self) -> String {
+        let paths: Vec<&str> = self
+            .updated_submodules()
+            .map(|submodule| submodule.path.as_str())
+            .collect();
+        // Include the version when updating a single submodule to a release.
+        let version = match self.updated_submodules().collect::<Vec<_>>().as_slice() {
+            [submodule] if SEMVER_RE.is_match(&submodule.rev) => Some(submodule.rev.as_str()),
+            _ => None,
+        };
+        // Mixed updates keep `Update`, the body says which are downgrades.
+        let downgraded = self
+            .updated_submodules()
+            .all(|submodule| submodule.downgraded);
+        title::commit_title(&self.rust_branch, &paths, version, downgraded)
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        if self.cli.is_interactive() && !self.cli.confirm("Ready to commit?", true)? {
+            self.cli
+                .warning("Skipping commit, you will need to commit manually.")?;
+            return Ok(());
+        }
+        let given = self.cli.matches.value_of("commit-title");
+        let default = given
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| self.default_commit_title());
+        self.commit_title = self
+            .cli
+            .input("Commit title", Some(&default))?
+            .or_else(|| given.map(|s| s.to_string()));
+        match &self.commit_title {
+            Some(title) => {
+                self.cli.status("Committing changes")?;
+                let body = fs::read_to_string(log::COMMIT_MSG_PLAIN_PATH)
+                    .with_context(|| format!("Failed to read {}", log::COMMIT_MSG_PLAIN_PATH))?;
+                let mut args = Vec::new();
+                if let Some(committer) = self.cli.matches.value_of("committer") {
+                    let (name, email) = parse_identity(committer)?;
+                    args.push("-c".to_string());
+                    args.push(format!("user.name={}", name));
+                    args.push("-c".to_string());
+                    args.push(format!("user.email={}", email));
+                }
+                args.push("commit".to_string());
+                if let Some(author) = self.cli.matches.value_of("author") {
+                    args.push(format!("--author={}", author));
+                }
+                if self.cli.matches.is_present("signoff-dco") {
+                    args.push("--signoff".to_string());
+                }
+                let mut messages = vec![title.to_string(), body.trim_end().to_string()];
+                if !self.cli.matches.is_present("no-trailers") {
+                    messages.push(format!(
+                        "Subup-Version: {}\nSubup-Command: {}",
+                        clap::crate_version!(),
+                        invocation()
+                    ));
+                }
+                for message in messages {
+                    args.push("-m".to_string());
+                    args.push(message);
+                }
+                self.cli.git_cmd(&args).run("Failed to commit changes.")?;
+                if !self.cli.is_dry_run() {
+                    self.report.commit_hash = Some(self.get_hash("HEAD", ".")?);
+                    if self.cli.matches.value_of("merge-strategy") == Some("ff-only") {
+                        self.verify_ff_only()?;
+                    }
+                }
+            }
+            None => {
+                self.cli
+                    .warning("No commit title, skipping commit, use --commit-title to set.")?;
+                return Ok(());
+            }
+        }
+        let (remote, branch) = self.push_target();
+        if !self
+            .cli
+            .confirm_remote(&format!("push `{}` to {}", branch, remote))?
+        {
+            return Ok(());
+        }
+        let mut cmd = self.cli.git("push").remote();
+        if self.refresh {
+            // The branch was recreated, but do not overwrite anything pushed
+            // since it was last fetched.
+            cmd = cmd.args(&["--force-with-lease"]);
+        } else if self.cli.matches.is_present("force") {
+            cmd = cmd.args(&["--force"]);
+        }
+        if self.cli.matches.is_present("push-to") {
+            // `git push` refuses to push to a branch with a different name.
+            cmd = cmd.args(&[remote.to_string(), format!("HEAD:refs/heads/{}", branch)]);
+        }
+        cmd.run("Failed to push changes.")?;
+        Ok(())
+    }
+
+    /// Verifies the branch is exactly one non-merge commit on top of the
+    /// upstream branch.
+    fn verify_ff_only(&self) -> Result<(), Error> {
+        self.cli.status("Verifying branch history.")?;
+        let base = self.cli.upstream_branch(&self.rust_branch);
+        let is_descendant = self
+            .cli
+            .git_cmd(["merge-base", "--is-ancestor", &base, "HEAD"])
+            .status("Failed to check branch ancestry.")?
+            .success();
+        if !is_descendant {
+            bail!(
+                "Branch `{}` is not a descendant of `{}`.\n\
+                 Rerun subup to recreate the branch from `{}`.",
+                self.up_branch,
+                base,
+                base
+            );
+        }
+        let count = |extra: &str| -> Result<String, Error> {
+            self.cli
+                .git_cmd(["rev-list", "--count", extra, &format!("{}..HEAD", base)])
+                .capture_stdout("Failed to count branch commits.")
+        };
+        let merges = count("--merges")?;
+        if merges != "0" {
+            bail!(
+                "Branch `{}` contains {} merge commit(s) on top of `{}`.\n\
+                 Rerun subup to recreate the branch from `{}`.",
+                self.up_branch,
+                merges,
+                base,
+                base
+            );
+        }
+        let commits = count("")?;
+        if commits != "1" {
+            bail!(
+                "Branch `{}` has {} commits on top of `{}`, expected 1.\n\
+                 Use `git reset --soft {}` and commit again, or rerun subup.",
+                self.up_branch,
+                commits,
+                base,
+                base
+            );
+        }
+        Ok(())
+    }
+
+    fn create_pr(&mut self) -> Result<(), Error> {
+        if !self.cli.confirm_remote("create a PR")? {
+            self.cli
+                .warning("Skipping PR, you will need to create it manually.")?;
+            return Ok(());
+        }
+        if self.commit_title.is_none() {
+            bail!("Creating a PR requires the commit title (use --commit-title)");
+        }
+        self.fit_pr_body()?;
+        if self.cli.matches.is_present("preview-pr") && !self.preview_pr()? {
+            self.cli
+                .warning("Skipping PR, you will need to create it manually.")?;
+            return Ok(());
+        }
+        if self.cli.is_dry_run() {
+            self.cli.info(&format!(
+                "Would create a PR from `{}` to `{}` titled `{}`.",
+                self.up_branch,
+                self.rust_branch,
+                self.commit_title.as_ref().unwrap()
+            ))?;
+            return Ok(());
+        }
+        let forge = self.forge()?;
+        let (remote, branch) = self.push_target();
+        let pr = PullRequest {
+            title: self.commit_title.as_ref().unwrap(),
+            body_file: log::COMMIT_MSG_PATH,
+            base: &self.rust_branch,
+            head: &branch,
+            head_remote: remote,
+            self_assign: self.cli.matches.is_present("self-assign"),
+            labels: Vec::new(),
+        };
+        let pr_url = forge.create_pr(self.cli, &pr)?;
+        self.cli.emit(&Event::PrCreated { url: &pr_url })?;
+        if let Some(body) = self.cli.matches.value_of("self-approve") {
+            forge
+                .comment(self.cli, &pr_url, body)
+                .context("Failed to post a comment to self-approve.")?;
+        }
+        self.report.pr_url = Some(pr_url);
+        Ok(())
+    }
+
+    /// Files (or comments on) a sync issue in each updated tool's
+    /// repository, linking the hash range and the PR.
+    fn tracking_issue(&mut self) -> Result<(), Error> {
+        if !self
+            .cli
+            .confirm_remote("open or comment on tracking issues")?
+        {
+            return Ok(());
+        }
+        let upstream = self
+            .cli
+            .git_cmd(["remote", "get-url", self.cli.upstream_remote()])
+            .capture_stdout("Failed to get upstream url.")?;
+        let date = self
+            .cli
+            .git("show --no-patch --format=%cs HEAD")
+            .capture_stdout("Failed to get commit date.")?;
+        let title = format!("{} sync {}", log::github_repo(&upstream)?, date);
+        let api = GitHubApi::new(self.cli)?;
+        for submodule in self.updated_submodules() {
+            if submodule.kind != SourceKind::Submodule {
+                // The hashes are rewritten by josh.
+                continue;
+            }
+            let repo = match log::git_origin(self.cli, &submodule.path) {
+                Ok(repo) => repo,
+                Err(e) => {
+                    self.cli.warning(&format!(
+                        "Skipping tracking issue for `{}`: {}",
+                        submodule.path, e
+                    ))?;
+                    continue;
+                }
+            };
+            let new_hash = submodule.new_hash.as_ref().unwrap();
+            let mut body = format!(
+                "`{}` was updated to {}...{}\n\nhttps://github.com/{}/compare/{}...{}",
+                submodule.path,
+                short_hash(&submodule.original_hash),
+                short_hash(new_hash),
+                repo,
+                submodule.original_hash,
+                new_hash
+            );
+            if let Some(pr_url) = &self.report.pr_url {
+                body.push_str(&format!("\n\nPR: {}", pr_url));
+            }
+            match api.find_open_issue(&repo, &title)? {
+                Some(number) if self.cli.is_dry_run() => self.cli.info(&format!(
+                    "Would comment on {}#{} `{}`.",
+                    repo, number, title
+                ))?,
+                Some(number) => {
+                    api.request(
+                        "POST",
+                        &format!("/repos/{}/issues/{}/comments", repo, number),
+                        Some(&json!({ "body": body })),
+                    )?;
+                    self.cli
+                        .status(&format!("Commented on {}#{}.", repo, number))?;
+                }
+                None if self.cli.is_dry_run() => self
+                    .cli
+                    .info(&format!("Would open issue `{}` in {}.", title, repo))?,
+                None => {
+                    let issue = api.request(
+                        "POST",
+                        &format!("/repos/{}/issues", repo),
+                        Some(&json!({ "title": title, "body": body })),
+                    )?;
+                    self.cli.status(&format!(
+                        "Opened tracking issue {}",
+                        issue["html_url"].as_str().unwrap_or(&repo)
+                    ))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Cleans up the PR body so it is not rejected after all the work is
+    /// done. The commit message keeps the full text.
+    fn fit_pr_body(&self) -> Result<(), Error> {
+        let body = fs::read_to_string(log::COMMIT_MSG_PATH)
+            .with_context(|| format!("Failed to read {}", log::COMMIT_MSG_PATH))?;
+        let len = body.chars().count();
+        let rest = if len > forge::MAX_BODY_CHARS {
+            self.cli.warning(&format!(
+                "The PR body is {} characters, truncating it to GitHub's limit of {}.",
+                len,
+                forge::MAX_BODY_CHARS
+            ))?;
+            self.save_full_changelog(&body)?
+        } else {
+            "the commit message".to_string()
+        };
+        let fitted = forge::fit_body(&body, &rest);
+        if fitted != body {
+            if len <= forge::MAX_BODY_CHARS {
+                self.cli
+                    .info("Removed control characters from the PR body.")?;
+            }
+            fs::write(log::COMMIT_MSG_PATH, fitted)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the full PR body where `--full-changelog` says, returning
+    /// where to tell readers of the truncated body to look.
+    fn save_full_changelog(&self, body: &str) -> Result<String, Error> {
+        let dest = match self.cli.matches.value_of("full-changelog") {
+            Some(dest) => dest,
+            None => return Ok("the commit message".to_string()),
+        };
+        if dest == "gist" {
+            if self.cli.is_dry_run() {
+                self.cli
+                    .info("Would upload the full changelog as a secret gist.")?;
+                return Ok("the gist".to_string());
+            }
+            if !self
+                .cli
+                .confirm_remote("upload the full changelog as a secret gist")?
+            {
+                return Ok("the commit message".to_string());
+            }
+            let url = GitHubApi::new(self.cli)?.create_gist(
+                self.commit_title.as_ref().unwrap(),
+                "changelog.md",
+                body,
+            )?;
+            self.cli
+                .status(&format!("Uploaded the full changelog to {}", url))?;
+            Ok(url)
+        } else {
+            fs::write(dest, body).with_context(|| format!("Failed to write {}", dest))?;
+            self.cli
+                .info(&format!("Wrote the full changelog to {}.", dest))?;
+            let link = self.cli.matches.value_of("full-changelog-url");
+            Ok(link.unwrap_or(dest).to_string())
+        }
+    }
+
+    /// The forge that hosts the upstream repository.
+    fn forge(&self) -> Result<Box<dyn Forge>, Error> {
+        let upstream = self
+            .cli
+            .git_cmd(["remote", "get-url", self.cli.upstream_remote()])
+            .capture_stdout("Failed to get upstream url.")?;
+        forge::detect(self.cli, &upstream)
+    }
+
+    /// Shows what the PR will look like, returns whether to create it.
+    fn preview_pr(&self) -> Result<bool, Error> {
+        let body = fs::read_to_string(log::COMMIT_MSG_PATH)
+            .with_context(|| format!("Failed to read {}", log::COMMIT_MSG_PATH))?;
+        let assignees = if self.cli.matches.is_present("self-assign") {
+            "@me"
+        } else {
+            "(none)"
+        };
+        let (remote, branch) = self.push_target();
+        let mut lines = vec![
+            format!("Title: {}", self.commit_title.as_ref().unwrap()),
+            format!("Base: {}", self.rust_branch),
+            format!("Head: {}/{}", remote, branch),
+            format!("Assignees: {}", assignees),
+            "Labels: (none)".to_string(),
+            String::new(),
+        ];
+        lines.extend(body.lines().map(|line| line.to_string()));
+        self.cli.boxed("PR preview", &lines)?;
+        self.cli.confirm("Create this PR?", true)
+    }
+
+    fn finish_manual_pr(&mut self) -> Result<(), Error> {
+        let (remote, branch) = self.push_target();
+        let remote_url = self
+            .cli
+            .git_cmd(["remote", "get-url", remote])
+            .capture_stdout(format!("Failed to get {} url.", remote))?;
+        let repo_url = forge::repo_web_url(&remote_url)?;
+        let url = self.forge()?.manual_pr_url(&repo_url, &branch);
+        self.cli.info(&format!("Open {}", url))?;
+        self.cli.info(&format!(
+            "Paste the contents of {}, assign yourself, \
+             click create, then approve with bors.",
+            log::COMMIT_MSG_PATH
+        ))?;
+        self.report.pr_url = Some(url);
+        Ok(())
+    }
+
+    /// Runs a step, recording how long it took. `number` is the step's
+    /// position in the plan, and the number of steps.
+    fn timed(
+        &mut self,
+        step: &'static str,
+        number: (usize, usize),
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.cli.emit(&Event::PhaseStarted {
+            phase: step,
+            step: number.0,
+            total: number.1,
+        })?;
+        let start = Instant::now();
+        f(self)?;
+        let duration = start.elapsed();
+        self.cli.emit(&Event::PhaseFinished {
+            phase: step,
+            duration,
+        })?;
+        self.report.timings.push((step, duration));
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        let steps = steps();
+        if self.cli.matches.is_present("explain-plan") {
+            let lines = plan::explain(&steps, |step| self.skip_reason(step))?;
+            return self.cli.boxed("Plan", &lines);
+        }
+        let start = Instant::now();
+        let order = plan::order(&steps)?;
+        // Only the steps that run are numbered. A step may still be skipped
+        // because of what an earlier one did.
+        let mut total = order
+            .iter()
+            .filter(|&&i| self.skip_reason(&steps[i]).is_none())
+            .count();
+        let mut number = 0;
+        for i in order {
+            let step = &steps[i];
+            if let Some(reason) = self.skip_reason(step) {
+                let message = format!("Skipping {}: {}.", step.name, reason);
+                if step.warn_on_skip {
+                    self.cli.warning(&message)?;
+                } else if self.cli.is_verbose() {
+                    self.cli.info(&message)?;
+                }
+                continue;
+            }
+            number += 1;
+            total = total.max(number);
+            if let Err(e) = self.timed(step.name, (number, total), step.run) {
+                // Keep what the step found for `--resume`, such as the
+                // tests that failed.
+                self.save_state()?;
+                if let Some(path) = self.cli.matches.value_of("emit") {
+                    self.report.total = start.elapsed();
+                    self.emit_summary(Path::new(path), Some((step.name, &e)))?;
+                }
+                return Err(e);
+            }
+            self.completed.push(step.name.to_string());
+            self.save_state()?;
+        }
+        if Path::new(STATE_PATH).exists() {
+            fs::remove_file(STATE_PATH)
+                .with_context(|| format!("Failed to remove {}", STATE_PATH))?;
+        }
+        self.report.total = start.elapsed();
+        self.print_summary()?;
+        if let Some(path) = self.cli.matches.value_of("emit") {
+            self.emit_summary(Path::new(path), None)?;
+        }
+        Ok(())
+    }
+
+    fn skip_reason(&self, step: &Step<SubUp<'a>>) -> Option<String> {
+        if self.completed.iter().any(|name| name == step.name) {
+            Some("already done".to_string())
+        } else {
+            (step.skip)(self)
+        }
+    }
+
+    /// Saves the progress so that a failed run can be resumed.
+    fn save_state(&self) -> Result<(), Error> {
+        if self.cli.is_dry_run() {
+            return Ok(());
+        }
+        let state = State {
+            submodule_args: self.submodule_args.clone(),
+            rust_branch: self.rust_branch.clone(),
+            up_branch: self.up_branch.clone(),
+            completed: self.completed.clone(),
+            submodules: self
+                .submodules
+                .iter()
+                .map(|submodule| Submodule {
+                    name: submodule.name.clone(),
+                    path: submodule.path.clone(),
+                    kind: submodule.kind.clone(),
+                    rev: submodule.rev.clone(),
+                    wants_update: submodule.wants_update,
+                    was_updated: submodule.was_updated,
+                    original_hash: submodule.original_hash.clone(),
+                    new_hash: submodule.new_hash.clone(),
+                    downgraded: submodule.downgraded,
+                    members: Vec::new(),
+                })
+                .collect(),
+            commit_title: self.commit_title.clone(),
+            notes: self.notes.clone(),
+            gitmodules_changed: self.gitmodules_changed,
+            lock_changed: self.report.lock_changed,
+            lock_packages: self.report.lock_packages.clone(),
+            tests: self.report.tests.clone(),
+            affected_tests: self.affected_tests.clone(),
+            refresh: self.refresh,
+            push_remote: self.push_remote.clone(),
+            failed_tests: self.failed_tests.clone(),
+        };
+        fs::write(STATE_PATH, serde_json::to_string_pretty(&state)? + "\n")
+            .with_context(|| format!("Failed to write {}", STATE_PATH))
+    }
+
+    /// Restores the progress saved by a failed run.
+    fn resume(cli: &'a Cli<'a>) -> Result<SubUp<'a>, Error> {
+        let state = fs::read_to_string(STATE_PATH).with_context(|| {
+            format!(
+                "Failed to read {}, there is no update to resume.",
+                STATE_PATH
+            )
+        })?;
+        let state: State = serde_json::from_str(&state)
+            .with_context(|| format!("Failed to parse {}", STATE_PATH))?;
+        let branch = repo::current_branch(".")?;
+        if branch != state.up_branch {
+            bail!(
+                "The update to resume is on branch `{}`, but `{}` is checked out.",
+                state.up_branch,
+                branch
+            );
+        }
+        cli.status(&format!(
+            "Resuming update of {} after {}.",
+            state.submodule_args.join(" "),
+            state.completed.last().map_or("nothing", |s| s.as_str())
+        ))?;
+        // Metadata and members are not saved, reload them.
+        let metadata = load_metadata(cli)?;
+        let mut submodules = state.submodules;
+        for submodule in &mut submodules {
+            submodule.members = SubUp::compute_members(&metadata, &submodule.path)?;
+        }
+        Ok(SubUp {
+            cli,
+            submodule_args: state.submodule_args,
+            submodules,
+            rust_branch: state.rust_branch,
+            up_branch: state.up_branch,
+            orig_metadata: Some(metadata),
+            commit_title: state.commit_title,
+            report: Report {
+                lock_changed: state.lock_changed,
+                lock_packages: state.lock_packages,
+                tests: state.tests,
+                ..Report::default()
+            },
+            notes: state.notes,
+            gitmodules_changed: state.gitmodules_changed,
+            pending_metadata: None,
+            pending_ci: Vec::new(),
+            completed: state.completed,
+            affected_tests: state.affected_tests,
+            refresh: state.refresh,
+            push_remote: state.push_remote,
+            failed_tests: state.failed_tests,
+        })
+    }
+
+    /// Writes the summary as JSON for CI consumers. With `failure`, it
+    /// includes the step that failed and its error.
+    fn emit_summary(&self, path: &Path, failure: Option<(&str, &Error)>) -> Result<(), Error> {
+        let submodules: Vec<_> = self
+            .updated_submodules()
+            .map(|submodule| {
+                json!({
+                    "path": submodule.path,
+                    "old_hash": submodule.original_hash,
+                    "new_hash": submodule.new_hash,
+                })
+            })
+            .collect();
+        let timings: Vec<_> = self
+            .report
+            .timings
+            .iter()
+            .map(|(step, duration)| json!({"step": step, "seconds": duration.as_secs_f64()}))
+            .collect();
+        let processes: serde_json::Map<_, _> = self
+            .cli
+            .spawns()
+            .counts()
+            .into_iter()
+            .map(|(category, count)| (category, json!(count)))
+            .collect();
+        let summary = json!({
+            "status": if failure.is_some() { "failed" } else { "success" },
+            "failed_step": failure.map(|(step, _)| step),
+            "error": failure.map(|(_, e)| format!("{:#}", e)),
+            "branch": self.up_branch,
+            "rust_branch": self.rust_branch,
+            "submodules": submodules,
+            "cargo_lock": {
+                "changed": self.report.lock_changed,
+                "packages": self.report.lock_packages,
+            },
+            "tests": self.report.tests,
+            "commit_title": self.commit_title,
+            "commit_hash": self.report.commit_hash,
+            "pr_url": self.report.pr_url,
+            "message_path": log::COMMIT_MSG_PATH,
+            "plain_message_path": log::COMMIT_MSG_PLAIN_PATH,
+            "timings": timings,
+            "total_seconds": self.report.total.as_secs_f64(),
+            "processes": processes,
+            "total_processes": self.cli.spawns().total(),
+        });
+        fs::write(path, serde_json::to_string_pretty(&summary)? + "\n")
+            .with_context(|| format!("Failed to write summary to `{}`.", path.display()))?;
+        Ok(())
+    }
+
+    fn print_summary(&self) -> Result<(), Error> {
+        let mut lines = vec![format!("Branch: {}", self.up_branch)];
+        for submodule in self.updated_submodules() {
+            lines.push(format!(
+                "Updated {}: {} -> {}",
+                submodule.path,
+                short_hash(&submodule.original_hash),
+                short_hash(submodule.new_hash.as_deref().unwrap_or("?"))
+            ));
+        }
+        lines.push(if self.report.lock_changed {
+            format!(
+                "Cargo.lock: changed ({} packages)",
+                self.report.lock_packages.len()
+            )
+        } else {
+            "Cargo.lock: unchanged".to_string()
+        });
+        lines.push(match &self.report.tests {
+            Some(tests) => format!("Tests: passed ({})", tests.join(" ")),
+            None => "Tests: skipped".to_string(),
+        });
+        lines.push(format!(
+            "Commit: {}",
+            self.report
+                .commit_hash
+                .as_deref()
+                .unwrap_or("not committed")
+        ));
+        lines.push(format!(
+            "PR: {}",
+            self.report.pr_url.as_deref().unwrap_or("not created")
+        ));
+        if self.cli.is_verbose() {
+            let spawns = self.cli.spawns();
+            let counts: Vec<String> = spawns
+                .counts()
+                .iter()
+                .map(|(category, count)| format!("{} {}", category, count))
+                .collect();
+            lines.push(format!(
+                "Processes: {} in {:.1}s ({})",
+                spawns.total(),
+                self.report.total.as_secs_f64(),
+                counts.join(", ")
+            ));
+        }
+        self.cli.boxed("Summary", &lines)
+    }
+}
+
+fn step<'a>(
+    name: &'static str,
+    deps: &'static [&'static str],
+    run: fn(&mut SubUp<'a>) -> Result<(), Error>,
+) -> Step<SubUp<'a>> {
+    Step {
+        name,
+        deps,
+        run,
+        skip: plan::always,
+        warn_on_skip: false,
+    }
+}
+
+/// The steps of `subup update`.
+fn steps<'a>() -> Vec<Step<SubUp<'a>>> {
+    vec![
+        step("check_environment", &[], |s| s.check_environment()),
+        Step {
+            skip: |s| {
+                if s.cli.matches.is_present("blocked-by") {
+                    None
+                } else {
+                    Some("no --blocked-by".to_string())
+                }
+            },
+            ..step("check_blockers", &["check_environment"], |s| {
+                s.check_blockers()
+            })
+        },
+        step("check_branch", &["check_environment"], |s| s.check_branch()),
+        Step {
+            skip: |s| {
+                if s.cli.matches.is_present("push-to") {
+                    Some("--push-to given".to_string())
+                } else if s.cli.matches.is_present("push-remote") {
+                    Some("--push-remote given".to_string())
+                } else if s.cli.is_local_only() {
+                    Some("--local-only".to_string())
+                } else {
+                    None
+                }
+            },
+            ..step("check_push_remote", &["check_environment"], |s| {
+                s.check_push_remote()
+            })
+        },
+        step("make_branch", &["check_branch", "check_push_remote"], |s| {
+            s.make_branch()
+        }),
+        step("load_metadata", &["make_branch"], |s| {
+            let metadata = match load_metadata(s.cli) {
+                Err(e) if repo::is_sparse_checkout(".")? => {
+                    return Err(e.context(
+                        "This is a sparse checkout, cargo needs every member of the \
+                         root workspace to be checked out.",
+                    ))
+                }
+                metadata => metadata?,
+            };
+            s.orig_metadata = Some(metadata);
+            Ok(())
+        }),
+        step("check_args", &["load_metadata"], |s| s.check_args()),
+        Step {
+            skip: |_| match repo::is_sparse_checkout(".") {
+                Ok(true) => None,
+                _ => Some("not a sparse checkout".to_string()),
+            },
+            ..step("check_sparse_checkout", &["check_args"], |s| {
+                s.check_sparse_checkout()
+            })
+        },
+        step("check_submodule_settings", &["check_args"], |s| {
+            s.check_submodule_settings()
+        }),
+        step("fetch_submodules", &["check_submodule_settings"], |s| {
+            s.fetch_submodules()
+        }),
+        step("check_submodule_rev", &["fetch_submodules"], |s| {
+            s.check_submodule_rev()
+        }),
+        Step {
+            skip: |s| {
+                if s.rust_branch == "master" {
+                    Some("only used for beta/stable".to_string())
+                } else {
+                    None
+                }
+            },
+            ..step("choose_release_tags", &["check_submodule_rev"], |s| {
+                s.choose_release_tags()
+            })
+        },
+        Step {
+            skip: |s| {
+                let matches = &s.cli.matches;
+                if matches.is_present("since") || matches.is_present("max-commits") {
+                    None
+                } else {
+                    Some("no --since or --max-commits".to_string())
+                }
+            },
+            ..step("apply_limits", &["check_submodule_rev"], |s| {
+                s.apply_limits()
+            })
+        },
+        Step {
+            skip: |s| {
+                if s.cli.matches.is_present("latest-green") {
+                    None
+                } else {
+                    Some("no --latest-green".to_string())
+                }
+            },
+            ..step("pick_latest_green", &["apply_limits"], |s| {
+                s.pick_latest_green()
+            })
+        },
+        Step {
+            skip: |s| {
+                if s.cli.matches.is_present("josh") {
+                    None
+                } else {
+                    Some("no --josh".to_string())
+                }
+            },
+            ..step("pull_subtrees", &["check_args"], |s| s.pull_subtrees())
+        },
+        Step {
+            skip: |s| {
+                if s.cli.matches.is_present("cherry-pick") {
+                    None
+                } else {
+                    Some("no --cherry-pick".to_string())
+                }
+            },
+            ..step("cherry_pick", &["choose_release_tags"], |s| s.cherry_pick())
+        },
+        step(
+            "check_for_updates",
+            &[
+                "cherry_pick",
+                "choose_release_tags",
+                "pick_latest_green",
+                "pull_subtrees",
+            ],
+            |s| s.check_for_updates(),
+        ),
+        step("update_submodules", &["check_for_updates"], |s| {
+            s.update_submodules()
+        }),
+        step("check_submodule_updated", &["update_submodules"], |s| {
+            s.check_submodule_updated()
+        }),
+        Step {
+            skip: skip_ci,
+            ..step("check_ci", &["check_submodule_updated"], |s| s.check_ci())
+        },
+        step("generate_changelog", &["check_submodule_updated"], |s| {
+            s.generate_changelog()
+        }),
+        step("update_lock", &["check_submodule_updated"], |s| {
+            s.update_lock()
+        }),
+        step("check_msrv", &["update_lock"], |s| s.check_msrv()),
+        step(
+            "update_gitmodules_branch",
+            &["check_submodule_updated"],
+            |s| s.update_gitmodules_branch(),
+        ),
+        step(
+            "git_add",
+            &["update_lock", "update_gitmodules_branch"],
+            |s| s.git_add(),
+        ),
+        step(
+            "prepare_commit_message",
+            &[
+                "generate_changelog",
+                "update_lock",
+                "update_gitmodules_branch",
+            ],
+            |s| s.prepare_commit_message(),
+        ),
+        Step {
+            skip: |s| {
+                if s.cli.matches.values_of("pre-check").unwrap().eq(["skip"]) {
+                    Some("--pre-check skip".to_string())
+                } else {
+                    None
+                }
+            },
+            ..step("pre_check", &["update_lock"], |s| s.pre_check())
+        },
+        Step {
+            skip: |s| {
+                let tests: Vec<&str> = s
+                    .cli
+                    .matches
+                    .values_of("test")
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|test| test.split_whitespace())
+                    .collect();
+                if tests.contains(&"skip") {
+                    Some("`skip` specified".to_string())
+                } else if !s.cli.is_interactive() && tests.is_empty() {
+                    Some("use --test to test".to_string())
+                } else {
+                    None
+                }
+            },
+            warn_on_skip: true,
+            ..step("test", &["pre_check", "git_add", "check_msrv"], |s| {
+                s.test()
+            })
+        },
+        Step {
+            skip: skip_ci,
+            ..step("report_ci", &["check_ci", "test"], |s| s.report_ci())
+        },
+        Step {
+            skip: |s| {
+                let book_updated = s
+                    .updated_submodules()
+                    .any(|submodule| title::is_book(&submodule.path));
+                match &s.report.tests {
+                    _ if !book_updated => Some("no books were updated".to_string()),
+                    None => Some("the tests were skipped".to_string()),
+                    Some(tests) if tests.iter().any(|test| test == LINKCHECK_TEST) => {
+                        Some("the tests checked the links".to_string())
+                    }
+                    Some(_) => None,
+                }
+            },
+            ..step("check_links", &["test"], |s| s.check_links())
+        },
+        step(
+            "commit",
+            &[
+                "git_add",
+                "prepare_commit_message",
+                "report_ci",
+                "check_links",
+            ],
+            |s| s.commit(),
+        ),
+        Step {
+            skip: |s| {
+                if !s.cli.matches.is_present("create-pr") {
+                    Some("no --create-pr".to_string())
+                } else if s.refresh {
+                    Some("refreshing an existing branch".to_string())
+                } else if s.cli.is_local_only() {
+                    Some("--local-only".to_string())
+                } else {
+                    None
+                }
+            },
+            ..step("create_pr", &["commit"], |s| s.create_pr())
+        },
+        Step {
+            skip: |s| {
+                if s.cli.matches.is_present("create-pr") {
+                    Some("using --create-pr".to_string())
+                } else if s.refresh {
+                    Some("refreshing an existing branch".to_string())
+                } else if s.cli.is_local_only() {
+                    Some("--local-only".to_string())
+                } else {
+                    None
+                }
+            },
+            ..step("finish_manual_pr", &["commit"], |s| s.finish_manual_pr())
+        },
+        Step {
+            skip: |s| {
+                if !s.cli.matches.is_present("tracking-issue") {
+                    Some("no --tracking-issue".to_string())
+                } else if s.refresh {
+                    Some("refreshing an existing branch".to_string())
+                } else if s.cli.is_local_only() {
+                    Some("--local-only".to_string())
+                } else {
+                    None
+                }
+            },
+            ..step("tracking_issue", &["create_pr", "finish_manual_pr"], |s| {
+                s.tracking_issue()
+            })
+        },
+    ]
+}
+
+fn skip_ci(s: &SubUp<'_>) -> Option<String> {
+    if s.cli.matches.is_present("skip-ci-check") {
+        Some("--skip-ci-check".to_string())
+    } else {
+        None
+    }
+}
+
+/// A submodule and the number of new commits on its branch.
+struct Outdated {
+    path: String,
+    /// The `branch` from `.gitmodules`, if set.
+    branch: Option<String>,
+    /// How many commits it is behind.
+    behind: usize,
+}
+
+impl Outdated {
+    /// The `[rev:]path` argument to update it.
+    fn arg(&self) -> String {
+        match &self.branch {
+            Some(branch) => format!("{}:{}", branch, self.path),
+            None => self.path.clone(),
+        }
+    }
+
+    /// How it is shown in the picker of submodules to update.
+    fn label(&self) -> String {
+        match self.behind {
+            0 => format!("{} (up to date)", self.path),
+            1 => format!("{} (1 commit behind)", self.path),
+            behind => format!("{} ({} commits behind)", self.path, behind),
+        }
+    }
+}
+
+/// The number of fetches to run at once, from `--jobs`.
+fn jobs(cli: &Cli<'_>) -> Result<usize, Error> {
+    match cli.matches.value_of("jobs") {
+        Some(jobs) => match jobs.parse::<usize>() {
+            Ok(0) | Err(_) => bail!("Invalid --jobs `{}`, expected a positive number", jobs),
+            Ok(jobs) => Ok(jobs),
+        },
+        None => Ok(usize::MAX),
+    }
+}
+
+/// Submodules whose pinned commit is behind their branch (the `branch` in
+/// `.gitmodules`, or the remote's default branch).
+///
+/// Submodules that are not initialized are skipped.
+fn outdated_submodules(cli: &Cli<'_>) -> Result<Vec<Outdated>, Error> {
+    let mut submodules = all_submodules_behind(cli)?;
+    submodules.retain(|submodule| submodule.behind > 0);
+    Ok(submodules)
+}
+
+/// Every initialized submodule, with how many commits its pinned commit is
+/// behind its branch.
+fn all_submodules_behind(cli: &Cli<'_>) -> Result<Vec<Outdated>, Error> {
+    let jobs = jobs(cli)?;
+    let mut fetches: VecDeque<(String, Option<String>, Spawned)> = VecDeque::new();
+    let mut fetched = Vec::new();
+    for (name, path) in repo::submodule_entries(".")? {
+        if !Path::new(&path).join(".git").exists() {
+            continue;
+        }
+        if fetches.len() == jobs {
+            let (path, branch, fetch) = fetches.pop_front().unwrap();
+            fetch.wait()?;
+            fetched.push((path, branch));
+        }
+        cli.info(&format!("Fetching `{}`.", path))?;
+        let fetch = cli
+            .git("fetch --quiet origin")
+            .dir(&path)
+            .spawn(format!("Failed to fetch in module `{}`.", path))?;
+        let branch = repo::submodule_branch(".", &name)?.filter(|branch| branch != ".");
+        fetches.push_back((path, branch, fetch));
+    }
+    for (path, branch, fetch) in fetches {
+        fetch.wait()?;
+        fetched.push((path, branch));
+    }
+    let mut result = Vec::new();
+    for (path, branch) in fetched {
+        cli.git("remote set-head origin -a")
+            .dir(&path)
+            .quiet()
+            .read_only()
+            .run(format!("Failed to set-head in module `{}`.", path))?;
+        let pinned = repo::head_gitlink(".", &path)?;
+        let upstream = format!("origin/{}", branch.as_deref().unwrap_or("HEAD"));
+        let behind = cli
+            .git_cmd(["rev-list", "--count", &format!("{}..{}", pinned, upstream)])
+            .dir(&path)
+            .capture_stdout(format!("Failed to count new commits in `{}`.", path))?;
+        let behind: usize = behind.parse()?;
+        result.push(Outdated {
+            path,
+            branch,
+            behind,
+        });
+    }
+    Ok(result)
+}
+
+/// Parses `git submodule status` output into `(state, hash, path)` tuples.
+fn parse_submodule_status(output: &str) -> Vec<(char, String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let state = line.chars().next()?;
+            let (hash, rest) = line[state.len_utf8()..].split_once(' ')?;
+            let path = match rest.rfind(" (") {
+                Some(i) if rest.ends_with(')') => &rest[..i],
+                _ => rest,
+            };
+            Some((state, hash.to_string(), path.to_string()))
+        })
+        .collect()
+}
+
+/// Asks the user which submodules to update, showing how far behind each
+/// one is.
+fn pick_submodules(cli: &Cli<'_>) -> Result<Vec<String>, Error> {
+    if !cli.is_interactive() {
+        bail!("No submodules specified.");
+    }
+    cli.status("Checking how far behind the submodules are.")?;
+    let submodules = all_submodules_behind(cli)?;
+    if submodules.is_empty() {
+        bail!("There are no checked out submodules to update.");
+    }
+    let items: Vec<_> = submodules.iter().map(Outdated::label).collect();
+    let chosen = cli
+        .multi_select("Which submodules do you want to update?", &items)?
+        .unwrap_or_default();
+    if chosen.is_empty() {
+        bail!("No submodules selected.");
+    }
+    Ok(chosen.into_iter().map(|i| submodules[i].arg()).collect())
+}
+
+/// Like `resolve_submodule_path`, but asks which submodule was meant when
+/// `path` is ambiguous.
+fn pick_submodule_path(
+    cli: &Cli<'_>,
+    path: &str,
+    entries: &[(String, String)],
+) -> Result<String, Error> {
+    let candidates = submodule_candidates(path, entries);
+    if candidates.len() > 1 {
+        let prompt = format!("`{}` is ambiguous, which submodule do you mean?", path);
+        if let Some(i) = cli.select(&prompt, &candidates, None)? {
+            return Ok(candidates[i].to_string());
+        }
+    }
+    Ok(resolve_submodule_path(path, entries)?.to_string())
+}
+
+/// Selects every submodule that is behind its branch, for `--all-outdated`.
+fn all_outdated(cli: &Cli<'_>) -> Result<Vec<String>, Error> {
+    cli.status("Checking for out-of-date submodules.")?;
+    let outdated = outdated_submodules(cli)?;
+    if outdated.is_empty() {
+        cli.warning("All submodules are up to date, exiting...")?;
+        cli.exit_no_changes();
+    }
+    let lines: Vec<_> = outdated
+        .iter()
+        .map(|o| match &o.branch {
+            Some(branch) => format!("{} ({} commits on {})", o.path, o.behind, branch),
+            None => format!("{} ({} commits)", o.path, o.behind),
+        })
+        .collect();
+    cli.boxed("Outdated submodules", &lines)?;
+    if cli.is_interactive() && !cli.confirm("Update all of these?", true)? {
+        bail!("Aborting...");
+    }
+    Ok(outdated.iter().map(Outdated::arg).collect())
+}
+
+/// Determine the head branch name to use.
+fn up_branch(cli: &Cli<'_>, rust_branch: &str, submodule_args: &[String]) -> String {
+    if let Some(branch) = cli.matches.value_of("up-branch") {
+        branch.to_string()
+    } else {
+        let paths: Vec<&str> = submodule_args.iter().map(|arg| arg.as_str()).collect();
+        title::branch_name(rust_branch, &paths)
+    }
+}
+
+/// Determine the base branch name to use (master/beta/stable).
+fn rust_branch(cli: &Cli<'_>) -> Result<String, Error> {
+    if let Some(branch) = cli.matches.value_of("rust-branch") {
+        Ok(branch.to_string())
+    } else {
+        let branch = repo::current_branch(".")?;
+        if !["master", "beta", "stable"].iter().any(|b| *b == branch) {
+            cli.warning(&format!(
+                "Current branch `{}` is not master/beta/stable.",
+                branch
+            ))?;
+            let branch = cli.input("Which base branch do you want to use?", Some("master"))?;
+            if let Some(branch) = branch {
+                return Ok(branch);
+            } else {
+                cli.warning("Use `--rust-branch` to explicitly specify the base branch.")?;
+                cli.exit_err();
+            }
+        }
+        Ok(branch)
+    }
+}
+
+/// The command line used to run subup, quoted for a shell.
+fn invocation() -> String {
+    command_line(env::args())
+}
+
+/// `args` quoted for a shell, with only the file name of the program, which
+/// is usually a local path.
+fn command_line(args: impl IntoIterator<Item = String>) -> String {
+    let mut args = args.into_iter();
+    let program = args.next().unwrap_or_default();
+    let program = Path::new(&program)
+        .file_name()
+        .map_or(program.clone(), |name| name.to_string_lossy().into_owned());
+    iter::once(program)
+        .chain(args)
+        .map(|arg| shell_quote(&arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes `arg` for a POSIX shell, unless it only has characters that are
+/// never special.
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The first 8 characters of `hash`, or all of it if it is shorter.
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(8)]
+}
+
+/// Splits a `Name <email>` identity into its name and email.
+fn parse_identity(identity: &str) -> Result<(&str, &str), Error> {
+    identity
+        .strip_suffix('>')
+        .and_then(|s| s.split_once('<'))
+        .map(|(name, email)| (name.trim(), email.trim()))
+        .filter(|(name, email)| !name.is_empty() && !email.is_empty())
+        .ok_or_else(|| format_err!("Expected `Name <email>`, got `{}`", identity))
+}
+
+/// The `Cargo.lock` files tracked in the rust repo, which has several
+/// workspaces (the root, `library`, and tools outside of the root
+/// workspace). Lockfiles without a `Cargo.toml` next to them are skipped.
+fn tracked_locks(cli: &Cli<'_>) -> Result<Vec<String>, Error> {
+    let output = cli
+        .git_cmd(["ls-files", "--", "Cargo.lock", "*/Cargo.lock"])
+        .capture_stdout("Failed to list Cargo.lock files.")?;
+    Ok(output
+        .lines()
+        .filter(|path| Path::new(path).with_file_name("Cargo.toml").exists())
+        .map(|path| path.to_string())
+        .collect())
+}
+
+/// Names of the workspace members in `metadata`.
+fn member_names(metadata: &Metadata) -> BTreeSet<String> {
+    metadata
+        .packages
+        .iter()
+        .filter(|package| metadata.workspace_members.contains(&package.id))
+        .map(|package| package.name.clone())
+        .collect()
+}
+
+/// Path of the `Cargo.lock` of the workspace in `metadata`, relative to the
+/// current directory (the root of the rust repo).
+fn lock_path(metadata: &Metadata) -> Result<String, Error> {
+    let lock = metadata.workspace_root.join("Cargo.lock");
+    let cwd = env::current_dir()?;
+    let relative = lock.strip_prefix(&cwd).with_context(|| {
+        format!(
+            "Workspace root `{}` is outside of the repository `{}`.",
+            metadata.workspace_root.display(),
+            cwd.display()
+        )
+    })?;
+    Ok(relative.to_string_lossy().into_owned())
+}
+
+fn load_metadata(cli: &Cli<'_>) -> Result<Metadata, Error> {
+    wait_metadata(start_metadata(cli)?)
+}
+
+/// Starts `cargo metadata` in the background, use `wait_metadata` for the
+/// result.
+fn start_metadata(cli: &Cli<'_>) -> Result<Spawned, Error> {
+    cli.cargo("metadata --format-version 1 --no-deps")
+        .spawn("Failed to load cargo metadata.")
+}
+
+fn wait_metadata(metadata: Spawned) -> Result<Metadata, Error> {
+    let output = metadata.wait()?;
+    serde_json::from_str(&output.stdout).context("Failed to parse cargo metadata.")
+}
+
+fn doit(cli: &Cli<'_>) -> Result<(), Error> {
+    add_event_log(cli)?;
+    if cli.matches.is_present("resume") {
+        return SubUp::resume(cli)?.run();
+    }
+    let mut notes = Vec::new();
+    let mut submodule_args = match cli.matches.values_of("submodules") {
+        Some(values) => values.map(|value| value.to_string()).collect(),
+        None => match cli.matches.value_of("plan-url") {
+            Some(url) => {
+                let schedule = schedule::fetch(cli, url)?;
+                let owners: Vec<_> = schedule
+                    .iter()
+                    .filter_map(|(tool, entry)| {
+                        let owner = entry.owner.as_ref()?;
+                        Some(format!("{} ({})", owner, tool))
+                    })
+                    .collect();
+                let mut note = format!("Revisions are from the sync schedule at {}.", url);
+                if !owners.is_empty() {
+                    note.push_str(&format!(" Owners: {}.", owners.join(", ")));
+                }
+                notes.push(note);
+                schedule
+                    .iter()
+                    .map(|(tool, entry)| entry.submodule_arg(tool))
+                    .collect()
+            }
+            None if cli.matches.is_present("books") => Vec::new(),
+            None if cli.matches.is_present("explain-plan") => Vec::new(),
+            None if cli.matches.is_present("all-outdated") => all_outdated(cli)?,
+            None if cli.matches.is_present("josh") => Vec::new(),
+            None => pick_submodules(cli)?,
+        },
+    };
+    if cli.matches.is_present("books") {
+        submodule_args.push(BOOKS_PATTERN.to_string());
+    }
+    // Josh subtrees are updated along with the submodules.
+    for value in cli.matches.values_of("josh").into_iter().flatten() {
+        let spec = value.rsplit_once('=').map_or(value, |(spec, _)| spec);
+        submodule_args.push(spec.to_string());
+    }
+    // Expand patterns first so the branch name and saved state list the
+    // actual submodules.
+    let excludes: Vec<&str> = cli
+        .matches
+        .values_of("exclude")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    let submodule_args =
+        expand_submodule_args(&submodule_args, &excludes, &repo::submodule_entries(".")?)?;
+    update(cli, submodule_args, notes)
+}
+
+/// `subup from-tool`: updates the submodule of the tool checked out in the
+/// current directory to the tool's HEAD, from the rust checkout given with
+/// `--rust-checkout`.
+fn from_tool(cli: &Cli<'_>) -> Result<(), Error> {
+    add_event_log(cli)?;
+    let hash = cli
+        .git("rev-parse HEAD")
+        .capture_stdout("Failed to get the HEAD of the tool.")?;
+    let repo = log::git_origin(cli, ".")?;
+    let remote_branches = cli
+        .git_cmd(["branch", "--remotes", "--contains", &hash])
+        .capture_stdout("Failed to check if HEAD has been pushed.")?;
+    if remote_branches.is_empty() {
+        bail!(
+            "HEAD ({}) is not on any remote branch, push it before updating rust.",
+            hash
+        );
+    }
+    let checkout = cli.matches.value_of("rust-checkout").unwrap();
+    env::set_current_dir(checkout)
+        .with_context(|| format!("Failed to change to rust checkout `{}`.", checkout))?;
+    let mut paths = Vec::new();
+    for (name, path) in repo::submodule_entries(".")? {
+        let url = cli
+            .git_cmd([
+                "config",
+                "--file",
+                ".gitmodules",
+                "--get",
+                &format!("submodule.{}.url", name),
+            ])
+            .capture_stdout("Failed to get submodule url.")?;
+        if log::github_repo(&url).ok().as_ref() == Some(&repo) {
+            paths.push(path);
+        }
+    }
+    let path = match paths.as_slice() {
+        [path] => path,
+        [] => bail!("No submodule in `{}` is from {}.", checkout, repo),
+        _ => bail!(
+            "Multiple submodules in `{}` are from {}: {}",
+            checkout,
+            repo,
+            paths.join(", ")
+        ),
+    };
+    cli.status(&format!("Updating {} to {}.", path, hash))?;
+    update(cli, vec![format!("{}:{}", hash, path)], Vec::new())
+}
+
+/// The josh subtrees given with `--josh`, as `path => owner/name`.
+fn josh_subtrees(cli: &Cli<'_>) -> Result<HashMap<String, String>, Error> {
+    let mut subtrees = HashMap::new();
+    for value in cli.matches.values_of("josh").into_iter().flatten() {
+        let (spec, repo) = value.rsplit_once('=').ok_or_else(|| {
+            format_err!(
+                "Expected `[REV:]PATH=OWNER/NAME` for --josh, got `{}`",
+                value
+            )
+        })?;
+        let spec = parse_submodule_spec(spec)?;
+        subtrees.insert(spec.path, repo.to_string());
+    }
+    Ok(subtrees)
+}
+
+/// The commits given with `--cherry-pick`, as `path => commits`.
+fn cherry_picks(cli: &Cli<'_>) -> Result<HashMap<String, Vec<String>>, Error> {
+    let mut picks: HashMap<String, Vec<String>> = HashMap::new();
+    for value in cli.matches.values_of("cherry-pick").into_iter().flatten() {
+        let (path, commits) = value
+            .split_once('=')
+            .map(|(path, commits)| (path.trim(), commits))
+            .filter(|(path, _)| !path.is_empty())
+            .ok_or_else(|| {
+                format_err!(
+                    "Expected `PATH=COMMIT[,COMMIT...]` for --cherry-pick, got `{}`",
+                    value
+                )
+            })?;
+        let commits: Vec<String> = commits
+            .split(',')
+            .map(str::trim)
+            .filter(|commit| !commit.is_empty())
+            .map(str::to_string)
+            .collect();
+        if commits.is_empty() {
+            bail!("--cherry-pick `{}` does not list any commits", value);
+        }
+        picks.entry(path.to_string()).or_default().extend(commits);
+    }
+    Ok(picks)
+}
+
+/// The URL of `owner`'s fork of `upstream`, using ssh if `upstream` does.
+fn fork_url(upstream: &RemoteUrl, owner: &str) -> String {
+    match upstream.scheme {
+        Scheme::Ssh => format!("git@{}:{}/{}.git", upstream.host, owner, upstream.repo),
+        _ => format!("https://{}/{}/{}.git", upstream.host, owner, upstream.repo),
+    }
+}
+
+/// The variables given with `--test-env`, as `KEY=VAL` for every update or
+/// `SUBMODULE:KEY=VAL` when one of `updated` is SUBMODULE.
+fn test_env(cli: &Cli<'_>, updated: &[&str]) -> Result<Vec<(String, String)>, Error> {
+    let mut env = Vec::new();
+    for value in cli.matches.values_of("test-env").into_iter().flatten() {
+        let (name, val) = value.split_once('=').ok_or_else(|| {
+            format_err!(
+                "Expected `[SUBMODULE:]KEY=VAL` for --test-env, got `{}`",
+                value
+            )
+        })?;
+        let (path, key) = match name.rsplit_once(':') {
+            Some((path, key)) => (Some(path), key),
+            None => (None, name),
+        };
+        if key.is_empty() || path == Some("") {
+            bail!(
+                "Expected `[SUBMODULE:]KEY=VAL` for --test-env, got `{}`",
+                value
+            );
+        }
+        if path.is_none_or(|path| updated.contains(&path)) {
+            env.push((key.to_string(), val.to_string()));
+        }
+    }
+    Ok(env)
+}
+
+/// Adds the `--event-log` sink, if given.
+fn add_event_log(cli: &Cli<'_>) -> Result<(), Error> {
+    if let Some(path) = cli.matches.value_of("event-log") {
+        cli.events().add(JsonLines::create(Path::new(path))?);
+    }
+    Ok(())
+}
+
+/// Runs the update steps for the given `[rev:]path` submodule arguments.
+fn update(cli: &Cli<'_>, submodule_args: Vec<String>, notes: Vec<String>) -> Result<(), Error> {
+    check_identities(cli)?;
+    let rust_branch = rust_branch(cli)?;
+    let up_branch = up_branch(cli, &rust_branch, &submodule_args);
+    run_update(cli, submodule_args, notes, rust_branch, up_branch, false)
+}
+
+/// Checks the `--author` and `--committer` values before starting.
+fn check_identities(cli: &Cli<'_>) -> Result<(), Error> {
+    for identity in &["author", "committer"] {
+        if let Some(value) = cli.matches.value_of(identity) {
+            parse_identity(value).with_context(|| format!("Invalid --{}", identity))?;
+        }
+    }
+    Ok(())
+}
+
+fn run_update(
+    cli: &Cli<'_>,
+    submodule_args: Vec<String>,
+    notes: Vec<String>,
+    rust_branch: String,
+    up_branch: String,
+    refresh: bool,
+) -> Result<(), Error> {
+    let mut s = SubUp {
+        cli,
+        submodule_args,
+        submodules: Vec::new(),
+        rust_branch,
+        up_branch,
+        orig_metadata: None,
+        commit_title: None,
+        report: Report::default(),
+        notes,
+        gitmodules_changed: false,
+        pending_metadata: None,
+        pending_ci: Vec::new(),
+        completed: Vec::new(),
+        affected_tests: Vec::new(),
+        refresh,
+        push_remote: None,
+        failed_tests: Vec::new(),
+    };
+    s.run()
+}
+
+/// `subup refresh`: redoes the update of an existing branch on the latest
+/// upstream, for the submodules the branch updates.
+fn refresh(cli: &Cli<'_>) -> Result<(), Error> {
+    add_event_log(cli)?;
+    check_identities(cli)?;
+    if cli.matches.is_present("up-branch") {
+        bail!("--up-branch cannot be used with refresh, give the branch to refresh instead.");
+    }
+    let branch = match cli.matches.value_of("branch") {
+        Some(branch) => branch.to_string(),
+        None => repo::current_branch(".")?,
+    };
+    if !repo::has_branch(".", &branch)? {
+        bail!(
+            "Branch `{}` does not exist, there is nothing to refresh.",
+            branch
+        );
+    }
+    let rust_branch = base_branch(cli, &branch);
+    if branch == rust_branch {
+        bail!(
+            "`{}` is the base branch, give the name of the update branch to refresh.",
+            branch
+        );
+    }
+    cli.status("Fetching upstream.")?;
+    cli.git_cmd(["fetch", cli.upstream_remote()])
+        .read_only()
+        .run("Failed to fetch upstream.")?;
+    // Compare with where the branch started, so that submodules updated
+    // upstream since then are not included.
+    let fork_point = cli
+        .git_cmd(["merge-base", &cli.upstream_branch(&rust_branch), &branch])
+        .capture_stdout(format!("Failed to find where `{}` started.", branch))?;
+    let mut submodule_args = Vec::new();
+    for (name, path) in repo::submodule_entries(".")? {
+        let base = repo::try_hash(".", &format!("{}:{}", fork_point, path))?;
+        let updated = repo::try_hash(".", &format!("{}:{}", branch, path))?;
+        if updated.is_none() || base == updated {
+            continue;
+        }
+        submodule_args.push(match repo::submodule_branch(".", &name)? {
+            Some(rev) => format!("{}:{}", rev, path),
+            None => path,
+        });
+    }
+    if submodule_args.is_empty() {
+        bail!(
+            "Branch `{}` does not update any submodules of `{}`.",
+            branch,
+            cli.upstream_branch(&rust_branch)
+        );
+    }
+    cli.status(&format!(
+        "Refreshing `{}` with {}.",
+        branch,
+        submodule_args.join(" ")
+    ))?;
+    run_update(cli, submodule_args, Vec::new(), rust_branch, branch, true)
+}
+
+/// `subup msg`: generates the commit message for the checked out
+/// submodules.
+fn msg(cli: &Cli<'_>) -> Result<(), Error> {
+    cli.status(&format!("Generating {}", log::COMMIT_MSG_PATH))?;
+    let submodules: Vec<&str> = cli.matches.values_of("submodules").unwrap().collect();
+    log::generate_commit_from_branch(cli, cli.matches.value_of("branch").unwrap(), &submodules)?;
+    if cli.matches.is_present("compare-upstream") {
+        compare_with_pr(cli)?;
+    }
+    cli.status("Complete")?;
+    Ok(())
+}
+
+/// Shows how the generated message differs from the body of the open PR of
+/// the current branch.
+fn compare_with_pr(cli: &Cli<'_>) -> Result<(), Error> {
+    let branch = repo::current_branch(".")?;
+    let upstream = forge::remote_repo(cli, cli.upstream_remote())?;
+    let fork = forge::remote_repo(cli, &push_remote_of(cli, &branch)?)?;
+    let owner = fork.split('/').next().unwrap();
+    let head = format!("{}:{}", owner, branch);
+    cli.status(&format!("Looking for the PR from `{}`.", head))?;
+    let (url, body) = GitHubApi::new(cli)?
+        .open_pr_from(&upstream, &head)?
+        .ok_or_else(|| format_err!("No open PR from `{}` in {}.", head, upstream))?;
+    let message = fs::read_to_string(log::COMMIT_MSG_PATH)
+        .with_context(|| format!("Failed to read {}", log::COMMIT_MSG_PATH))?;
+    // Bodies edited on GitHub have CRLF line endings. Only the changelog
+    // is generated by `subup msg`, not what `subup update` adds after it.
+    let body = log::strip_pr_extras(&body.replace("\r\n", "\n"));
+    let lines = log::diff_lines(&body, &message);
+    if lines.is_empty() {
+        cli.info(&format!("The body of {} is the same.", url))?;
+        return Ok(());
+    }
+    cli.boxed(&format!("{} -> {}", url, log::COMMIT_MSG_PATH), &lines)?;
+    cli.info(&format!(
+        "Run `gh pr edit {} --body-file {}` to replace the body.",
+        url,
+        log::COMMIT_MSG_PATH
+    ))
+}
+
+/// The remote the update branch `branch` is pushed to: `--push-remote`,
+/// else the one saved by an update in progress or configured for the
+/// branch, else `origin`.
+fn push_remote_of(cli: &Cli<'_>, branch: &str) -> Result<String, Error> {
+    if let Some(remote) = cli.matches.value_of("push-remote") {
+        return Ok(remote.to_string());
+    }
+    if let Ok(state) = fs::read_to_string(STATE_PATH) {
+        let state: State = serde_json::from_str(&state)
+            .with_context(|| format!("Failed to parse {}", STATE_PATH))?;
+        if state.up_branch == branch {
+            if let Some(remote) = state.push_remote {
+                return Ok(remote);
+            }
+        }
+    }
+    let remote = cli
+        .git_cmd([
+            "config",
+            "--default",
+            "origin",
+            &format!("branch.{}.remote", branch),
+        ])
+        .capture_stdout("Failed to get the branch remote.")?;
+    if remote.is_empty() {
+        Ok("origin".to_string())
+    } else {
+        Ok(remote)
+    }
+}
+
+/// The base branch of the update branch `branch`.
+fn base_branch(cli: &Cli<'_>, branch: &str) -> String {
+    match cli.matches.value_of("rust-branch") {
+        Some(base) => base.to_string(),
+        None => title::branch_base(branch).to_string(),
+    }
+}
+
+/// `subup status`: shows what the current update branch contains.
+fn status(cli: &Cli<'_>) -> Result<(), Error> {
+    let branch = repo::current_branch(".")?;
+    let upstream = cli.upstream_branch(&base_branch(cli, &branch));
+    let mut lines = vec![format!("Branch: {} (based on {})", branch, upstream)];
+    let commits = cli
+        .git_cmd(["log", "--oneline", &format!("{}..HEAD", upstream)])
+        .capture_stdout("Failed to get commits.")?;
+    if commits.is_empty() {
+        lines.push("Commits: none".to_string());
+    } else {
+        lines.push("Commits:".to_string());
+        lines.extend(commits.lines().map(|line| format!("  {}", line)));
+    }
+    for (_name, path) in repo::submodule_entries(".")? {
+        let base = repo::try_hash(".", &format!("{}:{}", upstream, path))?;
+        let committed = repo::try_hash(".", &format!("HEAD:{}", path))?;
+        let checked_out = if Path::new(&path).join(".git").exists() {
+            repo::try_hash(&path, "HEAD")?
+        } else {
+            None
+        };
+        let (base, committed) = match (base, committed) {
+            (Some(base), Some(committed)) => (base, committed),
+            _ => continue,
+        };
+        let mut line = format!(
+            "{}: {} -> {}",
+            path,
+            short_hash(&base),
+            short_hash(&committed)
+        );
+        match checked_out {
+            Some(hash) if hash != committed => {
+                line.push_str(&format!(
+                    " (checked out {}, not committed)",
+                    short_hash(&hash)
+                ));
+            }
+            _ if base == committed => continue,
+            _ => {}
+        }
+        lines.push(line);
+    }
+    let root_lock = lock_path(&load_metadata(cli)?)?;
+    for lock_path in tracked_locks(cli)? {
+        let lock_changed = !cli
+            .git_cmd(["diff", "--quiet", &upstream, "--", &lock_path])
+            .status(format!("Failed to check {}.", lock_path))?
+            .success();
+        // Only the root lockfile is always shown.
+        if lock_changed || lock_path == root_lock {
+            lines.push(format!(
+                "{}: {}",
+                lock_path,
+                if lock_changed { "changed" } else { "unchanged" }
+            ));
+        }
+    }
+    let head = repo::try_hash(".", "HEAD")?;
+    let remote = push_remote_of(cli, &branch)?;
+    let pushed = repo::try_hash(".", &format!("{}/{}", remote, branch))?;
+    lines.push(format!(
+        "Pushed: {}",
+        match pushed {
+            None => "no",
+            Some(pushed) if Some(&pushed) == head.as_ref() => "yes",
+            Some(_) => "yes, but differs from HEAD",
+        }
+    ));
+    cli.boxed("Status", &lines)
+}
+
+/// Removes the commit messages and saved state written by an update.
+fn remove_generated_files(cli: &Cli<'_>) -> Result<(), Error> {
+    for path in &[log::COMMIT_MSG_PATH, log::COMMIT_MSG_PLAIN_PATH, STATE_PATH] {
+        if Path::new(path).exists() {
+            if cli.is_dry_run() {
+                cli.info(&format!("Would remove {}", path))?;
+            } else {
+                fs::remove_file(path).with_context(|| format!("Failed to remove {}", path))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `subup clean`: removes the files written by an update, leaving the
+/// branches alone.
+fn clean(cli: &Cli<'_>) -> Result<(), Error> {
+    remove_generated_files(cli)?;
+    cli.status("Cleaned.")?;
+    Ok(())
+}
+
+/// `subup check`: lists the submodules that are behind their branch,
+/// without changing anything.
+fn check(cli: &Cli<'_>) -> Result<(), Error> {
+    cli.status("Checking for out-of-date submodules.")?;
+    let outdated = outdated_submodules(cli)?;
+    if outdated.is_empty() {
+        cli.status("All submodules are up to date.")?;
+        cli.exit_no_changes();
+    }
+    let lines: Vec<_> = outdated
+        .iter()
+        .map(|o| format!("{} ({} commits behind)", o.arg(), o.behind))
+        .collect();
+    cli.boxed("Outdated submodules", &lines)
+}
+
+/// `subup doctor`: checks that the tools and the rust checkout are set up
+/// for an update.
+fn doctor(cli: &Cli<'_>) -> Result<(), Error> {
+    let mut lines = Vec::new();
+    let mut problems = 0;
+    // Optional things are only needed for some options.
+    let mut report = |ok: bool, optional: bool, what: &str, fix: &str| {
+        if ok {
+            lines.push(format!("ok: {}", what));
+        } else if optional {
+            lines.push(format!("optional: {} ({})", what, fix));
+        } else {
+            problems += 1;
+            lines.push(format!("missing: {} ({})", what, fix));
+        }
+    };
+    let works = |program: &str, args: &[&str]| {
+        cli.runner(program, args)
+            .read_only()
+            .status(format!("Failed to run {}.", program))
+            .is_ok_and(|status| status.success())
+    };
+    report(works("git", &["--version"]), false, "git", "install git");
+    report(
+        works("cargo", &["--version"]),
+        false,
+        "cargo",
+        "install rustup",
+    );
+    report(
+        Path::new("x.py").exists() && Path::new(".gitmodules").exists(),
+        false,
+        "rust checkout in the current directory",
+        "run subup in the root of a rust checkout",
+    );
+    let push_remote = cli.matches.value_of("push-remote").unwrap_or("origin");
+    let mut remotes = vec![cli.upstream_remote(), push_remote];
+    remotes.dedup();
+    for remote in remotes {
+        report(
+            works("git", &["remote", "get-url", remote]),
+            false,
+            &format!("`{}` remote", remote),
+            &format!("add it with `git remote add {} URL`", remote),
+        );
+    }
+    report(
+        works("gh", &["--version"]),
+        true,
+        "gh, to create PRs with --create-pr",
+        "install the GitHub CLI, or use --pr-backend=api",
+    );
+    report(
+        credentials::github_token(cli, "github.com").is_ok(),
+        true,
+        "GitHub token, for the CI checks and the API",
+        "set GITHUB_TOKEN or run `gh auth login`",
+    );
+    cli.boxed("Doctor", &lines)?;
+    if problems > 0 {
+        bail!("Found {} problem(s).", problems);
+    }
+    Ok(())
+}
+
+/// `subup abort`: deletes the current update branch and restores the
+/// submodules.
+fn abort(cli: &Cli<'_>) -> Result<(), Error> {
+    let branch = repo::current_branch(".")?;
+    if !branch.starts_with("update-") {
+        bail!(
+            "Current branch `{}` does not look like a branch created by subup.",
+            branch
+        );
+    }
+    let base = base_branch(cli, &branch);
+    // Before the branch and its saved state are deleted.
+    let remote = push_remote_of(cli, &branch)?;
+    cli.warning(&format!(
+        "This will delete branch `{}` and discard all changes in the working tree.",
+        branch
+    ))?;
+    if !cli.confirm("Do you want to continue?", true)? {
+        cli.exit_err();
+    }
+    cli.git("reset --hard").run("Failed to reset.")?;
+    let has_local_base = repo::has_branch(".", &base)?;
+    let checkout = if has_local_base {
+        vec!["checkout".to_string(), base.clone()]
+    } else {
+        vec![
+            "checkout".to_string(),
+            "--detach".to_string(),
+            cli.upstream_branch(&base),
+        ]
+    };
+    cli.git_cmd(&checkout)
+        .run(format!("Failed to check out `{}`.", base))?;
+    cli.git_cmd(["branch", "-D", &branch])
+        .run(format!("Failed to delete branch `{}`.", branch))?;
+    cli.git("submodule update --init --recursive")
+        .run("Failed to init/update submodules.")?;
+    remove_generated_files(cli)?;
+    if repo::try_hash(".", &format!("{}/{}", remote, branch))?.is_some() {
+        cli.info(&format!(
+            "The branch was pushed, delete it with `git push {} --delete {}`.",
+            remote, branch
+        ))?;
+    }
+    cli.status("Aborted.")?;
+    Ok(())
+}
+
+/// Arguments for `subup update`.
+fn update_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = vec![
+        Arg::with_name("submodules")
+            .help(
+                "Submodules to update (src/tools/cargo, etc.), \
+                     prefix with `branchname:` to specify the branch to use. \
+                     The branch may also be a revision, `@{DATE}` for the last \
+                     commit before a date, or `latest-release-tag`. \
+                     The path may be a pattern such as `src/tools/*`. \
+                     If not given, choose from all submodules.",
+            )
+            .multiple(true),
+        Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .value_name("NAME")
+            .help(
+                "Use the options of the named profile in subup.toml (in the \
+                     current directory or ~/.config/subup), options given on \
+                     the command line take precedence",
+            ),
+        Arg::with_name("blocked-by")
+            .long("blocked-by")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("SUBMODULE=PRS|QUERY")
+            .help(
+                "Don't update SUBMODULE (or `*` for any) while any of the given \
+                     PRs (`owner/name#N`, comma-separated) or PRs found by the \
+                     GitHub search QUERY are open",
+            ),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PATH")
+            .help("Don't update the given submodule, which may be a pattern"),
+        Arg::with_name("plan-url")
+            .long("plan-url")
+            .takes_value(true)
+            .value_name("URL")
+            .conflicts_with("submodules")
+            .help(
+                "Update the submodules listed in the tool sync schedule \
+                     at URL, a TOML or JSON file mapping each submodule to \
+                     its `rev` and `owner`",
+            ),
+        Arg::with_name("josh")
+            .long("josh")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("[REV:]PATH=OWNER/NAME")
+            .help(
+                "Also update the subtree at PATH, synced with josh from the \
+                     GitHub repository OWNER/NAME",
+            ),
+        Arg::with_name("cherry-pick")
+            .long("cherry-pick")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PATH=COMMIT[,COMMIT...]")
+            .conflicts_with_all(&["since", "max-commits", "latest-green"])
+            .help(
+                "Backport the given commits onto the branch the submodule at \
+                     PATH is updated to (such as `rust-1.50.0:PATH`), push it, \
+                     and update to the result",
+            ),
+        Arg::with_name("josh-proxy")
+            .long("josh-proxy")
+            .takes_value(true)
+            .value_name("URL")
+            .default_value("http://localhost:42042")
+            .help("The josh proxy used for --josh"),
+        Arg::with_name("all-outdated")
+            .long("all-outdated")
+            .conflicts_with_all(&["submodules", "plan-url"])
+            .help(
+                "Update every submodule that is behind its branch (the \
+                     branch in .gitmodules, or the remote's default branch)",
+            ),
+        Arg::with_name("books")
+            .long("books")
+            .conflicts_with_all(&["plan-url", "all-outdated"])
+            .help(
+                "Update every book in src/doc (along with any other given \
+                     submodules), and check their links",
+            ),
+        Arg::with_name("resume")
+            .long("resume")
+            .conflicts_with_all(&["submodules", "plan-url", "all-outdated", "books"])
+            .help(
+                "Continue a failed update from the step that failed, using \
+                     the progress saved in .subup-state.json",
+            ),
+        Arg::with_name("rerun-all-tests")
+            .long("rerun-all-tests")
+            .requires("resume")
+            .help(
+                "With --resume, run all tests again instead of only the ones \
+                     that failed",
+            ),
+    ];
+    args.extend(pipeline_args());
+    args
+}
+
+fn jobs_arg() -> Arg<'static, 'static> {
+    Arg::with_name("jobs")
+        .long("jobs")
+        .short("j")
+        .takes_value(true)
+        .value_name("N")
+        .help("Fetch at most N submodules at once (default: all of them)")
+}
+
+fn fail_if_no_changes_arg() -> Arg<'static, 'static> {
+    Arg::with_name("fail-if-no-changes")
+        .long("fail-if-no-changes")
+        .help(
+            "Exit with code 2 instead of 0 when there is nothing to \
+             update (failed tests exit with 3)",
+        )
+}
+
+/// Options for `log::generate_commit`.
+fn changelog_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("strip-commit-prefixes")
+            .long("strip-commit-prefixes")
+            .help("Remove conventional-commit prefixes such as `fix:` from the changelog"),
+        Arg::with_name("collapse-after")
+            .long("collapse-after")
+            .takes_value(true)
+            .value_name("N")
+            .help(
+                "For submodules with more than N commits, show only the \
+                     count, dates, and a compare link in the changelog",
+            ),
+    ]
+}
+
+/// Arguments that control the update steps, shared by `subup update` and
+/// `subup from-tool`.
+fn pipeline_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = vec![
+        Arg::with_name("verbose")
+            .long("verbose")
+            .short("v")
+            .help("Verbose output"),
+        Arg::with_name("event-log")
+            .long("event-log")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write every event (steps, commands, warnings) to FILE as JSON lines"),
+        Arg::with_name("json")
+            .long("json")
+            .help("Print every event as a line of JSON instead of text, for automation"),
+        Arg::with_name("explain-plan")
+            .long("explain-plan")
+            .help("Print the steps that would run, and which are skipped, then exit"),
+        Arg::with_name("dry-run").long("dry-run").help(
+            "Print the commands that would change anything instead of \
+                     running them, and show the commit message. Commands that \
+                     only read (or fetch) still run, and the current HEAD is \
+                     used in place of the new branch.",
+        ),
+        fail_if_no_changes_arg(),
+        Arg::with_name("local-only").long("local-only").help(
+            "Never change anything on a remote: don't push, create a PR, \
+                     or post comments",
+        ),
+        Arg::with_name("confirm-remote")
+            .long("confirm-remote")
+            .conflicts_with("local-only")
+            .help(
+                "Allow pushing, creating a PR, and posting comments without \
+                     asking (required when not run in a terminal)",
+            ),
+        Arg::with_name("allow-changes")
+            .long("allow-changes")
+            .help("Allow command to run with existing git changes"),
+        Arg::with_name("no-submodule-dirty-check")
+            .long("no-submodule-dirty-check")
+            .takes_value(true)
+            .value_name("PATH")
+            .min_values(0)
+            .use_delimiter(true)
+            // Otherwise the submodules to update are taken as paths.
+            .require_equals(true)
+            .help(
+                "Ignore local changes inside the given submodules \
+                     (`--no-submodule-dirty-check=PATH,...`, or all submodules \
+                     not being updated) when checking the working tree",
+            ),
+        Arg::with_name("always-reset")
+            .long("always-reset")
+            .help("Always reset the working tree, even if there are changes (DANGEROUS!)"),
+        Arg::with_name("force")
+            .long("force")
+            .help("Don't ask for interactive confirmations"),
+        Arg::with_name("rust-branch")
+            .long("rust-branch")
+            .takes_value(true)
+            .help("The target rust branch (master/beta/stable)"),
+        Arg::with_name("up-branch")
+            .long("up-branch")
+            .takes_value(true)
+            .help("The branch name to create (defaults to update-{module})"),
+        Arg::with_name("allow-lock-change")
+            .long("allow-lock-change")
+            .help("Allow updating Cargo.lock in non-interactive mode."),
+        Arg::with_name("test")
+            .long("test")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .help("Always run the given tests on modified submodules."),
+        Arg::with_name("test-env")
+            .long("test-env")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("[SUBMODULE:]KEY=VAL")
+            .help(
+                "Set an environment variable for `./configure` and `x.py`, \
+                     only when SUBMODULE is updated if given",
+            ),
+        Arg::with_name("pre-check")
+            .long("pre-check")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .default_value("compiler")
+            .help(
+                "Paths to `x.py check` before testing when a submodule the \
+                     compiler builds against is updated (`skip` to disable)",
+            ),
+        Arg::with_name("pr-body-file")
+            .long("pr-body-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Use the given file for the commit and PR body instead of generating one"),
+        Arg::with_name("since")
+            .long("since")
+            .visible_alias("max-age")
+            .alias("until")
+            .takes_value(true)
+            .value_name("DATE")
+            .help(
+                "Cap the update at DATE, such as 2024-05-01, taking the newest \
+                     submodule commit made before it instead of the tip",
+            ),
+        jobs_arg(),
+        Arg::with_name("max-commits")
+            .long("max-commits")
+            .takes_value(true)
+            .value_name("N")
+            .help("Only take the oldest N new submodule commits"),
+        Arg::with_name("latest-green")
+            .long("latest-green")
+            .alias("require-green")
+            .help("Update to the newest commit with passing CI instead of the branch tip"),
+        Arg::with_name("allow-downgrade")
+            .long("allow-downgrade")
+            .help(
+                "Allow updating a submodule to an ancestor of its current commit, \
+                     which is committed as a revert",
+            ),
+        Arg::with_name("skip-ci-check")
+            .long("skip-ci-check")
+            .help("Don't check the CI status of the new submodule commits"),
+        Arg::with_name("commit-title")
+            .long("commit-title")
+            .alias("commit-message")
+            .takes_value(true)
+            .help("Commit title to use"),
+        Arg::with_name("author")
+            .long("author")
+            .takes_value(true)
+            .value_name("NAME <EMAIL>")
+            .help("Author to use for the commit"),
+        Arg::with_name("committer")
+            .long("committer")
+            .takes_value(true)
+            .value_name("NAME <EMAIL>")
+            .help("Committer to use for the commit"),
+        Arg::with_name("signoff-dco")
+            .long("signoff-dco")
+            .help("Add a Signed-off-by trailer and a DCO attestation to the PR body"),
+        Arg::with_name("no-trailers")
+            .long("no-trailers")
+            .help("Don't add Subup-Version and Subup-Command trailers to the commit"),
+        Arg::with_name("set-config")
+            .long("set-config")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Set the given config.toml option"),
+        Arg::with_name("create-pr")
+            .long("create-pr")
+            .help("Automatically create a PR (see --pr-backend)"),
+        Arg::with_name("merge-strategy")
+            .long("merge-strategy")
+            .takes_value(true)
+            .possible_values(&["ff-only"])
+            .help("Verify the branch is a single commit on the base branch before pushing"),
+        Arg::with_name("forge")
+            .long("forge")
+            .takes_value(true)
+            .possible_values(&["github", "gitlab"])
+            .help("Where to create the PR (defaults to detecting from the upstream url)"),
+        Arg::with_name("pr-backend")
+            .long("pr-backend")
+            .takes_value(true)
+            .possible_values(&["cli", "api"])
+            .default_value("cli")
+            .help(
+                "How to create the PR: with the forge's CLI (`gh` or `glab`), \
+                     or with the GitHub REST API and a token from GITHUB_TOKEN",
+            ),
+        Arg::with_name("preview-pr")
+            .long("preview-pr")
+            .help("Show the PR and ask for confirmation before creating it")
+            .requires("create-pr"),
+        Arg::with_name("full-changelog")
+            .long("full-changelog")
+            .takes_value(true)
+            .value_name("gist|PATH")
+            .help(
+                "When the PR body is too long, upload the full changelog as a \
+                     secret gist, or write it to PATH (such as a CI artifacts \
+                     directory), and link to it from the truncated body",
+            ),
+        Arg::with_name("full-changelog-url")
+            .long("full-changelog-url")
+            .takes_value(true)
+            .value_name("URL")
+            .requires("full-changelog")
+            .help("The link to the file written by --full-changelog PATH"),
+        Arg::with_name("push-to")
+            .long("push-to")
+            .takes_value(true)
+            .value_name("REMOTE[:NAMESPACE/]")
+            .help(
+                "Push the branch to REMOTE instead of origin, such as a shared \
+                     fork, under the NAMESPACE/ prefix if given",
+            ),
+        Arg::with_name("self-assign")
+            .long("self-assign")
+            .help("Self-assign the PR when created")
+            .requires("create-pr"),
+        Arg::with_name("self-approve")
+            .long("self-approve")
+            .takes_value(true)
+            .help("Automatically approve with the given bors command")
+            .requires("create-pr")
+            .requires("self-assign"),
+        Arg::with_name("pr-snippet")
+            .long("pr-snippet")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("SUBMODULE=TEXT")
+            .help(
+                "Add TEXT to the PR body when SUBMODULE (or `*` for any) is \
+                     updated, such as `@rustbot label +A-cargo` for triagebot",
+            ),
+        Arg::with_name("cc")
+            .long("cc")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("SUBMODULE=USER[,USER...]")
+            .help(
+                "Mention the given GitHub users in the PR body when SUBMODULE \
+                     (or `*` for any) is updated",
+            ),
+        Arg::with_name("no-review-assign")
+            .long("no-review-assign")
+            .help("Add `r? ghost` to the PR body so triagebot doesn't assign a reviewer"),
+        Arg::with_name("tracking-issue")
+            .long("tracking-issue")
+            .help(
+                "Open an issue (or comment on today's) in each updated tool's \
+                     repository with the hash range and PR link",
+            ),
+        Arg::with_name("emit")
+            .long("emit")
+            .takes_value(true)
+            .value_name("PATH")
+            .help(
+                "Write a JSON summary of the run to the given path, also with \
+                     the failing step and error if it fails",
+            ),
+    ];
+    #[cfg(feature = "tui")]
+    args.push(
+        Arg::with_name("tui")
+            .long("tui")
+            .conflicts_with("json")
+            .help("Show a dashboard of the steps and command output while running"),
+    );
+    args.extend(changelog_args());
+    args
+}
+
+/// Runs `subup` with the arguments of the process.
+pub fn main() {
+    run_with_args(env::args_os().collect())
+}
+
+/// Runs `subup` with the given arguments, starting with the program name.
+pub fn run_with_args(mut args: Vec<OsString>) {
+    // `subup [ARGS]` without a subcommand is the same as
+    // `subup update [ARGS]`.
+    if !has_subcommand(&args) {
+        args.insert(1, "update".into());
+    }
+    let matches = app().get_matches_from(&args);
+    let matches = match with_profile(args, matches.clone()) {
+        Ok(matches) => matches,
+        Err(e) => {
+            // Only `update` has profiles.
+            let update = matches.subcommand_matches("update").unwrap();
+            Cli::new(update.clone()).fail(e)
+        }
+    };
+
+    let (name, matches) = matches.subcommand();
+    let cli = Cli::new(matches.unwrap().clone());
+    match name {
+        "update" => cli.doit(doit),
+        "from-tool" => cli.doit(from_tool),
+        "msg" => cli.doit(msg),
+        "refresh" => cli.doit(refresh),
+        "check" => cli.doit(check),
+        "status" => cli.doit(status),
+        "clean" => cli.doit(clean),
+        "doctor" => cli.doit(doctor),
+        "abort" => cli.doit(abort),
+        _ => unreachable!(),
+    }
+}
+
+/// Parses the command line again with the options of `--profile` added,
+/// for the ones that were not given.
+fn with_profile(
+    mut args: Vec<OsString>,
+    matches: ArgMatches<'static>,
+) -> Result<ArgMatches<'static>, Error> {
+    let update = match matches.subcommand() {
+        ("update", Some(update)) => update,
+        _ => return Ok(matches),
+    };
+    let name = match update.value_of("profile") {
+        Some(name) => name,
+        None => return Ok(matches),
+    };
+    let profile_args = profile::load(name)?
+        .args(|key| update.occurrences_of(key) > 0)
+        .with_context(|| format!("Invalid profile `{}`", name))?;
+    // After `subup update`.
+    args.splice(2..2, profile_args.into_iter().map(OsString::from));
+    app()
+        .get_matches_from_safe(args)
+        .with_context(|| format!("Invalid profile `{}`", name))
+}
+
+/// Whether `args` name a subcommand, which may come after global options.
+fn has_subcommand(args: &[OsString]) -> bool {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_str().unwrap_or(""));
+    while let Some(arg) = args.next() {
+        match arg {
+            "--color" | "--upstream-remote" | "--push-remote" => {
+                args.next();
+            }
+            "--isolate-git" => {}
+            arg if arg.starts_with("--color=")
+                || arg.starts_with("--upstream-remote=")
+                || arg.starts_with("--push-remote=") => {}
+            arg => {
+                return matches!(
+                    arg,
+                    "update"
+                        | "from-tool"
+                        | "msg"
+                        | "refresh"
+                        | "check"
+                        | "status"
+                        | "clean"
+                        | "doctor"
+                        | "abort"
+                        | "help"
+                        | "-h"
+                        | "--help"
+                        | "-V"
+                        | "--version"
+                )
+            }
+        }
+    }
+    false
+}
+
+/// The command line of `subup`.
+fn app() -> App<'static, 'static> {
+    let verbose = || {
+        Arg::with_name("verbose")
+            .long("verbose")
+            .short("v")
+            .help("Verbose output")
+    };
+    let rust_branch = || {
+        Arg::with_name("rust-branch")
+            .long("rust-branch")
+            .takes_value(true)
+            .help(
+                "The base branch of the update (master/beta/stable), \
+                 defaults to guessing from the branch name",
+            )
+    };
+    App::new("subup")
+        .version(clap::crate_version!())
+        .about("Update rust repo submodules")
+        .setting(clap::AppSettings::ColoredHelp)
+        .setting(clap::AppSettings::SubcommandRequired)
+        .arg(color_arg())
+        .arg(isolate_git_arg())
+        .arg(upstream_remote_arg())
+        .arg(push_remote_arg())
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Update submodules and create a PR (the default)")
+                .setting(clap::AppSettings::ColoredHelp)
+                .args(&update_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("from-tool")
+                .about(
+                    "Update the submodule of the tool in the current directory \
+                     to its HEAD",
+                )
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("rust-checkout")
+                        .long("rust-checkout")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .required(true)
+                        .help("Path to the rust checkout to update"),
+                )
+                .args(&pipeline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("msg")
+                .about("Generate the commit message for checked out submodules")
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("submodules")
+                        .help("Submodules to examine")
+                        .multiple(true)
+                        .required(true),
+                )
+                .arg(verbose())
+                .args(&changelog_args())
+                .arg(
+                    Arg::with_name("branch")
+                        .long("branch")
+                        .help("Parent branch")
+                        .default_value("master"),
+                )
+                .arg(
+                    Arg::with_name("compare-upstream")
+                        .long("compare-upstream")
+                        .help(
+                            "Show how the message differs from the body of the \
+                             open PR of the current branch",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("refresh")
+                .about(
+                    "Redo the update of an existing branch on the latest upstream, \
+                     and force-push it",
+                )
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("branch")
+                        .help("The update branch, defaults to the current branch"),
+                )
+                .args(&pipeline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("List the submodules that are behind their branch")
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(verbose())
+                .arg(jobs_arg())
+                .arg(fail_if_no_changes_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Show what the current update branch contains")
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(verbose())
+                .arg(rust_branch()),
+        )
+        .subcommand(
+            SubCommand::with_name("clean")
+                .about("Remove the commit messages and saved state of an update")
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(verbose())
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Print the files instead of removing them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check that the tools and the rust checkout are set up")
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(verbose()),
+        )
+        .subcommand(
+            SubCommand::with_name("abort")
+                .about("Delete the current update branch and restore the submodules")
+                .setting(clap::AppSettings::ColoredHelp)
+                .arg(verbose())
+                .arg(rust_branch())
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Do not ask for confirmation"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Print the commands instead of running them"),
+                ),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::Scripted;
+    use std::rc::Rc;
+
+    fn cli(executor: &Rc<Scripted>) -> Cli<'static> {
+        cli_with(executor, &[])
+    }
+
+    /// A `Cli` for `subup update` with extra arguments.
+    fn cli_with(executor: &Rc<Scripted>, args: &[&str]) -> Cli<'static> {
+        let args = ["subup", "update", "--force", "src/tools/cargo"]
+            .iter()
+            .chain(args);
+        let matches = app().get_matches_from(args);
+        let matches = matches.subcommand_matches("update").unwrap().clone();
+        Cli::new(matches).with_executor(executor.clone())
+    }
+
+    fn submodule(path: &str, wants_update: bool, original_hash: &str) -> Submodule {
+        Submodule {
+            name: path.to_string(),
+            path: path.to_string(),
+            kind: SourceKind::Submodule,
+            rev: "origin/master".to_string(),
+            wants_update,
+            was_updated: false,
+            original_hash: original_hash.to_string(),
+            new_hash: None,
+            downgraded: false,
+            members: Vec::new(),
+        }
+    }
+
+    fn subup<'a>(cli: &'a Cli<'a>, submodules: Vec<Submodule>) -> SubUp<'a> {
+        SubUp {
+            cli,
+            submodule_args: vec!["src/tools/cargo".to_string()],
+            submodules,
+            rust_branch: "master".to_string(),
+            up_branch: "update-cargo".to_string(),
+            orig_metadata: None,
+            commit_title: None,
+            report: Report::default(),
+            notes: Vec::new(),
+            gitmodules_changed: false,
+            pending_metadata: None,
+            pending_ci: Vec::new(),
+            completed: Vec::new(),
+            affected_tests: Vec::new(),
+            refresh: false,
+            push_remote: None,
+            failed_tests: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn update_lock_in_workspace_root() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/workspace");
+        let output = std::process::Command::new("cargo")
+            .args(["metadata", "--format-version", "1", "--no-deps"])
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let metadata: Metadata = serde_json::from_slice(&output.stdout).unwrap();
+        let members =
+            SubUp::compute_members(&metadata, root.join("src/tools/foo").to_str().unwrap())
+                .unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].workspace_root, root);
+
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let s = subup(&cli, Vec::new());
+        s.update_lock_submodule(&members[0]).unwrap();
+        assert_eq!(
+            executor.commands(),
+            // The version in the lockfile is older than the manifest's.
+            [format!(
+                "cd {} && cargo update -p foo@0.0.9",
+                root.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn origin_is_cached() {
+        let executor = Rc::new(Scripted::default());
+        executor.respond(
+            "cd src/tools/cargo && git config --get remote.origin.url",
+            0,
+            "https://github.com/rust-lang/cargo.git\n",
+        );
+        let cli = cli(&executor);
+        for _ in 0..2 {
+            assert_eq!(
+                log::git_origin(&cli, "src/tools/cargo").unwrap(),
+                "rust-lang/cargo"
+            );
+        }
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[test]
+    fn sync_issue_lookup() {
+        let executor = Rc::new(Scripted::default());
+        let search = "curl --silent --show-error --fail --request GET \
+                      --header Accept: application/vnd.github+json --header @- \
+                      https://api.github.com/search/issues?q=repo%3Arust-lang%2Fcargo\
+                      %20is%3Aissue%20is%3Aopen%20in%3Atitle\
+                      %20%22rust-lang%2Frust%20sync%202026-10-16%22";
+        executor
+            .respond("gh auth token --hostname github.com", 0, "token\n")
+            .respond(
+                search,
+                0,
+                r#"{"items": [
+                    {"number": 7, "title": "rust-lang/rust sync 2026-10-16 follow-up"},
+                    {"number": 5, "title": "rust-lang/rust sync 2026-10-16"}
+                ]}"#,
+            )
+            .respond(search, 0, r#"{"items": []}"#);
+        let cli = cli(&executor);
+        let api = GitHubApi::new(&cli).unwrap();
+        let title = "rust-lang/rust sync 2026-10-16";
+        assert_eq!(
+            api.find_open_issue("rust-lang/cargo", title).unwrap(),
+            Some(5)
+        );
+        assert_eq!(api.find_open_issue("rust-lang/cargo", title).unwrap(), None);
+    }
+
+    #[test]
+    fn update_submodules() {
+        let executor = Rc::new(Scripted::default());
+        executor.respond(
+            "git submodule status --recursive",
+            0,
+            " aaa src/tools/cargo (heads/master)\n bbb src/tools/rls (v1.0)\n",
+        );
+        let cli = cli(&executor);
+        let s = subup(
+            &cli,
+            vec![
+                submodule("src/tools/cargo", true, "aaa"),
+                submodule("src/tools/rls", false, "bbb"),
+            ],
+        );
+        s.update_submodules().unwrap();
+        assert_eq!(
+            executor.commands(),
+            [
+                "git submodule status --recursive",
+                "cd src/tools/cargo && git checkout origin/master",
+            ]
+        );
+    }
+
+    #[test]
+    fn submodule_drift() {
+        let executor = Rc::new(Scripted::default());
+        executor
+            .respond(
+                "git submodule status --recursive",
+                0,
+                " ccc src/tools/cargo (heads/master)\n bbb src/tools/rls (v1.0)\n",
+            )
+            .respond(
+                "git submodule status --recursive",
+                0,
+                " ccc src/tools/cargo (heads/master)\n\
+                 +ddd src/tools/cargo/crates/x (heads/main)\n \
+                 bbb src/tools/rls (v1.0)\n",
+            );
+        let cli = cli(&executor);
+        let mut s = subup(
+            &cli,
+            vec![
+                submodule("src/tools/cargo", true, "aaa"),
+                submodule("src/tools/rls", false, "bbb"),
+            ],
+        );
+        s.submodules[0].was_updated = true;
+        s.submodules[0].new_hash = Some("ccc".to_string());
+        s.check_submodule_status(true).unwrap();
+        // The nested submodule is not at the commit cargo records.
+        assert!(s.check_submodule_status(true).is_err());
+        // Before the update, cargo is expected to be at the original hash.
+        executor.respond(
+            "git submodule status --recursive",
+            0,
+            " ccc src/tools/cargo (heads/master)\n",
+        );
+        assert!(s.check_submodule_status(false).is_err());
+    }
+
+    #[test]
+    fn push_to_namespace() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--push-to", "staging:bots/"]);
+        let mut subup = subup(&cli, Vec::new());
+        assert_eq!(
+            subup.push_target(),
+            ("staging", "bots/update-cargo".to_string())
+        );
+        subup.make_branch().unwrap();
+        assert_eq!(
+            executor.commands(),
+            [
+                "git fetch upstream",
+                "git checkout -B update-cargo upstream/master",
+                "git config branch.update-cargo.remote staging",
+                "git config branch.update-cargo.merge refs/heads/bots/update-cargo",
+                "git submodule update --init --recursive",
+            ]
+        );
+    }
+
+    #[test]
+    fn latest_green_without_new_commits() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--latest-green"]);
+        let mut miri = submodule("src/tools/miri", true, "def");
+        miri.kind = SourceKind::Josh {
+            repo: "rust-lang/miri".to_string(),
+        };
+        let mut subup = subup(&cli, vec![submodule("src/tools/cargo", true, "abc"), miri]);
+        subup.pick_latest_green().unwrap();
+        assert_eq!(subup.submodules[0].rev, "origin/master");
+        assert_eq!(
+            executor.commands(),
+            ["cd src/tools/cargo && git rev-list --first-parent --max-count=50 origin/master ^abc"]
+        );
+    }
+
+    #[test]
+    fn subcommand_after_global_options() {
+        let args = |args: &[&str]| -> Vec<OsString> {
+            iter::once("subup")
+                .chain(args.iter().copied())
+                .map(OsString::from)
+                .collect()
+        };
+        assert!(has_subcommand(&args(&["msg"])));
+        assert!(has_subcommand(&args(&["--color", "never", "msg"])));
+        assert!(has_subcommand(&args(&[
+            "--isolate-git",
+            "--upstream-remote=origin",
+            "status"
+        ])));
+        assert!(has_subcommand(&args(&["--push-remote", "fork", "abort"])));
+        assert!(!has_subcommand(&args(&[
+            "--color",
+            "never",
+            "src/tools/cargo"
+        ])));
+        assert!(!has_subcommand(&args(&["src/tools/cargo", "msg"])));
+        assert!(!has_subcommand(&args(&["--color"])));
+    }
+
+    #[test]
+    fn dirty_check_args() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--no-submodule-dirty-check", "src/tools/rls"]);
+        assert!(cli.matches.is_present("no-submodule-dirty-check"));
+        assert_eq!(
+            cli.matches
+                .values_of("no-submodule-dirty-check")
+                .map_or(0, |paths| paths.count()),
+            0
+        );
+        assert_eq!(
+            cli.matches
+                .values_of("submodules")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            ["src/tools/cargo", "src/tools/rls"]
+        );
+        let cli = cli_with(&executor, &["--no-submodule-dirty-check=a,b"]);
+        assert_eq!(
+            cli.matches
+                .values_of("no-submodule-dirty-check")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn git_add_josh_subtree() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let mut miri = submodule("src/tools/miri", true, "abc");
+        miri.kind = SourceKind::Josh {
+            repo: "rust-lang/miri".to_string(),
+        };
+        miri.was_updated = true;
+        miri.new_hash = Some("def".to_string());
+        let subup = subup(&cli, vec![miri]);
+        subup.git_add().unwrap();
+        assert_eq!(
+            executor.commands().last().unwrap(),
+            "git add src/tools/miri"
+        );
+    }
+
+    #[test]
+    fn remote_names() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(
+            &executor,
+            &["--upstream-remote", "origin", "--push-remote", "fork"],
+        );
+        let mut subup = subup(&cli, Vec::new());
+        assert_eq!(subup.push_target(), ("fork", "update-cargo".to_string()));
+        subup.make_branch().unwrap();
+        assert_eq!(
+            executor.commands()[..3],
+            [
+                "git fetch origin",
+                "git checkout -B update-cargo origin/master",
+                "git config branch.update-cargo.remote fork",
+            ]
+        );
+        assert_eq!(push_remote_of(&cli, "update-cargo").unwrap(), "fork");
+        // Without the option, the remote configured for the branch.
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &[]);
+        executor.respond(
+            "git config --default origin branch.update-cargo.remote",
+            0,
+            "mine\n",
+        );
+        assert_eq!(push_remote_of(&cli, "update-cargo").unwrap(), "mine");
+        assert_eq!(push_remote_of(&cli, "update-rls").unwrap(), "origin");
+    }
+
+    #[test]
+    fn remote_changes() {
+        let pr_url = "https://github.com/rust-lang/rust/pull/1";
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--local-only"]);
+        assert!(!cli.confirm_remote("push").unwrap());
+        assert!(forge::GitHub.comment(&cli, pr_url, "hi").is_err());
+        let cli = cli_with(&executor, &["--dry-run", "--local-only"]);
+        forge::GitHub.comment(&cli, pr_url, "hi").unwrap();
+        assert!(executor.commands().is_empty());
+        // `--force` is not interactive.
+        let cli = cli_with(&executor, &[]);
+        assert!(cli.confirm_remote("push").is_err());
+        let cli = cli_with(&executor, &["--confirm-remote"]);
+        assert!(cli.confirm_remote("push").unwrap());
+        forge::GitHub.comment(&cli, pr_url, "hi").unwrap();
+        assert_eq!(
+            executor.commands(),
+            [format!("gh pr comment {} --body hi", pr_url)]
+        );
+    }
+
+    #[test]
+    fn check_links_after_book_update() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let mut book = submodule("src/doc/book", true, "abc");
+        book.was_updated = true;
+        let mut subup = subup(&cli, vec![book]);
+        let steps = steps();
+        let step = steps
+            .iter()
+            .find(|step| step.name == "check_links")
+            .unwrap();
+        assert_eq!(subup.skip_reason(step).unwrap(), "the tests were skipped");
+        subup.report.tests = Some(vec!["src/doc/book".to_string()]);
+        assert_eq!(subup.skip_reason(step), None);
+        subup.check_links().unwrap();
+        assert_eq!(
+            executor.commands().last().unwrap(),
+            "./x.py test src/tools/linkchecker"
+        );
+        assert_eq!(
+            subup.skip_reason(step).unwrap(),
+            "the tests checked the links"
+        );
+        subup.submodules[0].was_updated = false;
+        assert_eq!(subup.skip_reason(step).unwrap(), "no books were updated");
+    }
+
+    #[test]
+    fn cherry_pick_args() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(
+            &executor,
+            &[
+                "--cherry-pick=src/tools/cargo=abc123, def456",
+                "--cherry-pick",
+                "src/tools/cargo=789abc",
+            ],
+        );
+        let picks = cherry_picks(&cli).unwrap();
+        assert_eq!(picks.len(), 1);
+        assert_eq!(picks["src/tools/cargo"], ["abc123", "def456", "789abc"]);
+        for value in &["abc123", "=abc123", "src/tools/cargo=", "src/tools/cargo=,"] {
+            let cli = cli_with(&executor, &["--cherry-pick", value]);
+            assert!(cherry_picks(&cli).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rerun_failed_tests() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let mut subup = subup(&cli, Vec::new());
+        executor
+            .respond("./x.py test src/tools/cargo src/tools/rls", 1, "")
+            .respond("./x.py test src/tools/cargo", 1, "");
+        let err = subup
+            .run_tests(vec![
+                "src/tools/cargo".to_string(),
+                "src/tools/rls".to_string(),
+            ])
+            .unwrap_err();
+        assert!(err.downcast_ref::<TestsFailed>().is_some());
+        assert_eq!(subup.failed_tests, ["src/tools/cargo"]);
+        assert_eq!(subup.report.tests.as_ref().unwrap(), &["src/tools/rls"]);
+        assert_eq!(
+            executor.commands()[1..],
+            [
+                "./x.py test src/tools/cargo src/tools/rls",
+                "./x.py test src/tools/cargo",
+                "./x.py test src/tools/rls",
+            ]
+        );
+        // Not interactive, so only the failed tests are run again.
+        subup.test().unwrap();
+        assert_eq!(
+            executor.commands().last().unwrap(),
+            "./x.py test src/tools/cargo"
+        );
+        assert!(subup.failed_tests.is_empty());
+        assert_eq!(
+            subup.report.tests.as_ref().unwrap(),
+            &["src/tools/rls", "src/tools/cargo"]
+        );
+    }
+
+    #[test]
+    fn too_new_members() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let root = env::current_dir().unwrap().join("src/tools/cargo");
+        let member = |name: &str, path: &str| Member {
+            name: name.to_string(),
+            version: "0.1.0".to_string(),
+            path: root.join(path),
+            workspace_root: root.clone(),
+        };
+        let mut cargo = submodule("src/tools/cargo", true, "abc");
+        cargo.was_updated = true;
+        cargo.new_hash = Some("def".to_string());
+        cargo.members = vec![
+            member("cargo", ""),
+            member("cargo-util", "crates/cargo-util"),
+            member("xtask", "crates/xtask"),
+        ];
+        let workspace = "[workspace]\n\
+                         [workspace.package]\n\
+                         rust-version = \"1.80\"\n\
+                         [package]\n\
+                         name = \"cargo\"\n\
+                         rust-version.workspace = true\n";
+        // Read once for the workspace and once for the root package.
+        for _ in 0..2 {
+            executor.respond(
+                "cd src/tools/cargo && git show def:Cargo.toml",
+                0,
+                workspace,
+            );
+        }
+        executor
+            .respond(
+                "cd src/tools/cargo && git show def:crates/cargo-util/Cargo.toml",
+                0,
+                "[package]\nname = \"cargo-util\"\nrust-version = \"1.70\"\n",
+            )
+            .respond(
+                "cd src/tools/cargo && git cat-file -e def:crates/xtask/Cargo.toml",
+                1,
+                "",
+            );
+        let subup = subup(&cli, vec![cargo]);
+        assert_eq!(
+            subup.too_new_members("1.79.0").unwrap(),
+            ["cargo (rust-version 1.80)"]
+        );
+    }
+
+    #[test]
+    fn submodule_status() {
+        let output = " 1111111111111111111111111111111111111111 library/backtrace (heads/master)\n\
+                      +2222222222222222222222222222222222222222 src/tools/cargo (0.80.0-12-g2222222)\n\
+                      -3333333333333333333333333333333333333333 src/doc/book\n\
+                      U0000000000000000000000000000000000000000 src/tools/rls\n";
+        let entry = |state: char, hash: char, path: &str| {
+            (state, hash.to_string().repeat(40), path.to_string())
+        };
+        assert_eq!(
+            parse_submodule_status(output),
+            [
+                entry(' ', '1', "library/backtrace"),
+                entry('+', '2', "src/tools/cargo"),
+                entry('-', '3', "src/doc/book"),
+                entry('U', '0', "src/tools/rls"),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--max-age", "2024-05-01", "--max-commits=1"]);
+        executor.respond(
+            "cd src/tools/cargo && git rev-list --first-parent --before=2024-05-01 origin/master ^abc",
+            0,
+            "ccc\nbbb\n",
+        );
+        let mut s = subup(&cli, vec![submodule("src/tools/cargo", true, "abc")]);
+        s.apply_limits().unwrap();
+        assert_eq!(s.submodules[0].rev, "bbb");
+        assert_eq!(
+            s.notes,
+            ["`src/tools/cargo` was limited to commits made before 2024-05-01 and at most 1 commit."]
+        );
+        let cli = cli_with(&executor, &["--max-commits=0"]);
+        let mut s = subup(&cli, vec![submodule("src/tools/cargo", true, "abc")]);
+        let err = s.apply_limits().unwrap_err();
+        assert_eq!(err.to_string(), "--max-commits must be at least 1");
+    }
+
+    #[test]
+    fn update_settings() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let subup = subup(&cli, Vec::new());
+        let mut rls = submodule("src/tools/rls", true, "abc");
+        subup.check_update_setting(&rls, Some("rebase")).unwrap();
+        subup.check_update_setting(&rls, Some("checkout")).unwrap();
+        assert!(executor.commands().is_empty());
+        // Not checked out, so it is checked out for the update.
+        rls.path = "no/such/submodule".to_string();
+        subup.check_update_setting(&rls, Some("none")).unwrap();
+        assert_eq!(
+            executor.commands(),
+            ["git submodule update --init --checkout -- no/such/submodule"]
+        );
+    }
+
+    #[test]
+    fn failed_summary() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let subup = subup(&cli, Vec::new());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("summary.json");
+        let e = format_err!("x.py failed").context("Tests failed");
+        subup.emit_summary(&path, Some(("test", &e))).unwrap();
+        let summary: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(summary["status"], "failed");
+        assert_eq!(summary["failed_step"], "test");
+        assert_eq!(summary["error"], "Tests failed: x.py failed");
+        subup.emit_summary(&path, None).unwrap();
+        let summary: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(summary["status"], "success");
+        assert!(summary["failed_step"].is_null());
+    }
+
+    #[test]
+    fn quoted_invocation() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            command_line(args(&[
+                "/home/me/.cargo/bin/subup",
+                "--commit-title",
+                "Update cargo's deps",
+                "src/tools/*",
+                "",
+                "$HOME;x",
+                "origin/master",
+            ])),
+            "subup --commit-title 'Update cargo'\\''s deps' 'src/tools/*' '' '$HOME;x' origin/master"
+        );
+    }
+
+    #[test]
+    fn picker_labels() {
+        let outdated = |behind| Outdated {
+            path: "src/tools/cargo".to_string(),
+            branch: None,
+            behind,
+        };
+        assert_eq!(outdated(0).label(), "src/tools/cargo (up to date)");
+        assert_eq!(outdated(1).label(), "src/tools/cargo (1 commit behind)");
+        assert_eq!(outdated(12).label(), "src/tools/cargo (12 commits behind)");
+    }
+
+    #[test]
+    fn push_remote() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let subup = subup(&cli, Vec::new());
+        executor.respond(
+            "git remote -v",
+            0,
+            "origin\thttps://github.com/rust-lang/rust.git (fetch)\n\
+             origin\thttps://github.com/rust-lang/rust.git (push)\n\
+             mine\tgit@github.com:Someone/rust.git (fetch)\n",
+        );
+        assert_eq!(
+            subup.remote_for("someone/rust").unwrap().as_deref(),
+            Some("mine")
+        );
+        assert_eq!(subup.remote_for("other/rust").unwrap(), None);
+        let upstream = RemoteUrl::parse("git@github.com:rust-lang/rust.git").unwrap();
+        assert_eq!(
+            fork_url(&upstream, "someone"),
+            "git@github.com:someone/rust.git"
+        );
+        let upstream = RemoteUrl::parse("https://github.com/rust-lang/rust").unwrap();
+        assert_eq!(
+            fork_url(&upstream, "someone"),
+            "https://github.com/someone/rust.git"
+        );
+    }
+
+    #[test]
+    fn test_env_args() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(
+            &executor,
+            &[
+                "--test-env=RUST_TEST_THREADS=1",
+                "--test-env",
+                "src/tools/miri:MIRI_SKIP_UI_CHECKS=1",
+                "--test-env=src/tools/cargo:CARGO_X=a=b",
+            ],
+        );
+        assert_eq!(
+            test_env(&cli, &["src/tools/cargo"]).unwrap(),
+            [
+                ("RUST_TEST_THREADS".to_string(), "1".to_string()),
+                ("CARGO_X".to_string(), "a=b".to_string()),
+            ]
+        );
+        assert_eq!(test_env(&cli, &["src/tools/miri"]).unwrap().len(), 2);
+        for value in &["RUST_TEST_THREADS", "=1", ":X=1", "src/tools/miri:=1"] {
+            let cli = cli_with(&executor, &["--test-env", value]);
+            assert!(test_env(&cli, &[]).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn unofficial_rev() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let mut cargo = submodule("src/tools/cargo", true, "abc");
+        cargo.rev = "def".to_string();
+        let mut subup = subup(&cli, Vec::new());
+        let check = |subup: &SubUp<'_>| {
+            subup
+                .unofficial_rev_warning(&cargo, "origin/master")
+                .unwrap()
+        };
+        let is_ancestor = "cd src/tools/cargo && git merge-base --is-ancestor def origin/master";
+        let branches = "cd src/tools/cargo && git branch --remotes \
+                        --format=%(refname:short) --contains def origin/*";
+        assert_eq!(check(&subup), None);
+        executor.respond(is_ancestor, 1, "");
+        assert!(check(&subup).unwrap().contains("force-pushed"));
+        executor
+            .respond(is_ancestor, 1, "")
+            .respond(branches, 0, "origin/rust-1.50.0\n");
+        assert_eq!(
+            check(&subup).unwrap(),
+            "`def` of `src/tools/cargo` is not on `origin/master`, only on origin/rust-1.50.0."
+        );
+        subup.rust_branch = "beta".to_string();
+        executor
+            .respond(is_ancestor, 1, "")
+            .respond(branches, 0, "origin/rust-1.50.0\n");
+        assert_eq!(check(&subup), None);
+    }
+
+    #[test]
+    fn downgrade() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli(&executor);
+        let subup = subup(&cli, Vec::new());
+        let cargo = submodule("src/tools/cargo", true, "abc");
+        executor.respond(
+            "cd src/tools/cargo && git merge-base --is-ancestor def abc",
+            1,
+            "",
+        );
+        assert!(!subup.is_downgrade(&cargo, "def").unwrap());
+        assert!(subup.is_downgrade(&cargo, "def").unwrap());
+    }
+
+    #[test]
+    fn spawn_counts() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--dry-run"]);
+        cli.git("log --oneline").capture_stdout("").unwrap();
+        cli.git("-c core.abbrev=12 log").capture_stdout("").unwrap();
+        cli.git_cmd(["-C", "src/tools/cargo", "fetch"])
+            .read_only()
+            .run("")
+            .unwrap();
+        cli.cargo("update -p cargo").capture_stdout("").unwrap();
+        // Only reported in dry-run mode.
+        cli.git("commit").run("").unwrap();
+        let spawns = cli.spawns();
+        assert_eq!(
+            spawns.counts(),
+            [
+                ("git log".to_string(), 2),
+                ("cargo".to_string(), 1),
+                ("git fetch".to_string(), 1),
+            ]
+        );
+        assert_eq!(spawns.total(), 4);
+    }
+
+    #[test]
+    fn refresh_keeps_the_pr() {
+        let executor = Rc::new(Scripted::default());
+        let cli = cli_with(&executor, &["--create-pr", "--tracking-issue"]);
+        let mut subup = subup(&cli, Vec::new());
+        subup.refresh = true;
+        for step in steps() {
+            if ["create_pr", "finish_manual_pr", "tracking_issue"].contains(&step.name) {
+                assert_ne!(subup.skip_reason(&step), None, "{}", step.name);
+            }
+        }
+        subup.refresh = false;
+        let steps = steps();
+        let create_pr = steps.iter().find(|step| step.name == "create_pr").unwrap();
+        assert_eq!(subup.skip_reason(create_pr), None);
+    }
+}
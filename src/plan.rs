@@ -0,0 +1,133 @@
+//! Ordering of the steps of a pipeline by their dependencies.
+
+use anyhow::{bail, Error};
+
+/// A step of a pipeline operating on the state `T`.
+pub struct Step<T> {
+    pub name: &'static str,
+    /// Steps that must finish (or be skipped) before this one.
+    pub deps: &'static [&'static str],
+    pub run: fn(&mut T) -> Result<(), Error>,
+    /// Returns why the step will be skipped, if it will be.
+    pub skip: fn(&T) -> Option<String>,
+    /// Show the reason as a warning when skipped, instead of only in
+    /// verbose mode.
+    pub warn_on_skip: bool,
+}
+
+/// Never skips the step.
+pub fn always<T>(_: &T) -> Option<String> {
+    None
+}
+
+/// Returns the indexes of `steps` in the order to run them.
+///
+/// Steps run in the order they are listed, except a step waits for its
+/// dependencies. Returns an error for unknown dependencies or cycles.
+pub fn order<T>(steps: &[Step<T>]) -> Result<Vec<usize>, Error> {
+    for step in steps {
+        for dep in step.deps {
+            if !steps.iter().any(|s| s.name == *dep) {
+                bail!("Step `{}` depends on unknown step `{}`", step.name, dep);
+            }
+        }
+    }
+    let mut done: Vec<usize> = Vec::new();
+    while done.len() < steps.len() {
+        let next = steps.iter().enumerate().position(|(i, step)| {
+            !done.contains(&i)
+                && step
+                    .deps
+                    .iter()
+                    .all(|dep| done.iter().any(|&d| steps[d].name == *dep))
+        });
+        match next {
+            Some(i) => done.push(i),
+            None => {
+                let stuck: Vec<_> = (0..steps.len())
+                    .filter(|i| !done.contains(i))
+                    .map(|i| steps[i].name)
+                    .collect();
+                bail!("Steps have cyclic dependencies: {}", stuck.join(", "));
+            }
+        }
+    }
+    Ok(done)
+}
+
+/// Describes the order of the steps and which will be skipped, one line per
+/// step. `skip` returns why a step will be skipped.
+pub fn explain<T>(
+    steps: &[Step<T>],
+    skip: impl Fn(&Step<T>) -> Option<String>,
+) -> Result<Vec<String>, Error> {
+    Ok(order(steps)?
+        .into_iter()
+        .enumerate()
+        .map(|(n, i)| {
+            let step = &steps[i];
+            let mut line = format!("{:2}. {}", n + 1, step.name);
+            if !step.deps.is_empty() {
+                line.push_str(&format!(" (after {})", step.deps.join(", ")));
+            }
+            if let Some(reason) = skip(step) {
+                line.push_str(&format!(" - skipped: {}", reason));
+            }
+            line
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(name: &'static str, deps: &'static [&'static str]) -> Step<()> {
+        Step {
+            name,
+            deps,
+            run: |_| Ok(()),
+            skip: always,
+            warn_on_skip: false,
+        }
+    }
+
+    fn names(steps: &[Step<()>]) -> Vec<&'static str> {
+        order(steps)
+            .unwrap()
+            .into_iter()
+            .map(|i| steps[i].name)
+            .collect()
+    }
+
+    #[test]
+    fn listed_order() {
+        let steps = [step("a", &[]), step("b", &["a"]), step("c", &["a"])];
+        assert_eq!(names(&steps), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn waits_for_deps() {
+        let steps = [step("b", &["a"]), step("c", &[]), step("a", &[])];
+        assert_eq!(names(&steps), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn invalid() {
+        let unknown = [step("a", &["x"])];
+        assert!(order(&unknown).is_err());
+        let cycle = [step("a", &["b"]), step("b", &["a"]), step("c", &[])];
+        let err = order(&cycle).unwrap_err().to_string();
+        assert!(err.contains("a, b"), "{}", err);
+    }
+
+    #[test]
+    fn explain_skips() {
+        let mut steps = vec![step("a", &[]), step("b", &["a"])];
+        steps[1].skip = |_| Some("not needed".to_string());
+        assert_eq!(
+            explain(&steps, |step| (step.skip)(&())).unwrap(),
+            [" 1. a", " 2. b (after a) - skipped: not needed"]
+        );
+    }
+}
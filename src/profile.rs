@@ -0,0 +1,179 @@
+//! Named sets of `subup update` options from `subup.toml`.
+//!
+//! Each key of a profile is the long name of an option, and `submodules`
+//! for the submodules to update:
+//!
+//! ```toml
+//! [profile.weekly-cargo]
+//! submodules = ["src/tools/cargo"]
+//! test = ["src/tools/cargo"]
+//! pr-snippet = ["src/tools/cargo=@rustbot label +A-cargo"]
+//! create-pr = true
+//! self-assign = true
+//! self-approve = "@bors r+ rollup=never p=1"
+//! ```
+//!
+//! Options given on the command line take precedence over the profile.
+
+use anyhow::{bail, format_err, Context, Error};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use toml::Value;
+
+/// The name of the file with the profiles.
+pub const CONFIG_FILE: &str = "subup.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    profile: BTreeMap<String, Profile>,
+}
+
+/// The options of one profile.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Profile(BTreeMap<String, Value>);
+
+impl Profile {
+    /// The command-line arguments for the options of the profile, except for
+    /// the ones where `is_given` says they are already on the command line.
+    ///
+    /// Submodules come first, and values are attached with `=` so they are
+    /// not mistaken for more values of a multiple-value option.
+    pub fn args(&self, is_given: impl Fn(&str) -> bool) -> Result<Vec<String>, Error> {
+        let mut submodules = Vec::new();
+        let mut args = Vec::new();
+        for (key, value) in self.0.iter().filter(|(key, _)| !is_given(key)) {
+            if key == "profile" {
+                bail!("profiles cannot include other profiles");
+            }
+            let values = match value {
+                Value::Boolean(true) => {
+                    args.push(format!("--{}", key));
+                    continue;
+                }
+                Value::Boolean(false) => continue,
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    Value::Integer(i) => i.to_string(),
+                    _ => bail!(
+                        "`{}` should be a string, integer, or boolean, or an array of them",
+                        key
+                    ),
+                };
+                if key == "submodules" {
+                    submodules.push(value);
+                } else {
+                    args.push(format!("--{}={}", key, value));
+                }
+            }
+        }
+        submodules.extend(args);
+        Ok(submodules)
+    }
+}
+
+/// Parses the profiles of a `subup.toml` file.
+pub fn parse(text: &str) -> Result<BTreeMap<String, Profile>, Error> {
+    let config: Config = toml::from_str(text).context("Invalid TOML config")?;
+    Ok(config.profile)
+}
+
+/// Where to look for `subup.toml`: the current directory, then the user's
+/// config directory.
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE)];
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(dir) = config_dir {
+        paths.push(dir.join("subup").join(CONFIG_FILE));
+    }
+    paths
+}
+
+/// Loads the profile called `name` from the first `subup.toml` found.
+pub fn load(name: &str) -> Result<Profile, Error> {
+    let paths = config_paths();
+    let path = paths.iter().find(|path| path.exists()).ok_or_else(|| {
+        format_err!(
+            "Profile `{}` was given, but {} was not found in the current \
+             directory or the config directory",
+            name,
+            CONFIG_FILE
+        )
+    })?;
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut profiles =
+        parse(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    profiles.remove(name).ok_or_else(|| {
+        let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+        format_err!(
+            "Profile `{}` is not in {}, the profiles are: {}",
+            name,
+            path.display(),
+            names.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [profile.weekly-cargo]
+        submodules = ["src/tools/cargo"]
+        test = ["src/tools/cargo", "src/tools/rls"]
+        create-pr = true
+        preview-pr = false
+        self-approve = "@bors r+ p=1"
+        jobs = 4
+
+        [profile.beta-backport]
+        submodules = "beta:src/tools/cargo"
+        rust-branch = "beta"
+    "#;
+
+    #[test]
+    fn args() {
+        let profiles = parse(CONFIG).unwrap();
+        assert_eq!(
+            profiles["weekly-cargo"].args(|_| false).unwrap(),
+            [
+                "src/tools/cargo",
+                "--create-pr",
+                "--jobs=4",
+                "--self-approve=@bors r+ p=1",
+                "--test=src/tools/cargo",
+                "--test=src/tools/rls",
+            ]
+        );
+        assert_eq!(
+            profiles["beta-backport"]
+                .args(|key| key == "submodules")
+                .unwrap(),
+            ["--rust-branch=beta"]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("[profiles.x]").is_err());
+        assert!(parse("profile = 1").is_err());
+        assert!(parse("").unwrap().is_empty());
+        let profiles = parse("[profile.x]\ntest = 1.5\n[profile.y]\nprofile = \"x\"").unwrap();
+        assert!(profiles["x"].args(|_| false).is_err());
+        assert!(profiles["y"].args(|_| false).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Parsing of git remote URLs.
+
+use anyhow::{format_err, Error};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    Https,
+    Http,
+    /// `ssh://` URLs, and the scp-like `git@host:owner/repo` form.
+    Ssh,
+    Git,
+}
+
+/// The parts of a remote URL such as `https://github.com/rust-lang/cargo.git`
+/// or `git@github.com:rust-lang/cargo.git`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteUrl {
+    pub scheme: Scheme,
+    /// The host name, without a user or port.
+    pub host: String,
+    /// The owner, which may have several components for GitLab subgroups.
+    pub owner: String,
+    /// The repository name, without `.git`.
+    pub repo: String,
+}
+
+impl RemoteUrl {
+    pub fn parse(url: &str) -> Result<RemoteUrl, Error> {
+        let err = || format_err!("Could not parse remote url `{}`", url);
+        let trimmed = url.trim();
+        let (scheme, rest) = match trimmed.split_once("://") {
+            Some(("https", rest)) => (Scheme::Https, rest),
+            Some(("http", rest)) => (Scheme::Http, rest),
+            Some(("ssh", rest)) | Some(("git+ssh", rest)) => (Scheme::Ssh, rest),
+            Some(("git", rest)) => (Scheme::Git, rest),
+            Some(_) => return Err(err()),
+            // scp-like syntax.
+            None => (Scheme::Ssh, trimmed),
+        };
+        let (authority, path) = if trimmed.contains("://") {
+            rest.split_once('/')
+        } else {
+            rest.split_once(':')
+        }
+        .ok_or_else(err)?;
+        let host = authority.rsplit('@').next().unwrap();
+        let host = host.split(':').next().unwrap();
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let (owner, repo) = path.rsplit_once('/').ok_or_else(err)?;
+        let owner = owner.trim_start_matches('~');
+        if host.is_empty() || owner.is_empty() || repo.is_empty() {
+            return Err(err());
+        }
+        Ok(RemoteUrl {
+            scheme,
+            host: host.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+        })
+    }
+
+    /// `owner/repo`.
+    pub fn slug(&self) -> String {
+        format!("{}/{}", self.owner, self.repo)
+    }
+
+    /// The https URL of the repository's web page.
+    pub fn web_url(&self) -> String {
+        format!("https://{}/{}", self.host, self.slug())
+    }
+
+    pub fn is_github(&self) -> bool {
+        self.host == "github.com"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemes() {
+        for (url, scheme) in &[
+            ("https://github.com/rust-lang/cargo", Scheme::Https),
+            (
+                "https://user@github.com/rust-lang/cargo.git/",
+                Scheme::Https,
+            ),
+            ("http://github.com:80/rust-lang/cargo.git", Scheme::Http),
+            ("git@github.com:rust-lang/cargo.git", Scheme::Ssh),
+            ("github.com:rust-lang/cargo", Scheme::Ssh),
+            ("ssh://git@github.com:22/rust-lang/cargo.git", Scheme::Ssh),
+            ("git://github.com/rust-lang/cargo.git", Scheme::Git),
+        ] {
+            let remote = RemoteUrl::parse(url).unwrap();
+            assert_eq!(remote.scheme, *scheme, "{}", url);
+            assert_eq!(remote.host, "github.com", "{}", url);
+            assert_eq!(remote.slug(), "rust-lang/cargo", "{}", url);
+            assert_eq!(remote.web_url(), "https://github.com/rust-lang/cargo");
+            assert!(remote.is_github());
+        }
+    }
+
+    #[test]
+    fn other_hosts() {
+        let remote = RemoteUrl::parse("git@gitlab.com:group/sub/project.git").unwrap();
+        assert_eq!(remote.owner, "group/sub");
+        assert_eq!(remote.repo, "project");
+        assert!(!remote.is_github());
+        let remote = RemoteUrl::parse("https://github.com/rust-lang/crates.io-index").unwrap();
+        assert_eq!(remote.repo, "crates.io-index");
+    }
+
+    #[test]
+    fn invalid() {
+        for url in &[
+            "",
+            "github.com",
+            "https://github.com/cargo",
+            "file:///a/b",
+            "/a/b/c",
+        ] {
+            assert!(RemoteUrl::parse(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Read-only queries of git repositories, using libgit2.
+//!
+//! These avoid parsing `git` output and do not depend on the installed
+//! version of `git`. Commands that change a repository or use the network
+//! still run `git`, so that they show up in verbose and dry-run output.
+
+use anyhow::{bail, Context, Error};
+use git2::{
+    Delta, DiffOptions, ErrorCode, Index, IndexEntryExtendedFlag, ObjectType, Oid, Repository,
+    TreeWalkMode, TreeWalkResult,
+};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// The file mode of a gitlink (a submodule commit) in a tree or the index.
+const GITLINK_MODE: u32 = 0o160000;
+
+/// Opens the repository at (or containing) `dir`.
+fn open(dir: &str) -> Result<Repository, Error> {
+    Repository::discover(dir).with_context(|| format!("Failed to open git repository `{}`", dir))
+}
+
+/// Resolves `rev` like `git rev-parse`, including `rev:path` for the
+/// commit of a submodule (which is not in the superproject's objects).
+fn resolve(repo: &Repository, rev: &str) -> Result<Oid, git2::Error> {
+    match rev.split_once(':') {
+        Some((rev, path)) => {
+            let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+            Ok(tree.get_path(Path::new(path))?.id())
+        }
+        None => Ok(repo.revparse_single(rev)?.id()),
+    }
+}
+
+/// Returns the hash of `rev` in the repository at `dir`, `None` if it does
+/// not exist.
+pub fn try_hash(dir: &str, rev: &str) -> Result<Option<String>, Error> {
+    match resolve(&open(dir)?, rev) {
+        Ok(oid) => Ok(Some(oid.to_string())),
+        Err(e) if matches!(e.code(), ErrorCode::NotFound | ErrorCode::InvalidSpec) => Ok(None),
+        Err(e) => Err(Error::new(e).context(format!("Failed to resolve `{}` in `{}`", rev, dir))),
+    }
+}
+
+/// Returns the hash of `rev` in the repository at `dir`.
+pub fn hash(dir: &str, rev: &str) -> Result<String, Error> {
+    let oid = resolve(&open(dir)?, rev)
+        .with_context(|| format!("Failed to determine rev `{}` for path `{}`", rev, dir))?;
+    Ok(oid.to_string())
+}
+
+/// The commit recorded for the submodule at `path` in the index of the
+/// superproject at `dir`, like `git rev-parse :path`.
+pub fn staged_gitlink(dir: &str, path: &str) -> Result<String, Error> {
+    let repo = open(dir)?;
+    let index = repo.index().context("Failed to read the git index.")?;
+    match index.get_path(Path::new(path), 0) {
+        Some(entry) if entry.mode == GITLINK_MODE => Ok(entry.id.to_string()),
+        Some(_) => bail!(
+            "Expected `{}` to be staged as a submodule, but it is staged as a file.",
+            path
+        ),
+        None => bail!(
+            "Expected `{}` to be staged as a submodule, but it is not in the index \
+             (it may be conflicted, or outside of a sparse checkout).",
+            path
+        ),
+    }
+}
+
+/// The commit recorded for the submodule at `path` in `HEAD` of the
+/// superproject at `dir`, like `git rev-parse HEAD:path`.
+pub fn head_gitlink(dir: &str, path: &str) -> Result<String, Error> {
+    let repo = open(dir)?;
+    let tree = repo
+        .head()
+        .and_then(|head| head.peel_to_tree())
+        .context("Failed to read the tree of HEAD.")?;
+    match tree.get_path(Path::new(path)) {
+        Ok(entry) if entry.kind() == Some(ObjectType::Commit) => Ok(entry.id().to_string()),
+        Ok(_) => bail!(
+            "Expected `{}` to be a submodule in HEAD, but it is not.",
+            path
+        ),
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            bail!(
+                "Expected `{}` to be a submodule in HEAD, but it does not exist.",
+                path
+            )
+        }
+        Err(e) => Err(Error::new(e).context(format!("Failed to find `{}` in HEAD.", path))),
+    }
+}
+
+/// The commit checked out in the submodule at `path`.
+///
+/// Unlike `hash(path, "HEAD")`, this does not look in parent directories,
+/// which would find the superproject if the submodule is not initialized.
+pub fn worktree_head(path: &str) -> Result<String, Error> {
+    let repo = Repository::open(path).with_context(|| {
+        format!(
+            "Expected submodule `{}` to be checked out, run `git submodule update --init {}`.",
+            path, path
+        )
+    })?;
+    let head = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .with_context(|| format!("Failed to read HEAD of submodule `{}`.", path))?;
+    Ok(head.id().to_string())
+}
+
+/// Whether `path` has staged or unstaged changes compared to `HEAD`, like
+/// `git diff-index HEAD`.
+pub fn has_changes(dir: &str, path: &str) -> Result<bool, Error> {
+    let repo = open(dir)?;
+    let head = repo.head()?.peel_to_tree()?;
+    let mut opts = DiffOptions::new();
+    if path != "." {
+        opts.pathspec(path);
+    }
+    let diff = repo
+        .diff_tree_to_workdir_with_index(Some(&head), Some(&mut opts))
+        .context("Failed to check for changes.")?;
+    // Files outside of a sparse checkout are missing from the working tree,
+    // but are not deleted.
+    let index = repo.index().context("Failed to read the git index.")?;
+    Ok(diff.deltas().any(|delta| {
+        let skipped = delta
+            .old_file()
+            .path()
+            .is_some_and(|path| is_skip_worktree(&index, path));
+        !(delta.status() == Delta::Deleted && skipped)
+    }))
+}
+
+/// Whether `path` is outside of the sparse checkout, so it is not in the
+/// working tree.
+fn is_skip_worktree(index: &Index, path: &Path) -> bool {
+    index.get_path(path, 0).is_some_and(|entry| {
+        entry.flags_extended & IndexEntryExtendedFlag::SKIP_WORKTREE.bits() != 0
+    })
+}
+
+/// Whether the repository at `dir` uses a sparse checkout.
+pub fn is_sparse_checkout(dir: &str) -> Result<bool, Error> {
+    let config = open(dir)?.config()?;
+    match config.get_bool("core.sparseCheckout") {
+        Ok(sparse) => Ok(sparse),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(false),
+        Err(e) => Err(Error::new(e).context("Failed to read core.sparseCheckout.")),
+    }
+}
+
+/// The `paths` that are outside of the sparse checkout of the repository at
+/// `dir`.
+pub fn outside_sparse_checkout<'a>(dir: &str, paths: &[&'a str]) -> Result<Vec<&'a str>, Error> {
+    let index = open(dir)?
+        .index()
+        .context("Failed to read the git index.")?;
+    Ok(paths
+        .iter()
+        .copied()
+        .filter(|path| is_skip_worktree(&index, Path::new(path)))
+        .collect())
+}
+
+/// The name of the checked out branch.
+pub fn current_branch(dir: &str) -> Result<String, Error> {
+    let repo = open(dir)?;
+    let head = repo
+        .find_reference("HEAD")
+        .context("Could not determine current branch.")?;
+    let target = head
+        .symbolic_target()
+        .and_then(|target| target.strip_prefix("refs/heads/"))
+        .context("Could not determine current branch, HEAD is detached.")?;
+    Ok(target.to_string())
+}
+
+/// Whether the local branch `name` exists.
+pub fn has_branch(dir: &str, name: &str) -> Result<bool, Error> {
+    let repo = open(dir)?;
+    let exists = match repo.find_reference(&format!("refs/heads/{}", name)) {
+        Ok(_) => true,
+        Err(e) if e.code() == ErrorCode::NotFound => false,
+        Err(e) => return Err(Error::new(e).context("Failed to check branch status.")),
+    };
+    Ok(exists)
+}
+
+/// Whether the repository at `dir` is a shallow clone.
+pub fn is_shallow(dir: &str) -> Result<bool, Error> {
+    Ok(open(dir)?.is_shallow())
+}
+
+/// Paths of all gitlinks (submodule commits) in `HEAD`.
+pub fn gitlink_paths(dir: &str) -> Result<HashSet<String>, Error> {
+    let repo = open(dir)?;
+    let tree = repo
+        .head()
+        .and_then(|head| head.peel_to_tree())
+        .context("Failed to list the tree of HEAD.")?;
+    let mut paths = HashSet::new();
+    tree.walk(TreeWalkMode::PreOrder, |parent, entry| {
+        if entry.kind() == Some(ObjectType::Commit) {
+            if let Some(name) = entry.name() {
+                paths.insert(format!("{}{}", parent, name));
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(paths)
+}
+
+/// The `(name, path)` of every submodule in `.gitmodules`, in the order
+/// they are listed.
+pub fn submodule_entries(dir: &str) -> Result<Vec<(String, String)>, Error> {
+    let path = Path::new(dir).join(".gitmodules");
+    let config = git2::Config::open(&path).context("Failed to get submodule list.")?;
+    let mut entries = Vec::new();
+    let mut iter = config.entries(Some(r"^submodule\..*\.path$"))?;
+    while let Some(entry) = iter.next() {
+        let entry = entry?;
+        let (name, value) = match (entry.name(), entry.value()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => continue,
+        };
+        let name = name
+            .strip_prefix("submodule.")
+            .and_then(|key| key.strip_suffix(".path"))
+            .unwrap_or(value);
+        entries.push((name.to_string(), value.to_string()));
+    }
+    Ok(entries)
+}
+
+/// The `branch` of the submodule `name` in `.gitmodules`, if set.
+pub fn submodule_branch(dir: &str, name: &str) -> Result<Option<String>, Error> {
+    submodule_setting(dir, name, "branch")
+}
+
+/// The `key` setting (such as `update`) of the submodule `name` in
+/// `.gitmodules`, if set.
+pub fn submodule_setting(dir: &str, name: &str, key: &str) -> Result<Option<String>, Error> {
+    let path = Path::new(dir).join(".gitmodules");
+    let config = git2::Config::open(&path).context("Failed to read .gitmodules.")?;
+    match config.get_string(&format!("submodule.{}.{}", name, key)) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(Error::new(e).context("Failed to read .gitmodules.")),
+    }
+}
+
+/// Whether the submodule `name` is marked `shallow = true` in
+/// `.gitmodules`, so it is cloned with only the latest commit.
+pub fn is_shallow_submodule(dir: &str, name: &str) -> Result<bool, Error> {
+    let path = Path::new(dir).join(".gitmodules");
+    let config = git2::Config::open(&path).context("Failed to read .gitmodules.")?;
+    match config.get_bool(&format!("submodule.{}.shallow", name)) {
+        Ok(shallow) => Ok(shallow),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(false),
+        Err(e) => Err(Error::new(e).context("Failed to read .gitmodules.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{IndexEntry, IndexTime, Signature};
+    use std::fs;
+    use tempfile::TempDir;
+
+    const SUB_HASH: &str = "1111111111111111111111111111111111111111";
+
+    /// A repository with the file `file.txt` and the submodule `sub`
+    /// committed.
+    fn test_repo() -> TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repository::init(tmp.path()).unwrap();
+        fs::write(tmp.path().join("file.txt"), "hello\n").unwrap();
+        fs::write(
+            tmp.path().join(".gitmodules"),
+            "[submodule \"sub\"]\n\tpath = sub\n\turl = https://example.com/sub\n\
+             [submodule \"other\"]\n\tpath = src/other\n\turl = https://example.com/other\n",
+        )
+        .unwrap();
+        fs::create_dir(tmp.path().join("sub")).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file.txt")).unwrap();
+        index.add_path(Path::new(".gitmodules")).unwrap();
+        index
+            .add(&IndexEntry {
+                ctime: IndexTime::new(0, 0),
+                mtime: IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: GITLINK_MODE,
+                uid: 0,
+                gid: 0,
+                file_size: 0,
+                id: Oid::from_str(SUB_HASH).unwrap(),
+                flags: 0,
+                flags_extended: 0,
+                path: b"sub".to_vec(),
+            })
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[])
+            .unwrap();
+        tmp
+    }
+
+    #[test]
+    fn hashes() {
+        let tmp = test_repo();
+        let dir = tmp.path().to_str().unwrap();
+        let head = hash(dir, "HEAD").unwrap();
+        assert_eq!(try_hash(dir, "HEAD").unwrap().as_deref(), Some(&*head));
+        assert_eq!(
+            try_hash(dir, "HEAD:sub").unwrap().as_deref(),
+            Some(SUB_HASH)
+        );
+        assert_eq!(try_hash(dir, "no-such-branch").unwrap(), None);
+        assert!(hash(dir, "no-such-branch").is_err());
+    }
+
+    #[test]
+    fn gitlinks() {
+        let tmp = test_repo();
+        let dir = tmp.path().to_str().unwrap();
+        assert_eq!(staged_gitlink(dir, "sub").unwrap(), SUB_HASH);
+        assert_eq!(head_gitlink(dir, "sub").unwrap(), SUB_HASH);
+        for path in &["file.txt", "missing"] {
+            assert!(staged_gitlink(dir, path).is_err(), "{}", path);
+            assert!(head_gitlink(dir, path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let tmp = test_repo();
+        let dir = tmp.path().to_str().unwrap();
+        assert!(!has_changes(dir, ".").unwrap());
+        fs::write(tmp.path().join("file.txt"), "changed\n").unwrap();
+        assert!(has_changes(dir, "file.txt").unwrap());
+        assert!(has_changes(dir, ".").unwrap());
+        assert!(!has_changes(dir, "sub").unwrap());
+    }
+
+    #[test]
+    fn submodules() {
+        let tmp = test_repo();
+        let dir = tmp.path().to_str().unwrap();
+        assert_eq!(
+            submodule_entries(dir).unwrap(),
+            [
+                ("sub".to_string(), "sub".to_string()),
+                ("other".to_string(), "src/other".to_string()),
+            ]
+        );
+    }
+}
@@ -1,6 +1,220 @@
-use anyhow::{Context, Error};
+use crate::event::{Event, EventBus};
+use anyhow::{bail, Context, Error};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::process::{Command, ExitStatus, Output, Stdio};
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(windows)]
+use std::os::windows::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+
+/// Output of a command run with `Runner::capture`.
+#[derive(Debug)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the commands built by a `Runner`.
+///
+/// `System` runs real processes, `Scripted` returns canned output so that
+/// code using a `Runner` can be tested without a rust checkout.
+pub trait Executor {
+    /// Runs `cmd` to completion, writing `stdin` to it.
+    fn output(&self, cmd: Command, stdin: Option<&[u8]>) -> io::Result<Output>;
+
+    /// Runs `cmd` to completion, calling `on_line` with each line of its
+    /// stdout and stderr, which must be piped. By default the lines are
+    /// only passed on after the command finishes.
+    fn stream(
+        &self,
+        cmd: Command,
+        stdin: Option<&[u8]>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Output> {
+        let output = self.output(cmd, stdin)?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        for line in stdout.lines().chain(stderr.lines()) {
+            on_line(line);
+        }
+        Ok(output)
+    }
+
+    /// Starts `cmd` without waiting for it. By default this runs it to
+    /// completion.
+    fn spawn(&self, cmd: Command, stdin: Option<&[u8]>) -> io::Result<Process> {
+        self.output(cmd, stdin).map(Process::Finished)
+    }
+}
+
+/// A command started with `Executor::spawn`.
+pub enum Process {
+    Running(Child),
+    Finished(Output),
+}
+
+impl Process {
+    fn wait(self) -> io::Result<Output> {
+        match self {
+            Process::Running(child) => child.wait_with_output(),
+            Process::Finished(output) => Ok(output),
+        }
+    }
+}
+
+/// Runs commands as processes.
+pub struct System;
+
+impl Executor for System {
+    fn output(&self, mut cmd: Command, stdin: Option<&[u8]>) -> io::Result<Output> {
+        match stdin {
+            Some(data) => cmd.spawn().and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(data)?;
+                child.wait_with_output()
+            }),
+            None => cmd.output(),
+        }
+    }
+
+    fn stream(
+        &self,
+        mut cmd: Command,
+        stdin: Option<&[u8]>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Output> {
+        let mut child = cmd.spawn()?;
+        if let Some(data) = stdin {
+            child.stdin.take().unwrap().write_all(data)?;
+        }
+        // Both pipes are read on threads, so neither blocks the other, and
+        // the lines are passed on here as they arrive.
+        let (tx, rx) = mpsc::channel();
+        fn read_lines(pipe: impl Read + Send + 'static, tx: mpsc::Sender<String>) {
+            thread::spawn(move || {
+                for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        read_lines(child.stdout.take().unwrap(), tx.clone());
+        read_lines(child.stderr.take().unwrap(), tx);
+        for line in rx {
+            on_line(&line);
+        }
+        Ok(Output {
+            status: child.wait()?,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn spawn(&self, mut cmd: Command, stdin: Option<&[u8]>) -> io::Result<Process> {
+        let mut child = cmd.spawn()?;
+        if let Some(data) = stdin {
+            child.stdin.take().unwrap().write_all(data)?;
+        }
+        Ok(Process::Running(child))
+    }
+}
+
+/// An `Executor` for tests, which records the commands instead of running
+/// them, and returns the output scripted with `respond`.
+///
+/// Commands are written as `git checkout abc`, prefixed by `cd DIR && ` if
+/// run in another directory. Commands without a response succeed with no
+/// output.
+#[derive(Default)]
+pub struct Scripted {
+    responses: RefCell<Vec<(String, Output)>>,
+    commands: RefCell<Vec<String>>,
+}
+
+impl Scripted {
+    /// Returns `stdout` and the exit `code` the next time `command` runs.
+    pub fn respond(&self, command: &str, code: i32, stdout: &str) -> &Scripted {
+        #[cfg(unix)]
+        let status = ExitStatus::from_raw(code << 8);
+        #[cfg(windows)]
+        let status = ExitStatus::from_raw(code as u32);
+        self.responses.borrow_mut().push((
+            command.to_string(),
+            Output {
+                status,
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            },
+        ));
+        self
+    }
+
+    /// The commands run so far.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.borrow().clone()
+    }
+}
+
+impl Executor for Scripted {
+    fn output(&self, cmd: Command, _stdin: Option<&[u8]>) -> io::Result<Output> {
+        let mut line = String::new();
+        if let Some(dir) = cmd.get_current_dir() {
+            line.push_str(&format!("cd {} && ", dir.display()));
+        }
+        line.push_str(&cmd.get_program().to_string_lossy());
+        for arg in cmd.get_args() {
+            line.push(' ');
+            line.push_str(&arg.to_string_lossy());
+        }
+        self.commands.borrow_mut().push(line.clone());
+        let mut responses = self.responses.borrow_mut();
+        match responses.iter().position(|(command, _)| *command == line) {
+            Some(i) => Ok(responses.remove(i).1),
+            None => Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Counts the processes started by runners, by category such as
+/// `git log` or `cargo`.
+#[derive(Clone, Default)]
+pub struct SpawnCounter {
+    counts: Rc<RefCell<BTreeMap<String, usize>>>,
+}
+
+impl SpawnCounter {
+    fn record(&self, category: String) {
+        *self.counts.borrow_mut().entry(category).or_insert(0) += 1;
+    }
+
+    /// The number of processes in each category, most frequent first.
+    pub fn counts(&self) -> Vec<(String, usize)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .borrow()
+            .iter()
+            .map(|(category, count)| (category.clone(), *count))
+            .collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.borrow().values().sum()
+    }
+}
 
 /// Helper for using `Command`.
 #[must_use]
@@ -8,10 +222,28 @@ pub struct Runner {
     program: OsString,
     args: Vec<OsString>,
     cmd_str: String,
-    dir: Option<String>,
+    dir: Option<PathBuf>,
     env: Vec<(OsString, OsString)>,
     wants_success: bool,
     inherit_stdout: bool,
+    inherit_stderr: bool,
+    /// Where to report the command before running it.
+    events: Option<EventBus>,
+    /// Discard stdout and never echo the command.
+    quiet: bool,
+    /// Data to write to stdin.
+    stdin: Option<Vec<u8>>,
+    /// Only report the command instead of running it with `run`.
+    dry_run: bool,
+    /// Send the output of `run` as events instead of showing it.
+    stream_output: bool,
+    /// The command changes something on a remote.
+    remote: bool,
+    /// Refuse to run commands that change something on a remote.
+    local_only: bool,
+    executor: Rc<dyn Executor>,
+    /// Where to count the process when it starts.
+    counter: Option<SpawnCounter>,
 }
 
 impl Runner {
@@ -30,6 +262,16 @@ impl Runner {
             env: Vec::new(),
             wants_success: true,
             inherit_stdout: true,
+            inherit_stderr: true,
+            events: None,
+            quiet: false,
+            stdin: None,
+            dry_run: false,
+            stream_output: false,
+            remote: false,
+            local_only: false,
+            executor: Rc::new(System),
+            counter: None,
         }
     }
 
@@ -43,8 +285,8 @@ impl Runner {
         self
     }
 
-    pub fn dir(mut self, dir: &str) -> Runner {
-        self.dir = Some(dir.to_string());
+    pub fn dir(mut self, dir: impl AsRef<Path>) -> Runner {
+        self.dir = Some(dir.as_ref().to_path_buf());
         self
     }
 
@@ -54,54 +296,313 @@ impl Runner {
         self
     }
 
-    pub fn capture_stdout(&mut self, err_context: impl Into<String>) -> Result<String, Error> {
+    /// Adds every environment variable in `envs`.
+    pub fn envs<K, V>(mut self, envs: impl IntoIterator<Item = (K, V)>) -> Runner
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env.extend(
+            envs.into_iter()
+                .map(|(k, v)| (k.as_ref().to_os_string(), v.as_ref().to_os_string())),
+        );
+        self
+    }
+
+    /// Discards stdout, and does not echo the command in verbose mode.
+    pub fn quiet(mut self) -> Runner {
+        self.quiet = true;
+        self
+    }
+
+    /// Writes the given data to the command's stdin.
+    pub fn stdin(mut self, data: impl Into<Vec<u8>>) -> Runner {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    /// Sends a `CommandRun` event before running the command.
+    pub fn events(mut self, events: EventBus) -> Runner {
+        self.events = Some(events);
+        self
+    }
+
+    /// In dry-run mode, `run` only reports the command instead of running it.
+    /// Captured commands are assumed to only read state, and always run.
+    pub fn dry_run(mut self, dry_run: bool) -> Runner {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// With `run`, sends each line of output as a `CommandOutput` event
+    /// instead of showing it on the terminal.
+    pub fn stream_output(mut self, stream_output: bool) -> Runner {
+        self.stream_output = stream_output;
+        self
+    }
+
+    /// Runs the command with `executor` instead of as a process.
+    pub fn executor(mut self, executor: Rc<dyn Executor>) -> Runner {
+        self.executor = executor;
+        self
+    }
+
+    /// Counts the process in `counter` when it starts.
+    pub fn counter(mut self, counter: SpawnCounter) -> Runner {
+        self.counter = Some(counter);
+        self
+    }
+
+    /// Marks the command as changing something on a remote, such as
+    /// pushing or posting a comment. In dry-run mode it is only reported,
+    /// even if its output is captured.
+    pub fn remote(mut self) -> Runner {
+        self.remote = true;
+        self
+    }
+
+    /// Makes commands marked with `remote` fail instead of running.
+    pub fn local_only(mut self, local_only: bool) -> Runner {
+        self.local_only = local_only;
+        self
+    }
+
+    /// Runs the command even in dry-run mode. This is for commands like
+    /// `git fetch` that do not change the working tree or branches.
+    pub fn read_only(mut self) -> Runner {
+        self.dry_run = false;
+        self
+    }
+
+    /// Runs the command, capturing both stdout and stderr.
+    ///
+    /// If the command fails, the captured stderr is included in the error.
+    pub fn capture(&mut self, err_context: impl Into<String>) -> Result<CommandOutput, Error> {
         self.inherit_stdout = false;
+        self.inherit_stderr = false;
         let output = self.run(err_context)?;
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        Ok(CommandOutput {
+            status: output.status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    pub fn capture_stdout(&mut self, err_context: impl Into<String>) -> Result<String, Error> {
+        Ok(self.capture(err_context)?.stdout.trim().to_string())
     }
 
     pub fn status(&mut self, err_context: impl Into<String>) -> Result<ExitStatus, Error> {
         self.wants_success = false;
+        Ok(self.capture(err_context)?.status)
+    }
+
+    /// Starts the command without waiting for it, capturing both stdout and
+    /// stderr. Use `Spawned::wait` to get the output.
+    ///
+    /// Like `capture`, this always runs, even in dry-run mode, unless it is
+    /// marked with `remote`.
+    pub fn spawn(mut self, err_context: impl Into<String>) -> Result<Spawned, Error> {
         self.inherit_stdout = false;
-        let output = self.run(err_context)?;
-        Ok(output.status)
+        self.inherit_stderr = false;
+        let simulate = self.simulates();
+        self.check_remote(simulate)?;
+        self.emit_run(simulate)?;
+        let process = if simulate {
+            Ok(Process::Finished(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            }))
+        } else {
+            self.count();
+            self.executor.spawn(self.command(), self.stdin.as_deref())
+        };
+        match process {
+            Ok(process) => Ok(Spawned {
+                process,
+                runner: self,
+                err_context: err_context.into(),
+            }),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to run command: {}", self.cmd_str))
+                .with_context(|| err_context.into()),
+        }
     }
 
     pub fn run(&mut self, err_context: impl Into<String>) -> Result<Output, Error> {
+        let simulate = self.simulates();
+        self.check_remote(simulate)?;
+        self.emit_run(simulate)?;
+        if simulate {
+            return Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        }
+        self.count();
+        if let (true, Some(events)) = (self.streams(), &self.events) {
+            let mut result = Ok(());
+            let output = self
+                .executor
+                .stream(self.command(), self.stdin.as_deref(), &mut |line| {
+                    if result.is_ok() {
+                        result = events.emit(&Event::CommandOutput(line));
+                    }
+                });
+            result?;
+            return self.check(output, err_context.into());
+        }
+        let output = self.executor.output(self.command(), self.stdin.as_deref());
+        self.check(output, err_context.into())
+    }
+
+    fn count(&self) {
+        if let Some(counter) = &self.counter {
+            counter.record(self.category());
+        }
+    }
+
+    /// The program, and for git the subcommand after any `-c`/`-C` options.
+    fn category(&self) -> String {
+        let program = Path::new(&self.program)
+            .file_name()
+            .unwrap_or(&self.program)
+            .to_string_lossy()
+            .into_owned();
+        if program != "git" {
+            return program;
+        }
+        let mut args = self.args.iter().map(|arg| arg.to_string_lossy());
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "-c" | "-C" => {
+                    args.next();
+                }
+                arg if arg.starts_with('-') => {}
+                subcommand => return format!("git {}", subcommand),
+            }
+        }
+        program
+    }
+
+    /// Whether the command is only reported because of dry-run mode.
+    fn simulates(&self) -> bool {
+        self.dry_run && (self.inherit_stdout || self.remote)
+    }
+
+    /// Fails if the command would change a remote with `local_only`.
+    fn check_remote(&self, simulate: bool) -> Result<(), Error> {
+        if self.remote && self.local_only && !simulate {
+            bail!(
+                "Refusing to run `{}` with --local-only, it changes a remote.",
+                self.cmd_str
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether the output of `run` is sent as events.
+    fn streams(&self) -> bool {
+        self.stream_output && self.inherit_stdout && !self.quiet && self.events.is_some()
+    }
+
+    fn emit_run(&self, dry_run: bool) -> Result<(), Error> {
+        if let Some(events) = &self.events {
+            let dir = match self.dir {
+                Some(ref dir) if !dir.as_os_str().is_empty() => dir.as_path(),
+                _ => Path::new("."),
+            };
+            events.emit(&Event::CommandRun {
+                command: &self.cmd_str,
+                dir,
+                dry_run,
+                quiet: self.quiet,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn command(&self) -> Command {
         let mut cmd = Command::new(&self.program);
-        cmd.args(&self.args).stderr(Stdio::inherit());
-        if self.inherit_stdout {
+        cmd.args(&self.args);
+        if self.streams() {
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+        } else if self.quiet && self.inherit_stdout {
+            cmd.stdout(Stdio::null());
+        } else if self.inherit_stdout {
             cmd.stdout(Stdio::inherit());
         } else {
             cmd.stdout(Stdio::piped());
         };
+        if self.streams() {
+            // Piped above.
+        } else if self.inherit_stderr {
+            cmd.stderr(Stdio::inherit());
+        } else {
+            cmd.stderr(Stdio::piped());
+        };
+        if self.stdin.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
         if let Some(ref dir) = self.dir {
-            if !dir.is_empty() {
+            if !dir.as_os_str().is_empty() {
                 cmd.current_dir(dir);
             }
         }
         for (key, value) in &self.env {
             cmd.env(key, value);
         }
-        let output = cmd.output();
+        cmd
+    }
+
+    /// Checks the exit status of a finished command.
+    fn check(&self, output: io::Result<Output>, err_context: String) -> Result<Output, Error> {
         match output {
             Ok(output) => {
                 if output.status.code().is_none()
                     || (self.wants_success && output.status.code() != Some(0))
                     || (output.status.code() != Some(0) && output.status.code() != Some(1))
                 {
-                    Err(
-                        anyhow::format_err!("Command exit status {:?}", output.status.code())
-                            .context(format!("Failed to run command: {}", self.cmd_str))
-                            .context(err_context.into()),
-                    )
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    let mut msg = format!("Command exit status {:?}", output.status.code());
+                    if !stderr.trim().is_empty() {
+                        msg.push('\n');
+                        msg.push_str(stderr.trim());
+                    }
+                    Err(anyhow::format_err!(msg)
+                        .context(format!("Failed to run command: {}", self.cmd_str))
+                        .context(err_context))
                 } else {
                     Ok(output)
                 }
             }
             Err(e) => Err(e)
                 .with_context(|| format!("Failed to run command: {}", self.cmd_str))
-                .with_context(|| err_context.into()),
+                .context(err_context),
         }
     }
 }
+
+/// A command started with `Runner::spawn`.
+#[must_use]
+pub struct Spawned {
+    process: Process,
+    runner: Runner,
+    err_context: String,
+}
+
+impl Spawned {
+    /// Waits for the command to finish.
+    pub fn wait(self) -> Result<CommandOutput, Error> {
+        let output = self.process.wait();
+        let output = self.runner.check(output, self.err_context)?;
+        Ok(CommandOutput {
+            status: output.status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
@@ -0,0 +1,112 @@
+//! Update plans read from a shared "tool sync schedule" file.
+//!
+//! The schedule maps each tool (a submodule name or path) to the revision
+//! to update it to and who owns the update, as TOML:
+//!
+//! ```toml
+//! [cargo]
+//! rev = "master"
+//! owner = "ehuss"
+//! ```
+//!
+//! or the same structure as JSON.
+
+use crate::cli::Cli;
+use anyhow::{bail, Context, Error};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// The plan for one tool.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// The branch or revision to update to, the default branch if not given.
+    #[serde(alias = "branch")]
+    pub rev: Option<String>,
+    /// Who coordinates updates of the tool.
+    pub owner: Option<String>,
+}
+
+impl Entry {
+    /// The `[rev:]path` command-line argument for updating `tool`.
+    pub fn submodule_arg(&self, tool: &str) -> String {
+        match &self.rev {
+            Some(rev) => format!("{}:{}", rev, tool),
+            None => tool.to_string(),
+        }
+    }
+}
+
+/// Parses a schedule as JSON if it looks like a JSON object, otherwise as
+/// TOML.
+pub fn parse(text: &str) -> Result<BTreeMap<String, Entry>, Error> {
+    let schedule: BTreeMap<String, Entry> = if text.trim_start().starts_with('{') {
+        serde_json::from_str(text).context("Invalid JSON schedule")?
+    } else {
+        toml::from_str(text).context("Invalid TOML schedule")?
+    };
+    if schedule.is_empty() {
+        bail!("The schedule does not list any tools");
+    }
+    Ok(schedule)
+}
+
+/// Downloads and parses the schedule at `url`.
+pub fn fetch(cli: &Cli<'_>, url: &str) -> Result<BTreeMap<String, Entry>, Error> {
+    if !url.starts_with("https://") {
+        bail!("The schedule URL `{}` must use https", url);
+    }
+    let text = cli
+        .runner(
+            "curl",
+            &[
+                "--silent",
+                "--show-error",
+                "--fail",
+                "--location",
+                "--proto",
+                "=https",
+                url,
+            ],
+        )
+        .read_only()
+        .capture_stdout(format!("Failed to download schedule `{}`", url))?;
+    parse(&text).with_context(|| format!("Failed to parse schedule `{}`", url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_and_json() {
+        let toml = parse(
+            r#"
+            [cargo]
+            rev = "rust-1.50.0"
+            owner = "ehuss"
+
+            ["src/tools/rls"]
+            "#,
+        )
+        .unwrap();
+        let json =
+            parse(r#"{"cargo": {"branch": "rust-1.50.0", "owner": "ehuss"}, "src/tools/rls": {}}"#)
+                .unwrap();
+        assert_eq!(toml, json);
+        let args: Vec<_> = toml
+            .iter()
+            .map(|(tool, entry)| entry.submodule_arg(tool))
+            .collect();
+        assert_eq!(args, ["rust-1.50.0:cargo", "src/tools/rls"]);
+        assert_eq!(toml["cargo"].owner.as_deref(), Some("ehuss"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("{}").is_err());
+        assert!(parse("[cargo]\nrevision = \"master\"").is_err());
+        assert!(parse("cargo = 1").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! Parsing of the submodules given on the command line.
+
+use anyhow::{bail, format_err, Error};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::Path;
+
+/// A submodule argument of the form `[rev:]path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleSpec {
+    pub path: String,
+    /// The branch or revision to update to, if given.
+    pub rev: Option<String>,
+}
+
+/// Parses a `[rev:]path` submodule argument.
+///
+/// The rev is everything before the last colon, so that revs such as
+/// `@{2019-01-01 12:00:00}` may contain colons. Paths are normalized by
+/// removing a trailing slash (as added by shell completion) and a leading
+/// `./`.
+pub fn parse_submodule_spec(spec: &str) -> Result<SubmoduleSpec, Error> {
+    let (rev, path) = match spec.rsplit_once(':') {
+        Some((rev, path)) => {
+            if rev.is_empty() {
+                bail!("Empty branch or revision in `{}`", spec);
+            }
+            (Some(rev.to_string()), path)
+        }
+        None => (None, spec),
+    };
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    if path.is_empty() {
+        bail!("Missing submodule path in `{}`", spec);
+    }
+    Ok(SubmoduleSpec {
+        path: path.to_string(),
+        rev,
+    })
+}
+
+/// Finds the path of the submodule named by `path` in `entries`, which are
+/// `(name, path)` pairs from `.gitmodules`.
+///
+/// An exact path match is preferred. Otherwise the submodule may be given by
+/// its name in `.gitmodules`, or by the last component of its path, as long
+/// as that is not ambiguous.
+pub fn resolve_submodule_path<'a>(
+    path: &str,
+    entries: &'a [(String, String)],
+) -> Result<&'a str, Error> {
+    let matches = submodule_candidates(path, entries);
+    match matches.as_slice() {
+        [p] => Ok(p),
+        [] => Err(format_err!(
+            "Could not find submodule `{}` in git modules.",
+            path
+        )),
+        _ => bail!(
+            "Submodule `{}` is ambiguous, it could be any of: {}",
+            path,
+            matches.join(", ")
+        ),
+    }
+}
+
+/// The paths of the submodules that `path` may refer to, see
+/// `resolve_submodule_path`.
+pub fn submodule_candidates<'a>(path: &str, entries: &'a [(String, String)]) -> Vec<&'a str> {
+    if let Some((_name, p)) = entries.iter().find(|(_name, p)| p == path) {
+        return vec![p];
+    }
+    entries
+        .iter()
+        .filter(|(name, p)| {
+            name == path || Path::new(p).file_name().and_then(|f| f.to_str()) == Some(path)
+        })
+        .map(|(_name, p)| p.as_str())
+        .collect()
+}
+
+/// Whether a submodule path is a glob pattern.
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Matches `path` against a glob `pattern`, where `*` and `?` do not match
+/// `/`, and `**` matches anything.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).unwrap().is_match(path)
+}
+
+/// Expands the glob patterns in `[rev:]path` submodule arguments against
+/// the `(name, path)` pairs from `.gitmodules`, and removes the submodules
+/// matching `excludes` (paths, names, or patterns).
+///
+/// A pattern that matches nothing is an error. Submodules that are also
+/// given explicitly are not repeated.
+pub fn expand_submodule_args(
+    args: &[String],
+    excludes: &[&str],
+    entries: &[(String, String)],
+) -> Result<Vec<String>, Error> {
+    let excludes = excludes
+        .iter()
+        .map(|exclude| {
+            let exclude = exclude.trim_start_matches("./").trim_end_matches('/');
+            if is_glob(exclude) {
+                Ok(exclude)
+            } else {
+                resolve_submodule_path(exclude, entries)
+            }
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let is_excluded = |path: &str| excludes.iter().any(|exclude| glob_matches(exclude, path));
+    let specs = args
+        .iter()
+        .map(|arg| parse_submodule_spec(arg))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let explicit: Vec<&str> = specs
+        .iter()
+        .filter(|spec| !is_glob(&spec.path))
+        .map(|spec| resolve_submodule_path(&spec.path, entries).unwrap_or(&spec.path))
+        .collect();
+    let mut result = Vec::new();
+    for (arg, spec) in args.iter().zip(&specs) {
+        if !is_glob(&spec.path) {
+            let path = resolve_submodule_path(&spec.path, entries).unwrap_or(&spec.path);
+            if !is_excluded(path) {
+                result.push(arg.clone());
+            }
+            continue;
+        }
+        let matches: Vec<&str> = entries
+            .iter()
+            .map(|(_name, path)| path.as_str())
+            .filter(|path| glob_matches(&spec.path, path))
+            .collect();
+        if matches.is_empty() {
+            bail!("Pattern `{}` did not match any submodules.", spec.path);
+        }
+        for path in matches {
+            if is_excluded(path) || explicit.contains(&path) {
+                continue;
+            }
+            result.push(match &spec.rev {
+                Some(rev) => format!("{}:{}", rev, path),
+                None => path.to_string(),
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// A `--blocked-by` value: updates to `submodule` should wait until the
+/// blocking PRs are closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedBy {
+    /// Submodule path or name, or `*` for any submodule.
+    pub submodule: String,
+    pub blocker: Blocker,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Blocker {
+    /// Specific PRs, as `(owner/name, number)`.
+    Prs(Vec<(String, u64)>),
+    /// A GitHub search query, any open PR it finds is blocking.
+    Search(String),
+}
+
+/// Parses `SUBMODULE=PRS` or `SUBMODULE=QUERY`, where PRS is a
+/// comma-separated list of `owner/name#N` or PR URLs.
+pub fn parse_blocked_by(spec: &str) -> Result<BlockedBy, Error> {
+    lazy_static! {
+        static ref PR_RE: Regex =
+            Regex::new(r"^(?:https://github\.com/)?([\w.-]+/[\w.-]+)(?:#|/pull/)([0-9]+)$")
+                .unwrap();
+    }
+    let (submodule, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format_err!("`{}` should be SUBMODULE=PRS or SUBMODULE=QUERY", spec))?;
+    let (submodule, value) = (submodule.trim(), value.trim());
+    if submodule.is_empty() || value.is_empty() {
+        bail!("`{}` should be SUBMODULE=PRS or SUBMODULE=QUERY", spec);
+    }
+    let prs: Option<Vec<(String, u64)>> = value
+        .split(',')
+        .map(|pr| {
+            let c = PR_RE.captures(pr.trim())?;
+            Some((c[1].to_string(), c[2].parse().ok()?))
+        })
+        .collect();
+    Ok(BlockedBy {
+        submodule: submodule.to_string(),
+        blocker: match prs {
+            Some(prs) => Blocker::Prs(prs),
+            None => Blocker::Search(value.to_string()),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn spec(path: &str, rev: Option<&str>) -> SubmoduleSpec {
+        SubmoduleSpec {
+            path: path.to_string(),
+            rev: rev.map(|r| r.to_string()),
+        }
+    }
+
+    fn entries() -> Vec<(String, String)> {
+        [
+            ("cargo", "src/tools/cargo"),
+            ("book", "src/doc/book"),
+            ("src/llvm-project", "src/llvm-project"),
+            ("rustc-perf", "src/tools/rustc-perf"),
+            ("vendor-perf", "vendor/rustc-perf"),
+        ]
+        .iter()
+        .map(|(n, p)| (n.to_string(), p.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn plain_paths() {
+        assert_eq!(
+            parse_submodule_spec("src/tools/cargo").unwrap(),
+            spec("src/tools/cargo", None)
+        );
+        assert_eq!(
+            parse_submodule_spec("./src/tools/cargo/").unwrap(),
+            spec("src/tools/cargo", None)
+        );
+    }
+
+    #[test]
+    fn revs() {
+        assert_eq!(
+            parse_submodule_spec("rust-1.28.0:src/tools/cargo").unwrap(),
+            spec("src/tools/cargo", Some("rust-1.28.0"))
+        );
+        assert_eq!(
+            parse_submodule_spec("@{2019-01-01 12:00:00}:src/doc/book").unwrap(),
+            spec("src/doc/book", Some("@{2019-01-01 12:00:00}"))
+        );
+        assert_eq!(
+            parse_submodule_spec("latest-release-tag:src/tools/cargo").unwrap(),
+            spec("src/tools/cargo", Some("latest-release-tag"))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_submodule_spec("").is_err());
+        assert!(parse_submodule_spec(":src/tools/cargo").is_err());
+        assert!(parse_submodule_spec("master:").is_err());
+        assert!(parse_submodule_spec("master:/").is_err());
+    }
+
+    #[test]
+    fn resolve() {
+        let entries = entries();
+        let resolve = |path| resolve_submodule_path(path, &entries);
+        assert_eq!(resolve("src/tools/cargo").unwrap(), "src/tools/cargo");
+        assert_eq!(resolve("cargo").unwrap(), "src/tools/cargo");
+        assert_eq!(resolve("book").unwrap(), "src/doc/book");
+        assert_eq!(resolve("llvm-project").unwrap(), "src/llvm-project");
+        assert_eq!(resolve("vendor-perf").unwrap(), "vendor/rustc-perf");
+        assert!(resolve("src/tools/miri").is_err());
+        let err = resolve("rustc-perf").unwrap_err().to_string();
+        assert!(err.contains("ambiguous"), "{}", err);
+        assert_eq!(
+            submodule_candidates("rustc-perf", &entries),
+            ["src/tools/rustc-perf", "vendor/rustc-perf"]
+        );
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("src/tools/*", "src/tools/cargo"));
+        assert!(!glob_matches("src/tools/*", "src/tools/cargo/sub"));
+        assert!(glob_matches("src/**", "src/tools/cargo/sub"));
+        assert!(glob_matches("src/doc/?ook", "src/doc/book"));
+        assert!(!glob_matches("src/tools", "src/tools/cargo"));
+        assert!(!is_glob("src/tools/cargo"));
+    }
+
+    #[test]
+    fn expand() {
+        let entries = entries();
+        let expand = |args: &[&str], excludes: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            expand_submodule_args(&args, excludes, &entries)
+        };
+        assert_eq!(
+            expand(&["master:src/tools/*"], &["src/tools/rustc-perf"]).unwrap(),
+            ["master:src/tools/cargo"]
+        );
+        assert_eq!(
+            expand(&["src/*/*", "beta:cargo"], &["src/doc/*"]).unwrap(),
+            ["src/tools/rustc-perf", "beta:cargo"]
+        );
+        assert_eq!(
+            expand(&["src/llvm-project", "src/tools/cargo"], &["cargo"]).unwrap(),
+            ["src/llvm-project"]
+        );
+        let err = expand(&["src/nothing/*"], &[]).unwrap_err().to_string();
+        assert!(err.contains("did not match"), "{}", err);
+        assert!(expand(&["src/tools/cargo"], &["src/tools/miri"]).is_err());
+    }
+
+    #[test]
+    fn blocked_by() {
+        assert_eq!(
+            parse_blocked_by(
+                "src/tools/cargo=rust-lang/rust#123, https://github.com/rust-lang/cargo/pull/45"
+            )
+            .unwrap(),
+            BlockedBy {
+                submodule: "src/tools/cargo".to_string(),
+                blocker: Blocker::Prs(vec![
+                    ("rust-lang/rust".to_string(), 123),
+                    ("rust-lang/cargo".to_string(), 45)
+                ]),
+            }
+        );
+        assert_eq!(
+            parse_blocked_by("*=repo:rust-lang/rust label:S-blocked bootstrap").unwrap(),
+            BlockedBy {
+                submodule: "*".to_string(),
+                blocker: Blocker::Search(
+                    "repo:rust-lang/rust label:S-blocked bootstrap".to_string()
+                ),
+            }
+        );
+        assert!(parse_blocked_by("src/tools/cargo").is_err());
+        assert!(parse_blocked_by("src/tools/cargo=").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(
+            rev in proptest::option::of("[@{}a-zA-Z0-9._ :-]*[^:]"),
+            path in "[a-z0-9_-]+(/[a-z0-9_-]+)*",
+        ) {
+            let arg = match &rev {
+                Some(rev) => format!("{}:{}", rev, path),
+                None => path.clone(),
+            };
+            let parsed = parse_submodule_spec(&arg).unwrap();
+            prop_assert_eq!(parsed, SubmoduleSpec { path, rev });
+        }
+
+        #[test]
+        fn never_panics(arg in "\\PC*") {
+            if let Ok(parsed) = parse_submodule_spec(&arg) {
+                prop_assert!(!parsed.path.is_empty());
+                prop_assert!(!parsed.path.contains(':'));
+                prop_assert!(!parsed.path.ends_with('/'));
+                prop_assert_ne!(parsed.rev.as_deref(), Some(""));
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Commit titles and branch names for submodule updates.
+
+use std::path::Path;
+
+/// The name used for a submodule in titles and branch names, which is the
+/// last component of its path.
+pub fn submodule_name(path: &str) -> &str {
+    // Strip a `branch:` prefix from command-line arguments.
+    let path = path.rsplit(':').next().unwrap();
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+/// Whether `path` (possibly with a `branch:` prefix) is one of the
+/// documentation books in `src/doc`.
+pub fn is_book(path: &str) -> bool {
+    path.rsplit(':').next().unwrap().starts_with("src/doc/")
+}
+
+/// The names for the titles of `paths`, without duplicates, keeping the
+/// first occurrence. Several books are called `books`.
+fn unique<'a>(paths: &[&'a str]) -> Vec<&'a str> {
+    if paths.len() > 1 && paths.iter().all(|path| is_book(path)) {
+        return vec!["books"];
+    }
+    let mut result = Vec::new();
+    for name in paths.iter().map(|path| submodule_name(path)) {
+        if !result.contains(&name) {
+            result.push(name);
+        }
+    }
+    result
+}
+
+/// The commit and PR title, such as `Update cargo` or
+/// `[beta] Update cargo, rls`.
+///
+/// `version` is included when updating to a specific release. A
+/// `downgrade` to older commits is titled `Revert cargo to an older commit`.
+pub fn commit_title(
+    rust_branch: &str,
+    paths: &[&str],
+    version: Option<&str>,
+    downgrade: bool,
+) -> String {
+    let mut title = String::new();
+    if rust_branch != "master" {
+        title.push_str(&format!("[{}] ", rust_branch));
+    }
+    let names = unique(paths);
+    let verb = if downgrade { "Revert" } else { "Update" };
+    title.push_str(&format!("{} {}", verb, names.join(", ")));
+    match version {
+        Some(version) => title.push_str(&format!(" to {}", version)),
+        None if downgrade => title.push_str(" to an older commit"),
+        None => {}
+    }
+    title
+}
+
+/// The name of the branch to create, such as `update-beta-cargo-rls`.
+pub fn branch_name(rust_branch: &str, paths: &[&str]) -> String {
+    let mut parts = vec!["update"];
+    if rust_branch != "master" {
+        parts.push(rust_branch);
+    }
+    // Submodules may share a name (such as a vendored copy).
+    parts.extend(unique(paths));
+    parts.join("-")
+}
+
+/// The rust branch that a branch created by `branch_name` is based on.
+pub fn branch_base(branch: &str) -> &str {
+    match branch.split('-').nth(1) {
+        Some(base @ ("beta" | "stable")) => base,
+        _ => "master",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_master() {
+        assert_eq!(
+            commit_title("master", &["src/tools/cargo"], None, false),
+            "Update cargo"
+        );
+        assert_eq!(
+            commit_title("master", &["src/tools/cargo", "src/doc/book"], None, false),
+            "Update cargo, book"
+        );
+        assert_eq!(
+            commit_title(
+                "master",
+                &["src/doc/nomicon", "src/doc/reference"],
+                None,
+                false
+            ),
+            "Update books"
+        );
+    }
+
+    #[test]
+    fn title_beta() {
+        assert_eq!(
+            commit_title("beta", &["src/tools/cargo"], None, false),
+            "[beta] Update cargo"
+        );
+        assert_eq!(
+            commit_title(
+                "stable",
+                &["rust-1.28.0:src/tools/cargo"],
+                Some("0.29.0"),
+                false
+            ),
+            "[stable] Update cargo to 0.29.0"
+        );
+    }
+
+    #[test]
+    fn title_downgrade() {
+        assert_eq!(
+            commit_title("master", &["src/tools/cargo"], None, true),
+            "Revert cargo to an older commit"
+        );
+        assert_eq!(
+            commit_title("beta", &["src/tools/cargo"], Some("0.29.0"), true),
+            "[beta] Revert cargo to 0.29.0"
+        );
+    }
+
+    #[test]
+    fn branch_names() {
+        assert_eq!(
+            branch_name("master", &["src/tools/cargo", "src/tools/rls"]),
+            "update-cargo-rls"
+        );
+        assert_eq!(
+            branch_name("beta", &["rust-1.28.0:src/tools/cargo"]),
+            "update-beta-cargo"
+        );
+        assert_eq!(
+            branch_name("master", &["src/tools/cargo", "vendor/cargo"]),
+            "update-cargo"
+        );
+        assert_eq!(
+            branch_name("beta", &["src/doc/book", "beta:src/doc/reference"]),
+            "update-beta-books"
+        );
+        assert_eq!(branch_base("update-beta-cargo"), "beta");
+        assert_eq!(branch_base("update-cargo-rls"), "master");
+        assert_eq!(branch_base("update-stable-cargo"), "stable");
+    }
+}
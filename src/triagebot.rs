@@ -0,0 +1,196 @@
+//! Text added to the PR body for rust-lang's triagebot, and for pinging
+//! the maintainers of a submodule.
+
+use anyhow::{bail, format_err, Error};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Directive that tells triagebot not to assign a reviewer.
+pub const NO_REVIEW_ASSIGN: &str = "r? ghost";
+
+/// A `--pr-snippet` value, added to the PR body when `submodule` is updated.
+#[derive(Debug, PartialEq)]
+pub struct Snippet {
+    /// Submodule path, or `*` for any submodule.
+    pub submodule: String,
+    pub text: String,
+}
+
+impl Snippet {
+    /// Parses `SUBMODULE=TEXT`.
+    ///
+    /// Mentions other than `@rustbot` are rejected, since they would ping
+    /// someone every time the PR is opened, use `Cc` for that instead.
+    pub fn parse(spec: &str) -> Result<Snippet, Error> {
+        lazy_static! {
+            static ref MENTION_RE: Regex =
+                Regex::new(r"(?:^|[^\w`@])@([A-Za-z0-9][A-Za-z0-9-]*)").unwrap();
+        }
+        let (submodule, text) = spec
+            .split_once('=')
+            .ok_or_else(|| format_err!("PR snippet `{}` should be SUBMODULE=TEXT", spec))?;
+        let (submodule, text) = (submodule.trim(), text.trim());
+        if submodule.is_empty() || text.is_empty() {
+            bail!("PR snippet `{}` should be SUBMODULE=TEXT", spec);
+        }
+        for cap in MENTION_RE.captures_iter(text) {
+            if &cap[1] != "rustbot" {
+                bail!(
+                    "PR snippet `{}` would ping @{}, only @rustbot commands are allowed",
+                    spec,
+                    &cap[1]
+                );
+            }
+        }
+        Ok(Snippet {
+            submodule: submodule.to_string(),
+            text: text.to_string(),
+        })
+    }
+}
+
+/// A `--cc` value, the people to mention when `submodule` is updated.
+#[derive(Debug, PartialEq)]
+pub struct Cc {
+    /// Submodule path, or `*` for any submodule.
+    pub submodule: String,
+    /// GitHub user or team names, without the `@`.
+    pub users: Vec<String>,
+}
+
+impl Cc {
+    /// Parses `SUBMODULE=USER[,USER...]`, the `@` of each user is optional.
+    pub fn parse(spec: &str) -> Result<Cc, Error> {
+        lazy_static! {
+            static ref USER_RE: Regex =
+                Regex::new(r"^[A-Za-z0-9][A-Za-z0-9-]*(/[A-Za-z0-9][A-Za-z0-9_-]*)?$").unwrap();
+        }
+        let (submodule, users) = spec
+            .split_once('=')
+            .ok_or_else(|| format_err!("cc `{}` should be SUBMODULE=USER[,USER...]", spec))?;
+        let submodule = submodule.trim();
+        let users: Vec<String> = users
+            .split(',')
+            .map(|user| user.trim().trim_start_matches('@').to_string())
+            .filter(|user| !user.is_empty())
+            .collect();
+        if submodule.is_empty() || users.is_empty() {
+            bail!("cc `{}` should be SUBMODULE=USER[,USER...]", spec);
+        }
+        if let Some(user) = users.iter().find(|user| !USER_RE.is_match(user)) {
+            bail!("cc `{}` has an invalid GitHub user `{}`", spec, user);
+        }
+        Ok(Cc {
+            submodule: submodule.to_string(),
+            users,
+        })
+    }
+}
+
+fn matches(submodule: &str, paths: &[&str]) -> bool {
+    submodule == "*" || paths.contains(&submodule)
+}
+
+/// The lines to add to the PR body for the updated submodule `paths`.
+///
+/// Each snippet and user is included at most once, even if it matches
+/// several submodules.
+pub fn body_lines(
+    snippets: &[Snippet],
+    ccs: &[Cc],
+    paths: &[&str],
+    no_review_assign: bool,
+) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for snippet in snippets {
+        if matches(&snippet.submodule, paths) && !lines.contains(&snippet.text) {
+            lines.push(snippet.text.clone());
+        }
+    }
+    let mut users: Vec<&str> = Vec::new();
+    for cc in ccs.iter().filter(|cc| matches(&cc.submodule, paths)) {
+        for user in &cc.users {
+            if !users.contains(&user.as_str()) {
+                users.push(user);
+            }
+        }
+    }
+    if !users.is_empty() {
+        let mentions: Vec<String> = users.iter().map(|user| format!("@{}", user)).collect();
+        lines.push(format!("cc {}", mentions.join(" ")));
+    }
+    if no_review_assign {
+        lines.push(NO_REVIEW_ASSIGN.to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Snippet::parse("src/tools/cargo=@rustbot label +A-cargo").unwrap(),
+            Snippet {
+                submodule: "src/tools/cargo".to_string(),
+                text: "@rustbot label +A-cargo".to_string(),
+            }
+        );
+        assert!(Snippet::parse("@rustbot label +A-cargo").is_err());
+        assert!(Snippet::parse("*=").is_err());
+        assert!(Snippet::parse("*=cc @ehuss").is_err());
+        assert!(Snippet::parse("*=see `@ehuss` and a@b.com").is_ok());
+    }
+
+    #[test]
+    fn lines_for_updated_submodules() {
+        let snippets: Vec<Snippet> = [
+            "src/tools/cargo=@rustbot label +A-cargo",
+            "src/doc/book=@rustbot label +A-docs",
+            "*=@rustbot label +A-docs",
+        ]
+        .iter()
+        .map(|spec| Snippet::parse(spec).unwrap())
+        .collect();
+        assert_eq!(
+            body_lines(&snippets, &[], &["src/tools/cargo"], true),
+            [
+                "@rustbot label +A-cargo",
+                "@rustbot label +A-docs",
+                "r? ghost"
+            ]
+        );
+        assert_eq!(
+            body_lines(&snippets, &[], &["src/doc/book"], false),
+            ["@rustbot label +A-docs"]
+        );
+    }
+
+    #[test]
+    fn cc() {
+        assert_eq!(
+            Cc::parse("src/tools/miri=@RalfJung, oli-obk").unwrap(),
+            Cc {
+                submodule: "src/tools/miri".to_string(),
+                users: vec!["RalfJung".to_string(), "oli-obk".to_string()],
+            }
+        );
+        assert!(Cc::parse("src/tools/miri=").is_err());
+        assert!(Cc::parse("src/tools/miri=a b").is_err());
+        let ccs: Vec<Cc> = [
+            "src/tools/miri=RalfJung",
+            "*=ehuss",
+            "src/tools/cargo=ehuss",
+        ]
+        .iter()
+        .map(|spec| Cc::parse(spec).unwrap())
+        .collect();
+        assert_eq!(
+            body_lines(&[], &ccs, &["src/tools/miri", "src/tools/cargo"], false),
+            ["cc @RalfJung @ehuss"]
+        );
+        assert!(body_lines(&[], &ccs[..1], &["src/tools/cargo"], false).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+//! A dashboard for `--tui`, showing the steps of the pipeline, the output of
+//! the running command, and recent messages.
+//!
+//! The dashboard uses the alternate screen. It steps aside for prompts, and
+//! anything that should not be lost (warnings, boxes, the PR link) is printed
+//! to the normal screen before a prompt and when it closes.
+
+use crate::event::{Event, Sink};
+use anyhow::Error;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::crossterm::{cursor, execute};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::Terminal;
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::time::Duration;
+
+/// How many lines of output and messages to keep.
+const MAX_LINES: usize = 1000;
+
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    /// Steps that have started, with how long they took once finished.
+    steps: Vec<(String, Option<Duration>)>,
+    /// The last command that was run.
+    command: String,
+    output: VecDeque<String>,
+    messages: VecDeque<Line<'static>>,
+    /// Lines to print on the normal screen at the next prompt, or when the
+    /// dashboard closes.
+    unprinted: Vec<String>,
+}
+
+impl Dashboard {
+    /// Switches the terminal to the dashboard.
+    pub fn new() -> io::Result<Dashboard> {
+        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        Ok(Dashboard {
+            terminal,
+            steps: Vec::new(),
+            command: String::new(),
+            output: VecDeque::new(),
+            messages: VecDeque::new(),
+            unprinted: Vec::new(),
+        })
+    }
+
+    fn push_output(&mut self, line: &str) {
+        // Progress output redraws the line with `\r`, keep the last one.
+        let line = line.rsplit('\r').next().unwrap_or_default();
+        self.output.push_back(line.replace('\t', "    "));
+        if self.output.len() > MAX_LINES {
+            self.output.pop_front();
+        }
+    }
+
+    fn push_message(&mut self, label: &str, color: Color, message: &str) {
+        self.messages.push_back(Line::styled(
+            format!("{}{}", label, message),
+            Style::default().fg(color),
+        ));
+        if self.messages.len() > MAX_LINES {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Prints the lines saved for the normal screen.
+    fn print_unprinted(&mut self) {
+        for line in self.unprinted.drain(..) {
+            println!("{}", line);
+        }
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let steps: Vec<ListItem<'_>> = self
+            .steps
+            .iter()
+            .map(|(name, took)| match took {
+                Some(took) => ListItem::new(format!("✓ {} ({:.1}s)", name, took.as_secs_f64()))
+                    .style(Style::default().fg(Color::Green)),
+                None => ListItem::new(format!("▶ {}", name))
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            })
+            .collect();
+        let command = &self.command;
+        let output = &self.output;
+        let messages = &self.messages;
+        self.terminal.draw(|frame| {
+            let [main, bottom] =
+                Layout::vertical([Constraint::Min(5), Constraint::Length(10)]).areas(frame.area());
+            let [left, right] =
+                Layout::horizontal([Constraint::Length(36), Constraint::Min(20)]).areas(main);
+            frame.render_widget(
+                List::new(steps).block(Block::default().borders(Borders::ALL).title("Steps")),
+                left,
+            );
+            // The end of the output and messages, as much as fits.
+            let height = right.height.saturating_sub(2) as usize;
+            let lines: Vec<Line<'_>> = output
+                .iter()
+                .skip(output.len().saturating_sub(height))
+                .map(|line| Line::raw(line.as_str()))
+                .collect();
+            frame.render_widget(
+                Paragraph::new(lines).block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(command.as_str()),
+                ),
+                right,
+            );
+            let height = bottom.height.saturating_sub(2) as usize;
+            let lines: Vec<Line<'_>> = messages
+                .iter()
+                .skip(messages.len().saturating_sub(height))
+                .cloned()
+                .collect();
+            frame.render_widget(
+                Paragraph::new(lines)
+                    .block(Block::default().borders(Borders::ALL).title("Messages")),
+                bottom,
+            );
+        })?;
+        Ok(())
+    }
+}
+
+impl Sink for Dashboard {
+    fn event(&mut self, event: &Event<'_>) -> Result<(), Error> {
+        match *event {
+            Event::PhaseStarted { phase, .. } => self.steps.push((phase.to_string(), None)),
+            Event::PhaseFinished { phase, duration } => {
+                if let Some(step) = self.steps.iter_mut().rev().find(|(name, _)| name == phase) {
+                    step.1 = Some(duration);
+                }
+            }
+            Event::Status(message) => self.push_message("", Color::Green, message),
+            Event::Warning(message) => {
+                self.push_message("Warning: ", Color::Yellow, message);
+                self.unprinted.push(format!("Warning: {}", message));
+            }
+            Event::Info(message) => self.push_message("Info: ", Color::Blue, message),
+            Event::Progress {
+                done,
+                total,
+                message,
+            } => {
+                let message = format!("[{}/{}] {}", done, total, message);
+                self.push_message("Info: ", Color::Blue, &message)
+            }
+            Event::CommandRun {
+                command,
+                dir,
+                dry_run,
+                quiet,
+            } => {
+                let command = format!("{} (in {})", command, dir.display());
+                if dry_run {
+                    self.push_message("Would run: ", Color::Blue, &command);
+                } else if !quiet {
+                    self.push_output(&format!("$ {}", command));
+                    self.command = command;
+                }
+            }
+            Event::CommandOutput(line) => self.push_output(line),
+            Event::Boxed { title, lines } => {
+                self.push_message("", Color::Reset, title);
+                self.unprinted.push(format!("== {} ==", title));
+                for line in lines {
+                    self.push_message("  ", Color::Reset, line);
+                    self.unprinted.push(line.clone());
+                }
+            }
+            Event::PromptStarted => {
+                execute!(io::stdout(), LeaveAlternateScreen, cursor::Show)?;
+                self.print_unprinted();
+                return Ok(());
+            }
+            Event::PromptFinished => {
+                execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+                self.terminal.clear()?;
+            }
+            Event::SubmoduleUpdated { path, from, to } => {
+                let message = format!("`{}` updated from {} to {}", path, from, to);
+                self.push_message("", Color::Green, &message);
+            }
+            Event::PrCreated { url } => {
+                self.push_message("Created PR at ", Color::Green, url);
+                self.unprinted.push(format!("Created PR at {}", url));
+            }
+            // The warning about the change is shown instead, and the error is
+            // printed after the dashboard is closed.
+            Event::LockChanged { .. } | Event::Failed { .. } => return Ok(()),
+        }
+        self.draw()?;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+        self.print_unprinted();
+    }
+}